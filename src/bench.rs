@@ -0,0 +1,137 @@
+//! A small, reproducible search micro-benchmark built on the public API
+use std::time::{Duration, Instant};
+
+use crate::fragment::{Fragment, FragmentSeries};
+use crate::parent::Peptide;
+use crate::sort::{MassType, ParentID, SortType, Tolerance};
+use crate::SearchIndex;
+
+/// Timings and counts from one run of [`run_search_benchmark`]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BenchStats {
+    pub num_entries: usize,
+    pub num_parents: usize,
+    pub num_queries: usize,
+    pub num_hits: usize,
+    pub build_time: Duration,
+    pub sort_time: Duration,
+    pub search_time: Duration,
+}
+
+impl BenchStats {
+    /// The average time spent on each query
+    pub fn mean_query_time(&self) -> Duration {
+        if self.num_queries == 0 {
+            Duration::ZERO
+        } else {
+            self.search_time / self.num_queries as u32
+        }
+    }
+}
+
+/// A xorshift generator, so runs are reproducible from `seed` without extra dependencies
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn mass_in(&mut self, low: MassType, high: MassType) -> MassType {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        low + (high - low) * unit as MassType
+    }
+
+    fn index_below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Build an index of `num_fragments` synthetic fragments spread over one parent per ten
+/// fragments, then search it for `num_queries` masses drawn from those fragments at 10 ppm.
+///
+/// The same `seed` always produces the same index and queries.
+pub fn run_search_benchmark(num_fragments: usize, num_queries: usize, seed: u64) -> BenchStats {
+    let mut rng = XorShift::new(seed);
+    let num_parents = (num_fragments / 10).max(1);
+
+    let start = Instant::now();
+    let mut parent_masses: Vec<MassType> =
+        (0..num_parents).map(|_| rng.mass_in(500.0, 3000.0)).collect();
+    parent_masses.sort_by(|a, b| a.total_cmp(b));
+
+    let mut index: SearchIndex<Fragment, Peptide> =
+        SearchIndex::with_capacity(100, 2000.0, num_fragments, num_parents);
+    for (i, mass) in parent_masses.iter().enumerate() {
+        index.add_parent(Peptide::builder().mass(*mass).id(i as ParentID).build());
+    }
+    let mut fragment_masses = Vec::with_capacity(num_fragments);
+    for i in 0..num_fragments {
+        let mass = rng.mass_in(100.0, 2000.0);
+        let parent_id = rng.index_below(num_parents) as ParentID;
+        index.add(Fragment::new(mass, parent_id, FragmentSeries::b, (i % 50) as u16));
+        fragment_masses.push(mass);
+    }
+    let build_time = start.elapsed();
+
+    let start = Instant::now();
+    index.sort(SortType::ByParentId);
+    let sort_time = start.elapsed();
+
+    let queries: Vec<MassType> = if fragment_masses.is_empty() {
+        Vec::new()
+    } else {
+        (0..num_queries)
+            .map(|_| fragment_masses[rng.index_below(fragment_masses.len())])
+            .collect()
+    };
+
+    let tolerance = Tolerance::PPM(10.0);
+    let start = Instant::now();
+    let num_hits = queries
+        .iter()
+        .map(|query| index.search(*query, tolerance, None).count())
+        .sum();
+    let search_time = start.elapsed();
+
+    BenchStats {
+        num_entries: index.num_entries(),
+        num_parents: index.num_parents(),
+        num_queries: queries.len(),
+        num_hits,
+        build_time,
+        sort_time,
+        search_time,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tiny_benchmark() {
+        let stats = run_search_benchmark(200, 25, 7);
+        assert_eq!(stats.num_entries, 200);
+        assert_eq!(stats.num_parents, 20);
+        assert_eq!(stats.num_queries, 25);
+        // Every query is the mass of an indexed fragment
+        assert!(stats.num_hits >= 25);
+        assert!(stats.mean_query_time() <= stats.search_time);
+
+        let again = run_search_benchmark(200, 25, 7);
+        assert_eq!(again.num_hits, stats.num_hits);
+
+        let empty = run_search_benchmark(0, 5, 7);
+        assert_eq!(empty.num_entries, 0);
+        assert_eq!(empty.num_queries, 0);
+        assert_eq!(empty.mean_query_time(), Duration::ZERO);
+    }
+}
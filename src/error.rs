@@ -0,0 +1,102 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::io;
+
+#[cfg(feature = "binary_storage")]
+use arrow::error::ArrowError;
+#[cfg(feature = "binary_storage")]
+use parquet::errors::ParquetError;
+#[cfg(feature = "object_store")]
+use object_store::Error as ObjectStoreError;
+
+use crate::fragment::FragmentSeriesParsingError;
+
+/// The errors that can arise while reading, writing or building an index
+#[derive(Debug)]
+pub enum MassFragmentIndexError {
+    IO(io::Error),
+    #[cfg(feature = "binary_storage")]
+    Arrow(ArrowError),
+    #[cfg(feature = "binary_storage")]
+    Parquet(ParquetError),
+    #[cfg(feature = "object_store")]
+    ObjectStore(ObjectStoreError),
+    FragmentSeriesParsing(FragmentSeriesParsingError),
+}
+
+impl Display for MassFragmentIndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "An IO error occurred: {e}"),
+            #[cfg(feature = "binary_storage")]
+            Self::Arrow(e) => write!(f, "An Arrow error occurred: {e}"),
+            #[cfg(feature = "binary_storage")]
+            Self::Parquet(e) => write!(f, "A Parquet error occurred: {e}"),
+            #[cfg(feature = "object_store")]
+            Self::ObjectStore(e) => write!(f, "An object store error occurred: {e}"),
+            Self::FragmentSeriesParsing(e) => write!(f, "Failed to parse a fragment series: {e}"),
+        }
+    }
+}
+
+impl Error for MassFragmentIndexError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IO(e) => Some(e),
+            #[cfg(feature = "binary_storage")]
+            Self::Arrow(e) => Some(e),
+            #[cfg(feature = "binary_storage")]
+            Self::Parquet(e) => Some(e),
+            #[cfg(feature = "object_store")]
+            Self::ObjectStore(e) => Some(e),
+            Self::FragmentSeriesParsing(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for MassFragmentIndexError {
+    fn from(value: io::Error) -> Self {
+        Self::IO(value)
+    }
+}
+
+#[cfg(feature = "binary_storage")]
+impl From<ArrowError> for MassFragmentIndexError {
+    fn from(value: ArrowError) -> Self {
+        Self::Arrow(value)
+    }
+}
+
+#[cfg(feature = "binary_storage")]
+impl From<ParquetError> for MassFragmentIndexError {
+    fn from(value: ParquetError) -> Self {
+        Self::Parquet(value)
+    }
+}
+
+#[cfg(feature = "object_store")]
+impl From<ObjectStoreError> for MassFragmentIndexError {
+    fn from(value: ObjectStoreError) -> Self {
+        Self::ObjectStore(value)
+    }
+}
+
+impl From<FragmentSeriesParsingError> for MassFragmentIndexError {
+    fn from(value: FragmentSeriesParsingError) -> Self {
+        Self::FragmentSeriesParsing(value)
+    }
+}
+
+/// Lets code that works in terms of [`io::Result`] keep using `?` on this error
+impl From<MassFragmentIndexError> for io::Error {
+    fn from(value: MassFragmentIndexError) -> Self {
+        match value {
+            MassFragmentIndexError::IO(e) => e,
+            MassFragmentIndexError::FragmentSeriesParsing(e) => {
+                io::Error::new(io::ErrorKind::InvalidData, e)
+            }
+            #[allow(unreachable_patterns)]
+            e => io::Error::other(e),
+        }
+    }
+}
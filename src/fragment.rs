@@ -1,14 +1,15 @@
-use std::{str::FromStr, error::Error, fmt::Display};
+use std::{cmp::Ordering, str::FromStr, error::Error, fmt::Display};
 
 #[cfg(feature = "serde")]
 use serde::{Serialize, Deserialize};
 
 
-use crate::sort::{IndexSortable, ParentID, MassType};
+use crate::index::SearchIndex;
+use crate::interval::Interval;
+use crate::sort::{IndexSortable, ParentID, ParentIDMut, MassType, Tolerance};
 
 #[allow(non_snake_case, non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum FragmentSeries {
     b,
     y,
@@ -24,6 +25,63 @@ pub enum FragmentSeries {
 }
 
 impl FragmentSeries {
+    /// The number of fragment series variants
+    pub const COUNT: usize = 11;
+
+    /// Every fragment series variant, in declaration order
+    pub const fn all() -> &'static [FragmentSeries] {
+        const ALL: [FragmentSeries; FragmentSeries::COUNT] = [
+            FragmentSeries::b,
+            FragmentSeries::y,
+            FragmentSeries::c,
+            FragmentSeries::z,
+            FragmentSeries::a,
+            FragmentSeries::x,
+            FragmentSeries::Precursor,
+            FragmentSeries::PeptideY,
+            FragmentSeries::Oxonium,
+            FragmentSeries::Internal,
+            FragmentSeries::Unknown,
+        ];
+        &ALL
+    }
+
+    /// A stable code for this series used when storing fragments. Codes are fixed and
+    /// must not change when variants are added or reordered.
+    pub const fn to_code(&self) -> u8 {
+        match self {
+            FragmentSeries::Unknown => 0,
+            FragmentSeries::b => 1,
+            FragmentSeries::y => 2,
+            FragmentSeries::c => 3,
+            FragmentSeries::z => 4,
+            FragmentSeries::a => 5,
+            FragmentSeries::x => 6,
+            FragmentSeries::Precursor => 7,
+            FragmentSeries::PeptideY => 8,
+            FragmentSeries::Oxonium => 9,
+            FragmentSeries::Internal => 10,
+        }
+    }
+
+    pub const fn from_code(code: u8) -> Option<FragmentSeries> {
+        let series = match code {
+            0 => FragmentSeries::Unknown,
+            1 => FragmentSeries::b,
+            2 => FragmentSeries::y,
+            3 => FragmentSeries::c,
+            4 => FragmentSeries::z,
+            5 => FragmentSeries::a,
+            6 => FragmentSeries::x,
+            7 => FragmentSeries::Precursor,
+            8 => FragmentSeries::PeptideY,
+            9 => FragmentSeries::Oxonium,
+            10 => FragmentSeries::Internal,
+            _ => return None,
+        };
+        Some(series)
+    }
+
     pub const fn series_name(&self) -> &'static str {
         match self {
             FragmentSeries::b => "b",
@@ -46,6 +104,9 @@ pub enum FragmentSeriesParsingError {
     Empty,
     UnknownSeries(String),
     InvalidOrdinal(String),
+    InvalidNeutralLoss(String),
+    InvalidCharge(String),
+    InvalidInternalSpan(u16, u16),
 }
 
 impl Display for FragmentSeriesParsingError {
@@ -54,6 +115,9 @@ impl Display for FragmentSeriesParsingError {
             Self::Empty => "Fragment name cannot be an empty string".to_string(),
             Self::UnknownSeries(series_label) => format!("Unknown series label \"{}\"", series_label),
             Self::InvalidOrdinal(ordinal_label) => format!("Invalid ordinal value \"{}\", should be an integer", ordinal_label),
+            Self::InvalidNeutralLoss(loss_label) => format!("Unknown neutral loss \"{}\"", loss_label),
+            Self::InvalidCharge(charge_label) => format!("Invalid charge value \"{}\", should be an integer", charge_label),
+            Self::InvalidInternalSpan(start, end) => format!("Invalid internal fragment span {}-{}, start must not exceed end", start, end),
         };
         f.write_str(&text)
     }
@@ -62,7 +126,51 @@ impl Display for FragmentSeriesParsingError {
 impl Error for FragmentSeriesParsingError {}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct FragmentName(pub FragmentSeries, pub u16);
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NeutralLoss {
+    H2O,
+    NH3,
+    H3PO4,
+    HPO3,
+    CO2,
+}
+
+impl NeutralLoss {
+    pub const fn loss_name(&self) -> &'static str {
+        match self {
+            NeutralLoss::H2O => "H2O",
+            NeutralLoss::NH3 => "NH3",
+            NeutralLoss::H3PO4 => "H3PO4",
+            NeutralLoss::HPO3 => "HPO3",
+            NeutralLoss::CO2 => "CO2",
+        }
+    }
+}
+
+impl FromStr for NeutralLoss {
+    type Err = FragmentSeriesParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let loss = match s {
+            "H2O" => NeutralLoss::H2O,
+            "NH3" => NeutralLoss::NH3,
+            "H3PO4" => NeutralLoss::H3PO4,
+            "HPO3" => NeutralLoss::HPO3,
+            "CO2" => NeutralLoss::CO2,
+            _ => return Err(FragmentSeriesParsingError::InvalidNeutralLoss(s.to_string())),
+        };
+        Ok(loss)
+    }
+}
+
+impl Display for NeutralLoss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.loss_name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FragmentName(pub FragmentSeries, pub u16, pub Option<NeutralLoss>, pub Option<i16>);
 
 impl FromStr for FragmentSeries {
     type Err = FragmentSeriesParsingError;
@@ -91,35 +199,189 @@ impl FromStr for FragmentSeries {
     }
 }
 
-impl FromStr for FragmentName {
-    type Err = FragmentSeriesParsingError;
+impl Display for FragmentSeries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.series_name())
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+// Series and names are serialized as the same text labels `from_str` accepts
+#[cfg(feature = "serde")]
+mod serde_labels {
+    use super::*;
+    use serde::{de, Deserializer, Serializer};
+
+    impl Serialize for FragmentSeries {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.series_name())
+        }
+    }
+
+    impl<'de> Deserialize<'de> for FragmentSeries {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let label = String::deserialize(deserializer)?;
+            label.parse().map_err(de::Error::custom)
+        }
+    }
+
+    impl Serialize for FragmentName {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for FragmentName {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let label = String::deserialize(deserializer)?;
+            label.parse().map_err(de::Error::custom)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FragmentNameParseOptions {
+    pub case_insensitive: bool,
+}
+
+impl FragmentNameParseOptions {
+    pub fn new(case_insensitive: bool) -> Self {
+        Self { case_insensitive }
+    }
+}
+
+impl FragmentName {
+    pub fn parse_with(s: &str, options: FragmentNameParseOptions) -> Result<Self, FragmentSeriesParsingError> {
+        if s.is_empty() {
+            return Err(FragmentSeriesParsingError::Empty)
+        }
+        let (s, charge) = match s.rsplit_once('^') {
+            Some((head, charge_label)) => match charge_label.parse::<i16>() {
+                Ok(charge) => (head, Some(charge)),
+                Err(_) => {
+                    return Err(FragmentSeriesParsingError::InvalidCharge(charge_label.to_string()))
+                }
+            },
+            None => (s, None),
+        };
+        let (s, loss) = match s.split_once('-') {
+            Some((head, loss_label)) => (head, Some(loss_label.parse::<NeutralLoss>()?)),
+            None => (s, None),
+        };
         if s.len() == 0 {
             return Err(FragmentSeriesParsingError::Empty)
         }
-        let series = match &s[0..1] {
-            "b" => FragmentSeries::b,
-            "y" => FragmentSeries::y,
-            "c" => FragmentSeries::c,
-            "z" => FragmentSeries::z,
-            "a" => FragmentSeries::a,
-            "x" => FragmentSeries::x,
-            _ => {
-                return Err(FragmentSeriesParsingError::UnknownSeries(s[0..1].to_string()))
-            }
+        // Word-form series are written as `Series:ordinal`, e.g. `Precursor:0`
+        let (series, ordinal_label) = if let Some((series_label, ordinal_label)) = s.split_once(':') {
+            (series_label.parse::<FragmentSeries>()?, ordinal_label)
+        } else {
+            let series_label = if options.case_insensitive {
+                s[0..1].to_ascii_lowercase()
+            } else {
+                s[0..1].to_string()
+            };
+            let series = match series_label.as_str() {
+                "b" => FragmentSeries::b,
+                "y" => FragmentSeries::y,
+                "c" => FragmentSeries::c,
+                "z" => FragmentSeries::z,
+                "a" => FragmentSeries::a,
+                "x" => FragmentSeries::x,
+                _ => {
+                    return Err(FragmentSeriesParsingError::UnknownSeries(s[0..1].to_string()))
+                }
+            };
+            (series, &s[1..])
         };
-        let ordinal = match s[1..s.len()].parse() {
+        let ordinal = match ordinal_label.parse() {
             Ok(size) => size,
             Err(_) => {
-                return Err(FragmentSeriesParsingError::InvalidOrdinal(s[1..].to_string()))
+                return Err(FragmentSeriesParsingError::InvalidOrdinal(ordinal_label.to_string()))
             }
         };
-        Ok(FragmentName(series, ordinal))
+        Ok(FragmentName(series, ordinal, loss, charge))
     }
 }
 
+impl FromStr for FragmentName {
+    type Err = FragmentSeriesParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, FragmentNameParseOptions::default())
+    }
+}
+
+impl Display for FragmentName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            FragmentSeries::b
+            | FragmentSeries::y
+            | FragmentSeries::c
+            | FragmentSeries::z
+            | FragmentSeries::a
+            | FragmentSeries::x => write!(f, "{}{}", self.0, self.1)?,
+            _ => write!(f, "{}:{}", self.0, self.1)?,
+        }
+        if let Some(loss) = self.2 {
+            write!(f, "-{}", loss)?;
+        }
+        if let Some(charge) = self.3 {
+            write!(f, "^{}", charge)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InternalFragmentName {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl InternalFragmentName {
+    pub fn new(start: u16, end: u16) -> Result<Self, FragmentSeriesParsingError> {
+        if start > end {
+            return Err(FragmentSeriesParsingError::InvalidInternalSpan(start, end))
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl FromStr for InternalFragmentName {
+    type Err = FragmentSeriesParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(FragmentSeriesParsingError::Empty)
+        }
+        let span = match s.split_once(':') {
+            Some((series_label, span)) => {
+                if series_label.parse::<FragmentSeries>()? != FragmentSeries::Internal {
+                    return Err(FragmentSeriesParsingError::UnknownSeries(series_label.to_string()))
+                }
+                span
+            }
+            None => return Err(FragmentSeriesParsingError::UnknownSeries(s.to_string())),
+        };
+        let (start_label, end_label) = match span.split_once('-') {
+            Some(bounds) => bounds,
+            None => return Err(FragmentSeriesParsingError::InvalidOrdinal(span.to_string())),
+        };
+        let start = start_label
+            .parse()
+            .map_err(|_| FragmentSeriesParsingError::InvalidOrdinal(start_label.to_string()))?;
+        let end = end_label
+            .parse()
+            .map_err(|_| FragmentSeriesParsingError::InvalidOrdinal(end_label.to_string()))?;
+        Self::new(start, end)
+    }
+}
 
+impl Display for InternalFragmentName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}-{}", FragmentSeries::Internal, self.start, self.end)
+    }
+}
 
 
 impl Default for FragmentSeries {
@@ -135,6 +397,7 @@ pub struct Fragment {
     pub parent_id: ParentID,
     pub series: FragmentSeries,
     pub ordinal: u16,
+    pub charge: i8,
 }
 
 
@@ -146,6 +409,35 @@ impl IndexSortable for Fragment {
     fn parent_id(&self) -> ParentID {
         self.parent_id
     }
+
+    fn charge(&self) -> i8 {
+        self.charge
+    }
+
+    fn sort_key(&self) -> u64 {
+        ((self.series.to_code() as u64) << 32)
+            | ((self.ordinal as u64) << 8)
+            | self.charge as u8 as u64
+    }
+}
+
+/// Orders fragments by mass, breaking ties by parent id and then series, ordinal and charge
+/// as the index does when sorting by mass, so only fragments that compare equal are equal.
+impl PartialOrd for Fragment {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(
+            self.mass
+                .partial_cmp(&other.mass)?
+                .then_with(|| self.parent_id.cmp(&other.parent_id))
+                .then_with(|| self.sort_key().cmp(&other.sort_key())),
+        )
+    }
+}
+
+impl ParentIDMut for Fragment {
+    fn set_parent_id(&mut self, parent_id: ParentID) {
+        self.parent_id = parent_id
+    }
 }
 
 impl Fragment {
@@ -155,6 +447,339 @@ impl Fragment {
             parent_id,
             series,
             ordinal,
+            charge: 1,
+        }
+    }
+
+    pub fn with_charge(mut self, charge: i8) -> Self {
+        self.charge = charge;
+        self
+    }
+
+    /// Compare two fragments by mass alone, for use with [`slice::sort_by`]. Unlike
+    /// [`PartialOrd`], this is a total order, placing NaN masses last.
+    pub fn cmp_by_mass(&self, other: &Self) -> Ordering {
+        self.mass.total_cmp(&other.mass)
+    }
+}
+
+/// A [`Fragment`] carrying opaque annotation bytes, e.g. a serialized structure id, which
+/// are stored with it and returned by searches
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AnnotatedFragment {
+    pub fragment: Fragment,
+    pub payload: Option<Vec<u8>>,
+}
+
+impl AnnotatedFragment {
+    pub fn new(fragment: Fragment, payload: Option<Vec<u8>>) -> Self {
+        Self { fragment, payload }
+    }
+}
+
+impl From<Fragment> for AnnotatedFragment {
+    fn from(fragment: Fragment) -> Self {
+        Self::new(fragment, None)
+    }
+}
+
+impl IndexSortable for AnnotatedFragment {
+    fn mass(&self) -> MassType {
+        self.fragment.mass
+    }
+
+    fn parent_id(&self) -> ParentID {
+        self.fragment.parent_id
+    }
+
+    fn charge(&self) -> i8 {
+        self.fragment.charge
+    }
+
+    fn sort_key(&self) -> u64 {
+        self.fragment.sort_key()
+    }
+}
+
+impl ParentIDMut for AnnotatedFragment {
+    fn set_parent_id(&mut self, parent_id: ParentID) {
+        self.fragment.parent_id = parent_id
+    }
+}
+
+impl<P: IndexSortable + Default> SearchIndex<Fragment, P> {
+    /// Search for fragments matching `query` whose series is one of `allowed`
+    pub fn search_series<'a>(
+        &'a self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+        allowed: &'a [FragmentSeries],
+    ) -> impl Iterator<Item = &'a Fragment> + 'a {
+        self.search(query, error_tolerance, parent_interval)
+            .filter(move |frag| allowed.contains(&frag.series))
+    }
+
+    /// Search for fragments matching `query` whose ordinal lies in `min_ordinal..=max_ordinal`
+    pub fn search_ordinal_range(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+        min_ordinal: u16,
+        max_ordinal: u16,
+    ) -> impl Iterator<Item = &Fragment> + '_ {
+        self.search(query, error_tolerance, parent_interval)
+            .filter(move |frag| (min_ordinal..=max_ordinal).contains(&frag.ordinal))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fragment_ordering() {
+        let mut fragments = Vec::from([
+            Fragment::new(300.0, 0, FragmentSeries::y, 3),
+            Fragment::new(100.0, 1, FragmentSeries::b, 1),
+            Fragment::new(200.0, 1, FragmentSeries::y, 2),
+            Fragment::new(200.0, 0, FragmentSeries::b, 2),
+        ]);
+        fragments.sort_by(Fragment::cmp_by_mass);
+        assert!(fragments.windows(2).all(|w| w[0].mass <= w[1].mass));
+
+        fragments.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(fragments.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(fragments[1].parent_id, 0);
+        assert_eq!(fragments[2].parent_id, 1);
+
+        let nan = Fragment::new(MassType::NAN, 0, FragmentSeries::b, 1);
+        assert_eq!(nan.partial_cmp(&fragments[0]), None);
+    }
+
+    #[test]
+    fn test_name_round_trip() {
+        for s in FragmentSeries::all().iter().copied() {
+            for loss in [None, Some(NeutralLoss::H2O), Some(NeutralLoss::H3PO4)] {
+                for charge in [None, Some(2)] {
+                    let name = FragmentName(s, 12, loss, charge);
+                    let text = name.to_string();
+                    assert_eq!(text.parse::<FragmentName>(), Ok(name), "{text} did not round-trip");
+                }
+            }
+        }
+
+        assert_eq!(FragmentName(FragmentSeries::b, 5, None, None).to_string(), "b5");
+        assert_eq!(FragmentName(FragmentSeries::Precursor, 0, None, None).to_string(), "Precursor:0");
+    }
+
+    #[test]
+    fn test_all_series() {
+        let all = FragmentSeries::all();
+        assert_eq!(all.len(), FragmentSeries::COUNT);
+        let distinct: std::collections::HashSet<_> = all.iter().collect();
+        assert_eq!(distinct.len(), FragmentSeries::COUNT);
+        for s in all {
+            // Fails to compile when a variant is added, as a reminder to extend `all`
+            match s {
+                FragmentSeries::b
+                | FragmentSeries::y
+                | FragmentSeries::c
+                | FragmentSeries::z
+                | FragmentSeries::a
+                | FragmentSeries::x
+                | FragmentSeries::Precursor
+                | FragmentSeries::PeptideY
+                | FragmentSeries::Oxonium
+                | FragmentSeries::Internal
+                | FragmentSeries::Unknown => {}
+            }
+            assert_eq!(s.to_string().parse::<FragmentSeries>(), Ok(*s));
+        }
+    }
+
+    #[test]
+    fn test_series_codes() {
+        // These codes are stored on disk and must never change
+        let codes = [
+            (FragmentSeries::Unknown, 0),
+            (FragmentSeries::b, 1),
+            (FragmentSeries::y, 2),
+            (FragmentSeries::c, 3),
+            (FragmentSeries::z, 4),
+            (FragmentSeries::a, 5),
+            (FragmentSeries::x, 6),
+            (FragmentSeries::Precursor, 7),
+            (FragmentSeries::PeptideY, 8),
+            (FragmentSeries::Oxonium, 9),
+            (FragmentSeries::Internal, 10),
+        ];
+        assert_eq!(codes.len(), FragmentSeries::COUNT);
+        for (series, code) in codes {
+            assert_eq!(series.to_code(), code);
+            assert_eq!(FragmentSeries::from_code(code), Some(series));
+        }
+        assert_eq!(FragmentSeries::from_code(FragmentSeries::COUNT as u8), None);
+        assert_eq!(FragmentSeries::from_code(u8::MAX), None);
+    }
+
+    #[test]
+    fn test_neutral_loss() {
+        let name: FragmentName = "y7-NH3".parse().unwrap();
+        assert_eq!(name, FragmentName(FragmentSeries::y, 7, Some(NeutralLoss::NH3), None));
+        assert_eq!(name.to_string(), "y7-NH3");
+
+        assert_eq!(
+            "b5-HexNAc".parse::<FragmentName>(),
+            Err(FragmentSeriesParsingError::InvalidNeutralLoss("HexNAc".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let options = FragmentNameParseOptions::new(true);
+        assert_eq!(
+            FragmentName::parse_with("B5", options),
+            Ok(FragmentName(FragmentSeries::b, 5, None, None))
+        );
+        assert_eq!(
+            FragmentName::parse_with("Y12-H2O", options),
+            Ok(FragmentName(FragmentSeries::y, 12, Some(NeutralLoss::H2O), None))
+        );
+        assert_eq!(
+            FragmentName::parse_with("z3", options),
+            Ok(FragmentName(FragmentSeries::z, 3, None, None))
+        );
+
+        assert_eq!(
+            "B5".parse::<FragmentName>(),
+            Err(FragmentSeriesParsingError::UnknownSeries("B".to_string()))
+        );
+        assert_eq!(
+            FragmentName::parse_with("X2", FragmentNameParseOptions::default()),
+            Err(FragmentSeriesParsingError::UnknownSeries("X".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_charge() {
+        let name: FragmentName = "y7^2".parse().unwrap();
+        assert_eq!(name, FragmentName(FragmentSeries::y, 7, None, Some(2)));
+        assert_eq!(name.to_string(), "y7^2");
+
+        let name: FragmentName = "b5-H2O^3".parse().unwrap();
+        assert_eq!(name, FragmentName(FragmentSeries::b, 5, Some(NeutralLoss::H2O), Some(3)));
+
+        assert_eq!(
+            "y7^x".parse::<FragmentName>(),
+            Err(FragmentSeriesParsingError::InvalidCharge("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_internal() {
+        let name: InternalFragmentName = "Internal:4-9".parse().unwrap();
+        assert_eq!(name, InternalFragmentName { start: 4, end: 9 });
+        assert_eq!(name.to_string(), "Internal:4-9");
+        assert_eq!(name.to_string().parse::<InternalFragmentName>(), Ok(name));
+
+        assert_eq!(
+            "Internal:9-4".parse::<InternalFragmentName>(),
+            Err(FragmentSeriesParsingError::InvalidInternalSpan(9, 4))
+        );
+        assert_eq!(
+            "Internal:4".parse::<InternalFragmentName>(),
+            Err(FragmentSeriesParsingError::InvalidOrdinal("4".to_string()))
+        );
+        assert!("Oxonium:4-9".parse::<InternalFragmentName>().is_err());
+    }
+
+    #[test]
+    fn test_search_series() {
+        use crate::parent::Peptide;
+        use crate::sort::SortType;
+
+        let mut index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 2000.0);
+        let series = [
+            FragmentSeries::b,
+            FragmentSeries::y,
+            FragmentSeries::Oxonium,
+            FragmentSeries::Internal,
+        ];
+        for i in 0..8u32 {
+            index.add_parent(Peptide::new(800.0 + i as MassType, i, 0, 0, String::new()));
+            for (j, s) in series.iter().enumerate() {
+                index.add(Fragment::new(204.087, i, *s, j as u16));
+            }
+        }
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        let all: Vec<_> = index.search(204.087, tol, None).collect();
+        let allowed = [FragmentSeries::b, FragmentSeries::y];
+        let filtered: Vec<_> = index.search_series(204.087, tol, None, &allowed).collect();
+
+        assert_eq!(all.len(), 32);
+        assert_eq!(filtered.len(), 16);
+        assert!(filtered.iter().all(|f| allowed.contains(&f.series)));
+        assert!(filtered.iter().all(|f| all.contains(f)));
+    }
+
+    #[test]
+    fn test_search_ordinal_range() {
+        use crate::parent::Peptide;
+        use crate::sort::SortType;
+
+        let mut index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 2000.0);
+        for i in 0..4u32 {
+            index.add_parent(Peptide::new(800.0 + i as MassType, i, 0, 0, String::new()));
+            for ordinal in 1..15u16 {
+                index.add(Fragment::new(500.25, i, FragmentSeries::b, ordinal));
+            }
+        }
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        let hits: Vec<_> = index.search_ordinal_range(500.25, tol, None, 3, 10).collect();
+        assert_eq!(hits.len(), 4 * 8);
+        assert!(hits.iter().all(|f| f.ordinal >= 3 && f.ordinal <= 10));
+
+        assert_eq!(index.search_ordinal_range(500.25, tol, None, 20, 30).count(), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let series = [
+            FragmentSeries::b,
+            FragmentSeries::y,
+            FragmentSeries::c,
+            FragmentSeries::z,
+            FragmentSeries::a,
+            FragmentSeries::x,
+            FragmentSeries::Precursor,
+            FragmentSeries::PeptideY,
+            FragmentSeries::Oxonium,
+            FragmentSeries::Internal,
+            FragmentSeries::Unknown,
+        ];
+        for s in series {
+            let text = serde_json::to_string(&s).unwrap();
+            assert_eq!(text, format!("\"{}\"", s.series_name()));
+            assert_eq!(serde_json::from_str::<FragmentSeries>(&text).unwrap(), s);
+
+            let frag = Fragment::new(512.25, 3, s, 7);
+            let text = serde_json::to_string(&frag).unwrap();
+            assert_eq!(serde_json::from_str::<Fragment>(&text).unwrap(), frag);
+
+            let name = FragmentName(s, 7, Some(NeutralLoss::H2O), Some(2));
+            let text = serde_json::to_string(&name).unwrap();
+            assert_eq!(text, format!("\"{}\"", name));
+            assert_eq!(serde_json::from_str::<FragmentName>(&text).unwrap(), name);
         }
+        assert!(serde_json::from_str::<FragmentSeries>("\"q\"").is_err());
     }
 }
@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
 use std::io;
 use std::iter::FusedIterator;
 
@@ -9,13 +11,188 @@ use serde::{Deserialize, Serialize};
 use rayon::prelude::*;
 
 #[cfg(feature = "binary_storage")]
-use crate::storage::{ArrowStorage, IndexBinaryStorage, IndexMetadata, SplitIndexBinaryStorage};
+use crate::storage::{
+    ArrowStorage, IndexArchiveStorage, IndexBinaryStorage, IndexCompression, IndexIpcStorage,
+    IndexMetadata, SplitIndexBinaryStorage,
+};
 
 use crate::interval::Interval;
 use crate::sort::{
-    IndexBin, IndexSortable, MassType, ParentSortedIndexBinSearchIter, SortType, Tolerance,
+    neutral_mass, neutral_mass_with_adduct, IndexBin, IndexSortable, MassType, ParentID, ParentIDMut,
+    ParentSortedIndexBinSearchIter, SortIDMut, SortType, Tolerance,
 };
 
+/// The number of bins needed to span `[0, max_item_mass]`, plus one overflow bin
+pub(crate) fn bin_count_for(bins_per_dalton: u32, max_item_mass: MassType) -> usize {
+    if bins_per_dalton == 0 {
+        return 1;
+    }
+    let mut mass_step: MassType = 0.0;
+    let mut num_bins = 0;
+    while mass_step < max_item_mass {
+        num_bins += 1;
+        mass_step += 1 as MassType / bins_per_dalton as MassType;
+    }
+    num_bins + 1
+}
+
+pub(crate) fn bin_index_for(mass: MassType, bins_per_dalton: u32, num_bins: usize) -> usize {
+    let i = (mass * bins_per_dalton as MassType).round() as usize;
+    if i >= num_bins {
+        num_bins - 1
+    } else {
+        i
+    }
+}
+
+/// A piecewise bin resolution, for entries that are much denser in some mass ranges than in
+/// others. Each region has its own number of bins per Dalton and runs from its start mass
+/// up to the start of the next, the last running to the index's `max_item_mass`.
+///
+/// Unlike uniform binning, where bin `i` is centered on `i / bins_per_dalton`, a region's
+/// bins start at the region's start mass.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BinSchedule {
+    regions: Vec<(MassType, u32)>,
+    /// The first bin of each region, which does not depend on `max_item_mass`
+    first_bins: Vec<usize>,
+}
+
+impl BinSchedule {
+    /// A schedule of `bins_per_dalton` bins per Dalton from zero, to be refined with
+    /// [`BinSchedule::then`]
+    pub fn new(bins_per_dalton: u32) -> Self {
+        Self::from_regions(vec![(0.0, bins_per_dalton)]).expect("A region needs at least one bin per Dalton")
+    }
+
+    /// Switch to `bins_per_dalton` bins per Dalton from `start_mass` onward.
+    ///
+    /// # Panics
+    /// If `start_mass` is not above the start of the previous region or `bins_per_dalton`
+    /// is zero.
+    pub fn then(mut self, start_mass: MassType, bins_per_dalton: u32) -> Self {
+        self.regions.push((start_mass, bins_per_dalton));
+        Self::from_regions(self.regions)
+            .expect("Regions must start at increasing masses with at least one bin per Dalton")
+    }
+
+    /// Build a schedule from `(start_mass, bins_per_dalton)` pairs, or `None` if they are
+    /// not valid, see [`BinSchedule::is_valid`]
+    pub(crate) fn from_regions(regions: Vec<(MassType, u32)>) -> Option<Self> {
+        let this = Self::from_regions_unchecked(regions);
+        this.is_valid().then_some(this)
+    }
+
+    /// Build a schedule that may be invalid, e.g. while reading, to be checked later
+    pub(crate) fn from_regions_unchecked(regions: Vec<(MassType, u32)>) -> Self {
+        let mut offset = 0;
+        let first_bins = regions
+            .iter()
+            .enumerate()
+            .map(|(i, (start, bins_per_dalton))| {
+                let first_bin = offset;
+                if let Some((next_start, _)) = regions.get(i + 1) {
+                    offset += Self::region_bins(*start, *next_start, *bins_per_dalton);
+                }
+                first_bin
+            })
+            .collect();
+        Self {
+            regions,
+            first_bins,
+        }
+    }
+
+    fn region_bins(start: MassType, end: MassType, bins_per_dalton: u32) -> usize {
+        ((end - start).max(0.0) * bins_per_dalton as MassType).ceil() as usize
+    }
+
+    /// Whether the first region starts at zero, the regions start at increasing masses
+    /// and each has at least one bin per Dalton
+    pub(crate) fn is_valid(&self) -> bool {
+        self.regions.first().is_some_and(|(start, _)| *start == 0.0)
+            && self.regions.iter().all(|(_, bins_per_dalton)| *bins_per_dalton > 0)
+            && self.regions.windows(2).all(|w| w[0].0 < w[1].0)
+    }
+
+    /// The `(start_mass, bins_per_dalton)` of each region, in mass order
+    pub fn regions(&self) -> &[(MassType, u32)] {
+        &self.regions
+    }
+
+    /// The finest resolution of any region
+    pub fn max_bins_per_dalton(&self) -> u32 {
+        self.regions.iter().map(|(_, b)| *b).max().unwrap_or_default()
+    }
+
+    /// The number of bins of region `i`
+    fn bins_in_region(&self, i: usize, max_item_mass: MassType) -> usize {
+        match self.first_bins.get(i + 1) {
+            Some(next_first) => next_first - self.first_bins[i],
+            None => {
+                let (start, bins_per_dalton) = self.regions[i];
+                Self::region_bins(start, max_item_mass, bins_per_dalton)
+            }
+        }
+    }
+
+    /// The number of bins needed to span `[0, max_item_mass]`, plus one overflow bin
+    pub(crate) fn bin_count(&self, max_item_mass: MassType) -> usize {
+        match self.first_bins.last() {
+            Some(last) => last + self.bins_in_region(self.regions.len() - 1, max_item_mass) + 1,
+            None => 1,
+        }
+    }
+
+    pub(crate) fn bin_index(&self, mass: MassType, max_item_mass: MassType, num_bins: usize) -> usize {
+        let i = self.regions.partition_point(|(start, _)| *start <= mass);
+        if i == 0 {
+            return if self.regions.is_empty() { num_bins - 1 } else { 0 };
+        }
+        let i = i - 1;
+        let (start, bins_per_dalton) = self.regions[i];
+        let k = ((mass - start).max(0.0) * bins_per_dalton as MassType) as usize;
+        // Rounding must not spill a mass into the next region's bins
+        let k = if i + 1 < self.regions.len() {
+            k.min(self.bins_in_region(i, max_item_mass).saturating_sub(1))
+        } else {
+            k
+        };
+        (self.first_bins[i] + k).min(num_bins - 1)
+    }
+
+    /// The half-open mass range of `bin` before correcting for rounding, see
+    /// [`SearchIndex::bin_mass_range`]
+    pub(crate) fn bin_mass_range(&self, bin: usize, max_item_mass: MassType) -> (MassType, MassType) {
+        let i = self.first_bins.partition_point(|first_bin| *first_bin <= bin);
+        if i > 0 {
+            let i = i - 1;
+            let (start, bins_per_dalton) = self.regions[i];
+            let width = 1.0 / bins_per_dalton as MassType;
+            let k = bin - self.first_bins[i];
+            if k < self.bins_in_region(i, max_item_mass) {
+                let k = k as MassType;
+                let high = start + (k + 1.0) * width;
+                let next_start = self.regions.get(i + 1).map(|(s, _)| *s);
+                return (start + k * width, next_start.map_or(high, |next| high.min(next)));
+            }
+        }
+        // The overflow bin starts where the last region with any bins ends
+        let overflow_start = (0..self.regions.len())
+            .rev()
+            .find_map(|i| {
+                let region_bins = self.bins_in_region(i, max_item_mass);
+                (region_bins > 0).then(|| {
+                    let (start, bins_per_dalton) = self.regions[i];
+                    start + region_bins as MassType / bins_per_dalton as MassType
+                })
+            })
+            .unwrap_or_default();
+        (overflow_start, MassType::INFINITY)
+    }
+}
+
 #[derive(Debug, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SearchIndex<T: IndexSortable + Default, P: IndexSortable + Default> {
@@ -24,6 +201,9 @@ pub struct SearchIndex<T: IndexSortable + Default, P: IndexSortable + Default> {
     pub(crate) bins_per_dalton: u32,
     pub(crate) max_item_mass: MassType,
     pub(crate) sort_type: SortType,
+    pub(crate) auto_grow: bool,
+    pub(crate) bin_schedule: Option<BinSchedule>,
+    pub(crate) flat: bool,
 }
 
 impl<T: IndexSortable + Default, P: IndexSortable + Default> SearchIndex<T, P> {
@@ -31,22 +211,81 @@ impl<T: IndexSortable + Default, P: IndexSortable + Default> SearchIndex<T, P> {
         let mut inst = Self {
             bins_per_dalton,
             max_item_mass: max_fragment_size,
+            flat: bins_per_dalton == 0,
             ..Self::default()
         };
         inst.initialize_bins();
         inst
     }
 
-    fn initialize_bins(&mut self) {
-        let mut mass_step: MassType = 0.0;
-        self.bins = Vec::new();
-        while mass_step < self.max_item_mass {
-            let bin: IndexBin<T> = IndexBin::default();
-            self.bins.push(bin);
-            mass_step += 1 as MassType / self.bins_per_dalton as MassType;
+    /// Create an empty index that keeps all entries in a single mass-sorted bin, for
+    /// indices too small to benefit from binning. It is searched like any other index.
+    ///
+    /// This is the same as [`SearchIndex::empty`] with a `bins_per_dalton` of zero, which
+    /// is also how a flat index is recorded when written. Because the single bin must stay
+    /// ordered by mass, [`SearchIndex::sort`] treats [`SortType::ByParentId`] as
+    /// [`SortType::ByMass`].
+    pub fn flat(max_fragment_size: MassType) -> Self {
+        Self::empty(0, max_fragment_size)
+    }
+
+    /// Whether all entries are held in a single bin, see [`SearchIndex::flat`]
+    pub fn is_flat(&self) -> bool {
+        self.flat
+    }
+
+    /// Create an empty index whose bin resolution varies with mass according to `schedule`.
+    /// Its [`SearchIndex::bins_per_dalton`] reports the finest resolution of the schedule.
+    pub fn with_bin_schedule(schedule: BinSchedule, max_fragment_size: MassType) -> Self {
+        let mut inst = Self {
+            bins_per_dalton: schedule.max_bins_per_dalton(),
+            max_item_mass: max_fragment_size,
+            bin_schedule: Some(schedule),
+            ..Self::default()
+        };
+        inst.initialize_bins();
+        inst
+    }
+
+    /// The variable bin resolution of the index, if it was built with
+    /// [`SearchIndex::with_bin_schedule`]
+    pub fn bin_schedule(&self) -> Option<&BinSchedule> {
+        self.bin_schedule.as_ref()
+    }
+
+    fn bin_count(&self, max_item_mass: MassType) -> usize {
+        match &self.bin_schedule {
+            Some(schedule) => schedule.bin_count(max_item_mass),
+            None => bin_count_for(self.bins_per_dalton, max_item_mass),
         }
-        let bin: IndexBin<T> = IndexBin::default();
-        self.bins.push(bin);
+    }
+
+    /// Create an empty index that extends its bins when an entry heavier than
+    /// `max_item_mass` is added, instead of piling it into the overflow bin.
+    pub fn with_auto_grow(bins_per_dalton: u32, max_fragment_size: MassType) -> Self {
+        let mut inst = Self::empty(bins_per_dalton, max_fragment_size);
+        inst.auto_grow = true;
+        inst
+    }
+
+    /// Create an empty index with space reserved for `expected_parents` parents and
+    /// `expected_entries` entries, the latter spread evenly across the bins.
+    pub fn with_capacity(
+        bins_per_dalton: u32,
+        max_fragment_size: MassType,
+        expected_entries: usize,
+        expected_parents: usize,
+    ) -> Self {
+        let mut inst = Self::empty(bins_per_dalton, max_fragment_size);
+        let per_bin = expected_entries.div_ceil(inst.bins.len());
+        inst.bins.iter_mut().for_each(|bin| bin.reserve(per_bin));
+        inst.parents.reserve(expected_parents);
+        inst
+    }
+
+    fn initialize_bins(&mut self) {
+        let num_bins = self.bin_count(self.max_item_mass);
+        self.bins = (0..num_bins).map(|_| IndexBin::default()).collect();
     }
 
     pub fn num_bins(&self) -> usize {
@@ -57,10 +296,69 @@ impl<T: IndexSortable + Default, P: IndexSortable + Default> SearchIndex<T, P> {
         self.bins.iter().map(|b| b.len()).sum()
     }
 
+    pub fn num_parents(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty() && self.bins.iter().all(|b| b.is_empty())
+    }
+
+    /// The number of entries in each bin, in bin order
+    pub fn bin_histogram(&self) -> Vec<usize> {
+        self.bins.iter().map(|b| b.len()).collect()
+    }
+
+    /// Summarize how entries are distributed across the bins, e.g. to tune `bins_per_dalton`
+    pub fn bin_stats(&self) -> BinStats {
+        let histogram = self.bin_histogram();
+        let total: usize = histogram.iter().sum();
+        BinStats {
+            min: histogram.iter().copied().min().unwrap_or_default(),
+            max: histogram.iter().copied().max().unwrap_or_default(),
+            mean: if histogram.is_empty() {
+                0.0
+            } else {
+                total as f64 / histogram.len() as f64
+            },
+            empty_bins: histogram.iter().filter(|n| **n == 0).count(),
+        }
+    }
+
     pub fn iter_bins(&self) -> std::slice::Iter<'_, IndexBin<T>> {
         self.bins.iter()
     }
 
+    /// Iterate over all entries bin by bin in ascending mass order.
+    ///
+    /// Within a bin, entries are yielded in that bin's current order, so the sequence is
+    /// only strictly ordered by mass after `sort(SortType::ByMass)`. Otherwise the order is
+    /// only guaranteed to the resolution of a single bin.
+    pub fn iter_entries(&self) -> impl Iterator<Item = &T> + '_ {
+        self.bins.iter().flat_map(|bin| bin.iter())
+    }
+
+    /// Iterate over all entries whose mass lies within `[low, high]`, following the
+    /// same ordering rules as [`SearchIndex::iter_entries`].
+    pub fn iter_entries_in_range(
+        &self,
+        low: MassType,
+        high: MassType,
+    ) -> impl Iterator<Item = &T> + '_ {
+        let (start, end) = if low > high {
+            (0, 0)
+        } else {
+            (self.bin_for_mass(low), self.bin_for_mass(high) + 1)
+        };
+        self.bins[start..end]
+            .iter()
+            .flat_map(|bin| bin.iter())
+            .filter(move |entry| {
+                let mass = entry.mass();
+                mass >= low && mass <= high
+            })
+    }
+
     pub fn new(
         bins: Vec<IndexBin<T>>,
         parents: IndexBin<P>,
@@ -74,55 +372,321 @@ impl<T: IndexSortable + Default, P: IndexSortable + Default> SearchIndex<T, P> {
             bins_per_dalton,
             max_item_mass,
             sort_type,
+            auto_grow: false,
+            bin_schedule: None,
+            flat: bins_per_dalton == 0,
         }
     }
 
     pub fn total_bins_for_mass(&self) -> u32 {
+        if self.flat {
+            return 1;
+        }
         self.bins_per_dalton * (self.max_item_mass.round() as u32)
     }
 
     pub fn bin_for_mass(&self, mass: MassType) -> usize {
-        let i = (mass * self.bins_per_dalton as MassType).round() as usize;
-        let bin_index = if i >= self.bins.len() {
-            self.bins.len() - 1
+        match &self.bin_schedule {
+            Some(schedule) => schedule.bin_index(mass, self.max_item_mass, self.bins.len()),
+            None => bin_index_for(mass, self.bins_per_dalton, self.bins.len()),
+        }
+    }
+
+    /// The half-open mass range `[low, high)` that [`SearchIndex::bin_for_mass`] maps to
+    /// `bin`. The last bin also collects everything above `max_item_mass`, so its upper
+    /// bound is infinite.
+    pub fn bin_mass_range(&self, bin: usize) -> (MassType, MassType) {
+        let (low, high) = match &self.bin_schedule {
+            Some(schedule) => schedule.bin_mass_range(bin, self.max_item_mass),
+            None => {
+                let width = 1.0 / self.bins_per_dalton as MassType;
+                let low = ((bin as MassType - 0.5) * width).max(0.0);
+                let high = if bin + 1 >= self.bins.len() {
+                    MassType::INFINITY
+                } else {
+                    (bin as MassType + 0.5) * width
+                };
+                (low, high)
+            }
+        };
+        // Rounding can leave the computed boundaries just inside the previous bin
+        let nudge = |mut edge: MassType, bin: usize| {
+            while edge > 0.0 && edge.is_finite() && self.bin_for_mass(edge) < bin {
+                edge = MassType::from_bits(edge.to_bits() + 1);
+            }
+            edge
+        };
+        (nudge(low, bin), nudge(high, bin + 1))
+    }
+
+    /// The mass boundaries of every bin, one more than there are bins, so bin `i` spans
+    /// `edges[i]..edges[i + 1]` as given by [`SearchIndex::bin_mass_range`]. The final edge
+    /// closes the last bin at the usual bin width and is at least `max_item_mass`, although
+    /// the last bin also collects every heavier entry.
+    pub fn bin_edges(&self) -> Vec<MassType> {
+        let mut edges: Vec<MassType> = (0..self.bins.len())
+            .map(|bin| self.bin_mass_range(bin).0)
+            .collect();
+        let end = if self.is_flat() {
+            self.max_item_mass
+        } else if let Some(schedule) = &self.bin_schedule {
+            let (start, bins_per_dalton) = *schedule.regions().last().unwrap();
+            let last_low = edges.last().copied().unwrap_or_default();
+            last_low.max(start) + 1.0 / bins_per_dalton as MassType
         } else {
-            i
+            (self.bins.len() as MassType - 0.5) / self.bins_per_dalton as MassType
         };
-        return bin_index;
+        edges.push(end.max(self.max_item_mass));
+        edges
+    }
+
+    /// The lowest and highest entry mass actually stored, or `None` if the index has no
+    /// entries. Unlike `max_item_mass`, this reflects the data rather than the bin layout.
+    pub fn observed_mass_range(&self) -> Option<(MassType, MassType)> {
+        self.bins
+            .iter()
+            .flat_map(|b| b.as_slice().iter())
+            .map(|e| e.mass())
+            .fold(None, |acc, mass| match acc {
+                None => Some((mass, mass)),
+                Some((low, high)) => Some((low.min(mass), high.max(mass))),
+            })
     }
 
+    /// Sort the entries of every bin by `ordering`.
+    ///
+    /// A [flat](SearchIndex::flat) index is sorted [`SortType::ByMass`] when
+    /// [`SortType::ByParentId`] is requested, and [`SearchIndex::sort_type`] and written
+    /// metadata report that. Searches restricted to a parent interval find the same
+    /// entries, but in mass order rather than grouped by parent.
     pub fn sort(&mut self, ordering: SortType) {
+        let ordering = self.bin_ordering(ordering);
         for bin in self.bins.iter_mut() {
             bin.sort(ordering)
         }
         self.sort_type = ordering
     }
 
+    /// Remove duplicate entries within each bin, returning the number removed.
+    ///
+    /// This should be called after [`SearchIndex::sort`], which makes the duplicate check
+    /// cheaper, and it preserves the existing order.
+    pub fn dedup(&mut self) -> usize
+    where
+        T: PartialEq,
+    {
+        self.bins.iter_mut().map(|bin| bin.dedup()).sum()
+    }
+
+    /// Release excess capacity held by the bins and parents, e.g. after bulk loading.
+    pub fn shrink_to_fit(&mut self) {
+        self.bins.iter_mut().for_each(|bin| bin.shrink_to_fit());
+        self.parents.shrink_to_fit();
+    }
+
+    /// Remove all entries and parents while keeping the bins, their allocated capacity and
+    /// the index's mass range, so it can be refilled without reallocating.
+    pub fn clear(&mut self) {
+        self.bins.iter_mut().for_each(|bin| bin.clear());
+        self.parents.clear();
+        self.sort_type = SortType::Unsorted;
+    }
+
+    /// Reserve space for at least `additional` more parents, e.g. when the number of
+    /// peptides is known before they are added.
+    pub fn reserve_parents(&mut self, additional: usize) {
+        self.parents.reserve(additional);
+    }
+
+    /// Sort as with [`SearchIndex::sort`], processing the bins in parallel
     #[cfg(feature = "parallelism")]
     pub fn par_sort(&mut self, ordering: SortType)
     where
         T: Send,
     {
+        let ordering = self.bin_ordering(ordering);
         self.bins.par_iter_mut().for_each(|bin| bin.sort(ordering));
         self.sort_type = ordering;
     }
 
+    /// A flat index is only searched efficiently when its one bin is ordered by mass
+    fn bin_ordering(&self, ordering: SortType) -> SortType {
+        if self.is_flat() && ordering == SortType::ByParentId {
+            SortType::ByMass
+        } else {
+            ordering
+        }
+    }
+
     pub fn add_parent(&mut self, parent_molecule: P) {
-        self.parents.push(parent_molecule)
+        self.parents.push(parent_molecule);
+        self.sort_type = SortType::Unsorted;
     }
 
+    /// Add `entry` to the bin for its mass, returning that bin's index.
+    ///
+    /// # Panics
+    /// If the entry's mass is NaN or infinite, which would corrupt binning and sorting.
+    /// Use [`SearchIndex::try_add`] to handle such entries as errors instead.
     pub fn add(&mut self, entry: T) -> usize {
         let mass = entry.mass();
+        assert!(mass.is_finite(), "{}", IndexInsertError::NonFiniteMass(mass));
+        if self.auto_grow && mass > self.max_item_mass {
+            // Grow at least geometrically so ascending inserts don't rebuild the bins each time
+            self.grow_to(mass.max(self.max_item_mass * 2.0));
+        }
         let bin_index = self.bin_for_mass(mass);
         self.bins[bin_index].push(entry);
+        self.sort_type = SortType::Unsorted;
         bin_index
     }
 
+    /// Extend the bins to cover masses up to `max_item_mass`, keeping existing entries.
+    /// Entries held in the old overflow bin are moved to the bins they now fit in.
+    pub fn grow_to(&mut self, max_item_mass: MassType) {
+        if max_item_mass <= self.max_item_mass {
+            return;
+        }
+        let overflow = self.bins.pop().unwrap_or_default();
+        self.max_item_mass = max_item_mass;
+        let num_bins = self.bin_count(max_item_mass);
+        self.bins.resize_with(num_bins, IndexBin::default);
+        for entry in overflow.entries {
+            let bin_index = self.bin_for_mass(entry.mass());
+            self.bins[bin_index].push(entry);
+        }
+        self.sort_type = SortType::Unsorted;
+    }
+
+    pub fn auto_grow(&self) -> bool {
+        self.auto_grow
+    }
+
+    /// Build a copy of this index with its entries redistributed into `bins_per_dalton`
+    /// bins per Dalton. The copy is sorted the same way as this index.
+    pub fn rebin(&self, bins_per_dalton: u32) -> SearchIndex<T, P>
+    where
+        T: Clone,
+        P: Clone,
+    {
+        let mut inst = Self::with_capacity(
+            bins_per_dalton,
+            self.max_item_mass,
+            self.num_entries(),
+            0,
+        );
+        inst.parents = self.parents.clone();
+        for entry in self.iter_entries() {
+            inst.add(entry.clone());
+        }
+        inst.auto_grow = self.auto_grow;
+        if self.sort_type != SortType::Unsorted {
+            inst.sort(self.sort_type);
+        }
+        inst
+    }
+
+    /// Coalesce runs of sparse bins, e.g. after reading an index, by switching to a
+    /// [`BinSchedule`] whose resolution drops where bins would hold fewer than
+    /// `min_bin_size` entries. Dense mass ranges keep the index's resolution, searches
+    /// return the same entries and the sort order is kept. Flat indices are left as-is.
+    pub fn compact(&mut self, min_bin_size: usize) {
+        if self.is_flat() {
+            return;
+        }
+        // The granularity at which the resolution may change
+        const REGION_WIDTH: MassType = 25.0;
+        let num_regions = ((self.max_item_mass / REGION_WIDTH).ceil() as usize).max(1);
+        let mut counts = vec![0usize; num_regions];
+        for entry in self.iter_entries() {
+            let i = (entry.mass().max(0.0) / REGION_WIDTH) as usize;
+            counts[i.min(num_regions - 1)] += 1;
+        }
+
+        let mut regions: Vec<(MassType, u32)> = Vec::new();
+        for (i, count) in counts.into_iter().enumerate() {
+            let per_dalton = count as MassType / (REGION_WIDTH * min_bin_size.max(1) as MassType);
+            let bins_per_dalton = (per_dalton.ceil() as u32).clamp(1, self.bins_per_dalton);
+            if regions.last().is_some_and(|(_, b)| *b == bins_per_dalton) {
+                continue;
+            }
+            regions.push((i as MassType * REGION_WIDTH, bins_per_dalton));
+        }
+        let schedule = BinSchedule::from_regions(regions)
+            .expect("Compacted regions start at zero and increase");
+
+        let sort_type = self.sort_type;
+        let bins = std::mem::take(&mut self.bins);
+        self.bins_per_dalton = schedule.max_bins_per_dalton();
+        self.bin_schedule = Some(schedule);
+        self.initialize_bins();
+        for entry in bins.into_iter().flat_map(|bin| bin.entries) {
+            let bin_index = self.bin_for_mass(entry.mass());
+            self.bins[bin_index].push(entry);
+        }
+        self.sort_type = SortType::Unsorted;
+        if sort_type != SortType::Unsorted {
+            self.sort(sort_type);
+        }
+    }
+
+    /// Add `entry` as with [`SearchIndex::add`], but reject it instead of placing it in the
+    /// overflow bin when its mass exceeds the index's `max_item_mass`.
+    pub fn try_add(&mut self, entry: T) -> Result<usize, IndexInsertError> {
+        let mass = entry.mass();
+        if !mass.is_finite() {
+            return Err(IndexInsertError::NonFiniteMass(mass));
+        }
+        if !self.auto_grow && mass > self.max_item_mass {
+            return Err(IndexInsertError::MassExceedsMaximum(mass, self.max_item_mass));
+        }
+        Ok(self.add(entry))
+    }
+
     pub fn parents_for(&self, mass: MassType, error_tolerance: Tolerance) -> Interval {
+        self.debug_assert_sorted();
         let iv = self.parents.search_mass(mass, error_tolerance);
         iv
     }
 
+    /// The parent closest in mass to `mass` with its id and absolute mass difference, however
+    /// far away it is, or `None` if there are no parents. Ties go to the lighter parent.
+    pub fn nearest_parent(&self, mass: MassType) -> Option<(ParentID, &P, MassType)> {
+        self.debug_assert_sorted();
+        let parents = self.parents.as_slice();
+        let i = parents.partition_point(|p| p.mass() < mass);
+        let candidates = [i.checked_sub(1), (i < parents.len()).then_some(i)];
+        candidates
+            .into_iter()
+            .flatten()
+            .map(|j| (j as ParentID, &parents[j], (parents[j].mass() - mass).abs()))
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+    }
+
+    /// Iterate over the parents matching `mass`, rather than the [`Interval`] returned by
+    /// [`SearchIndex::parents_for`]
+    pub fn iter_parents_for(
+        &self,
+        mass: MassType,
+        error_tolerance: Tolerance,
+    ) -> std::slice::Iter<'_, P> {
+        let iv = self.parents_for(mass, error_tolerance);
+        self.parents.as_slice()[iv.start..iv.end].iter()
+    }
+
+    /// Iterate over the parents in ascending mass order, each paired with the id entries
+    /// use to refer to it, its position in [`SearchIndex::parents`]
+    pub fn iter_parents_sorted(&self) -> impl Iterator<Item = (ParentID, &P)> + '_ {
+        let mut parents: Vec<(ParentID, &P)> = self
+            .parents
+            .iter()
+            .enumerate()
+            .map(|(i, parent)| (i as ParentID, parent))
+            .collect();
+        parents.sort_by(|(_, a), (_, b)| a.mass().total_cmp(&b.mass()));
+        parents.into_iter()
+    }
+
     pub fn parents_for_range(
         &self,
         low: MassType,
@@ -141,6 +705,7 @@ impl<T: IndexSortable + Default, P: IndexSortable + Default> SearchIndex<T, P> {
         error_tolerance: Tolerance,
         parent_interval: Option<Interval>,
     ) -> SearchIndexSearchIter<'_, T, P> {
+        self.debug_assert_sorted();
         SearchIndexSearchIter::new(
             self,
             query,
@@ -149,263 +714,2281 @@ impl<T: IndexSortable + Default, P: IndexSortable + Default> SearchIndex<T, P> {
         )
     }
 
-    pub fn bins_per_dalton(&self) -> u32 {
-        self.bins_per_dalton
+    /// Search for entries matching `query` like [`SearchIndex::search`], calling `observer`
+    /// with the index and size of each bin as it is scanned, e.g. to profile which bins
+    /// dominate query time. The matches are the same as those of [`SearchIndex::search`].
+    pub fn search_instrumented(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+        observer: &mut dyn FnMut(usize, usize),
+    ) -> Vec<&T> {
+        self.debug_assert_sorted();
+        let parent_interval = parent_interval.unwrap_or_else(|| Interval::new(0, self.parents.len()));
+        let mut bins = SearchIndexBinIter::new(self, query, error_tolerance);
+        let mut hits = Vec::new();
+        while let Some(bin) = bins.next_bin() {
+            observer(bins.current_bin - 1, bin.len());
+            hits.extend(ParentSortedIndexBinSearchIter::new(
+                bin,
+                parent_interval,
+                query,
+                error_tolerance,
+            ));
+        }
+        hits
     }
 
-    pub fn max_item_mass(&self) -> f32 {
-        self.max_item_mass
+    /// Search for entries matching `query` like [`SearchIndex::search`], stopping as soon as
+    /// `predicate` returns `false` for a match. That match is not yielded and no further
+    /// bins are scanned.
+    pub fn search_while<'b, F: FnMut(&T) -> bool + 'b>(
+        &'b self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+        mut predicate: F,
+    ) -> impl Iterator<Item = &'b T> + 'b {
+        self.search(query, error_tolerance, parent_interval)
+            .take_while(move |entry| predicate(entry))
     }
 
-    pub fn sort_type(&self) -> SortType {
-        self.sort_type
+    /// Search for entries matching an observed `mz` at `charge`, converting it to a neutral
+    /// mass with [`neutral_mass`] first
+    pub fn search_mz(
+        &self,
+        mz: MassType,
+        charge: i8,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> SearchIndexSearchIter<'_, T, P> {
+        self.search(neutral_mass(mz, charge), error_tolerance, parent_interval)
     }
-}
-
-#[cfg(feature = "binary_storage")]
-mod storage {
-    use super::*;
-
-    impl<
-            'a,
-            T: IndexSortable + Default + ArrowStorage + 'a,
-            P: IndexSortable + Default + ArrowStorage + 'a,
-        > IndexBinaryStorage<'a, T, P, IndexMetadata> for SearchIndex<T, P>
-    {
-        fn parents(&self) -> &[P] {
-            self.parents.as_slice()
-        }
 
-        fn iter_entries(&'a self) -> impl Iterator<Item = &'a [T]> + 'a {
-            self.bins.iter().map(|b| b.as_slice())
-        }
+    /// Search for entries matching an observed `mz` at `charge` where the charge is carried by
+    /// `adduct_mass` instead of protons, see [`neutral_mass_with_adduct`]
+    pub fn search_mz_with_adduct(
+        &self,
+        mz: MassType,
+        charge: i8,
+        adduct_mass: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> SearchIndexSearchIter<'_, T, P> {
+        self.search(
+            neutral_mass_with_adduct(mz, charge, adduct_mass),
+            error_tolerance,
+            parent_interval,
+        )
+    }
 
-        fn to_metadata(&self) -> IndexMetadata {
-            IndexMetadata {
-                bins_per_dalton: self.bins_per_dalton,
-                max_item_mass: self.max_item_mass,
-            }
-        }
+    /// Search for entries matching `fragment_mass` whose parents match `precursor_mass`,
+    /// combining [`SearchIndex::parents_for`] and [`SearchIndex::search`]
+    pub fn search_with_precursor(
+        &self,
+        fragment_mass: MassType,
+        fragment_error_tolerance: Tolerance,
+        precursor_mass: MassType,
+        precursor_error_tolerance: Tolerance,
+    ) -> SearchIndexSearchIter<'_, T, P> {
+        let parent_interval = self.parents_for(precursor_mass, precursor_error_tolerance);
+        self.search(fragment_mass, fragment_error_tolerance, Some(parent_interval))
+    }
 
-        fn from_components(
-            metadata: IndexMetadata,
-            parents: Vec<P>,
-            entries: HashMap<u64, Vec<T>>,
-        ) -> Self {
-            let mut parents = IndexBin::from(parents);
-            parents.assume_sorted(SortType::ByMass);
-            let mut this = Self::empty(metadata.bins_per_dalton, metadata.max_item_mass);
-            this.parents = parents;
-            entries.into_iter().for_each(|(k, b)| {
-                let mut bin = IndexBin::from(b);
-                bin.assume_sorted(SortType::ByParentId);
-                this.bins[k as usize] = bin;
-            });
-            this
+    /// Search for entries matching `query` like [`SearchIndex::search`], grouping the
+    /// matches by their parent
+    pub fn search_grouped(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> HashMap<ParentID, Vec<&T>> {
+        let mut groups: HashMap<ParentID, Vec<&T>> = HashMap::new();
+        for entry in self.search(query, error_tolerance, parent_interval) {
+            groups.entry(entry.parent_id()).or_default().push(entry);
         }
+        groups
     }
 
-    impl<
-            'a,
-            T: IndexSortable + Default + ArrowStorage + 'a + Clone,
-            P: IndexSortable + Default + ArrowStorage + 'a,
-        > SplitIndexBinaryStorage<'a, T, P, IndexMetadata> for SearchIndex<T, P>
+    /// Search for entries matching `query` like [`SearchIndex::search`], returning owned
+    /// copies that are not tied to the lifetime of the index
+    pub fn search_owned(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> Vec<T>
+    where
+        T: Clone,
     {
+        self.search(query, error_tolerance, parent_interval)
+            .cloned()
+            .collect()
     }
 
-    impl<
-            'a,
-            T: IndexSortable + Default + ArrowStorage + 'a,
-            P: IndexSortable + Default + ArrowStorage + 'a,
-        > SearchIndex<T, P>
-    {
-        pub fn write_parquet<D: AsRef<std::path::Path>>(
-            &'a self,
-            directory: &D,
-            compression_level: Option<parquet::basic::Compression>,
-        ) -> io::Result<()> {
-            self.write(directory, compression_level)
-        }
+    #[inline(always)]
+    fn debug_assert_sorted(&self) {
+        debug_assert!(
+            self.sort_type != SortType::Unsorted,
+            "The index must be sorted with `sort` after adding entries or parents and before searching"
+        );
+    }
 
-        pub fn read_parquet<D: AsRef<std::path::Path>>(directory: &D) -> io::Result<Self> {
-            Self::read(directory)
+    pub fn search_batch(
+        &self,
+        queries: &[MassType],
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> Vec<Vec<&T>> {
+        // Visit the queries in mass order so neighboring lookups touch neighboring bins
+        let mut order: Vec<usize> = (0..queries.len()).collect();
+        order.sort_by(|a, b| queries[*a].total_cmp(&queries[*b]));
+        let mut results = vec![Vec::new(); queries.len()];
+        for i in order {
+            results[i] = self
+                .search(queries[i], error_tolerance, parent_interval)
+                .collect();
         }
+        results
     }
 
-    impl<
-            'a,
-            T: IndexSortable + Default + ArrowStorage + 'a + Clone,
-            P: IndexSortable + Default + ArrowStorage + 'a,
-        > SearchIndex<T, P>
-    {
-        pub fn write_banded_parquet<D: AsRef<std::path::Path>>(
-            &'a self,
-            directory: &D,
-            bin_width: f32,
-            compression_level: Option<parquet::basic::Compression>,
-        ) -> io::Result<()> {
-            self.write_split(directory, bin_width, compression_level)
-        }
+    #[cfg(feature = "parallelism")]
+    pub fn par_search_batch(
+        &self,
+        queries: &[MassType],
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> Vec<Vec<&T>>
+    where
+        T: Sync,
+        P: Sync,
+    {
+        queries
+            .par_iter()
+            .map(|query| {
+                self.search(*query, error_tolerance, parent_interval)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
 
-        pub fn read_banded_parquet<D: AsRef<std::path::Path>>(directory: &D) -> io::Result<Self> {
-            Self::read_split(directory)
+    pub fn search_with_error(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> impl Iterator<Item = SearchHit<'_, T>> + '_ {
+        self.search(query, error_tolerance, parent_interval)
+            .map(move |entry| SearchHit::new(entry, query))
+    }
+
+    /// Count the entries [`SearchIndex::search`] would yield without visiting each one
+    /// where possible. Bins sorted by parent ID that lie entirely inside the mass window
+    /// are counted by locating the parent range alone.
+    pub fn count_matches(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> usize {
+        self.debug_assert_sorted();
+        let parent_range = parent_interval.unwrap_or_else(|| Interval::new(0, self.parents.len()));
+        let (low, high) = error_tolerance.bounds(query);
+        SearchIndexBinIter::new(self, query, error_tolerance)
+            .map(|bin| {
+                if bin.sort_type == SortType::ByParentId
+                    && low <= bin.min_mass
+                    && high >= bin.max_mass
+                {
+                    let start = bin
+                        .entries
+                        .partition_point(|e| (e.parent_id() as usize) < parent_range.start);
+                    let end = bin
+                        .entries
+                        .partition_point(|e| (e.parent_id() as usize) < parent_range.end);
+                    end.saturating_sub(start)
+                } else {
+                    ParentSortedIndexBinSearchIter::new(bin, parent_range, query, error_tolerance)
+                        .count()
+                }
+            })
+            .sum()
+    }
+
+    /// Whether [`SearchIndex::search`] would yield any entry, stopping at the first one
+    /// found instead of counting them all as [`SearchIndex::count_matches`] does.
+    pub fn has_match(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> bool {
+        self.debug_assert_sorted();
+        let parent_range = parent_interval.unwrap_or_else(|| Interval::new(0, self.parents.len()));
+        let (low, high) = error_tolerance.bounds(query);
+        SearchIndexBinIter::new(self, query, error_tolerance).any(|bin| {
+            if bin.sort_type == SortType::ByParentId && low <= bin.min_mass && high >= bin.max_mass {
+                let start = bin
+                    .entries
+                    .partition_point(|e| (e.parent_id() as usize) < parent_range.start);
+                bin.entries
+                    .get(start)
+                    .is_some_and(|e| (e.parent_id() as usize) < parent_range.end)
+            } else {
+                ParentSortedIndexBinSearchIter::new(bin, parent_range, query, error_tolerance)
+                    .next()
+                    .is_some()
+            }
+        })
+    }
+
+    /// Find the single entry closest in mass to `query` within `error_tolerance`.
+    pub fn search_first(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> Option<&T> {
+        self.search_with_error(query, error_tolerance, parent_interval)
+            .min_by(|a, b| a.ppm_error.abs().total_cmp(&b.ppm_error.abs()))
+            .map(|hit| hit.entry)
+    }
+
+    pub fn bins_per_dalton(&self) -> u32 {
+        self.bins_per_dalton
+    }
+
+    pub fn max_item_mass(&self) -> MassType {
+        self.max_item_mass
+    }
+
+    /// The ordering last applied with [`SearchIndex::sort`], or [`SortType::Unsorted`]
+    /// if the index has been modified since.
+    pub fn sort_type(&self) -> SortType {
+        self.sort_type
+    }
+
+    /// Borrow a read-only [`SearchIndexView`] of this index to share between threads
+    pub fn view(&self) -> SearchIndexView<'_, T, P> {
+        SearchIndexView::new(self)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BinStats {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub empty_bins: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexMergeError {
+    BinsPerDaltonMismatch(u32, u32),
+    /// The indices do not share the same [`BinSchedule`]
+    BinScheduleMismatch,
+}
+
+impl Display for IndexMergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BinsPerDaltonMismatch(ours, theirs) => write!(
+                f,
+                "Cannot merge an index with {theirs} bins per dalton into one with {ours} bins per dalton"
+            ),
+            Self::BinScheduleMismatch => {
+                write!(f, "Cannot merge indices with different bin schedules")
+            }
         }
     }
 }
 
-pub struct SearchIndexBinIter<'a, T: IndexSortable + Default, P: IndexSortable + Default> {
-    index: &'a SearchIndex<T, P>,
-    pub query: MassType,
-    pub error_tolerance: Tolerance,
-    pub low_bin: usize,
-    pub high_bin: usize,
-    pub current_bin: usize,
+impl Error for IndexMergeError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexInsertError {
+    MassExceedsMaximum(MassType, MassType),
+    /// The entry's mass is NaN or infinite
+    NonFiniteMass(MassType),
 }
 
-impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> ExactSizeIterator
-    for SearchIndexBinIter<'a, T, P>
-{
-    fn len(&self) -> usize {
-        self.high_bin.saturating_sub(self.low_bin)
+impl Display for IndexInsertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MassExceedsMaximum(mass, max_item_mass) => write!(
+                f,
+                "Cannot add an entry with mass {mass} to an index with a maximum item mass of {max_item_mass}"
+            ),
+            Self::NonFiniteMass(mass) => {
+                write!(f, "Cannot add an entry with non-finite mass {mass} to an index")
+            }
+        }
     }
 }
 
-impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> FusedIterator
-    for SearchIndexBinIter<'a, T, P>
-{
+impl Error for IndexInsertError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexError {
+    /// The parent at a position carries a different id
+    NonContiguousParentId(usize, ParentID),
+    /// An entry references a parent id beyond the number of parents
+    UnknownParentId(ParentID, usize),
+    /// An entry or parent has a negative or NaN mass
+    InvalidMass(MassType),
 }
 
-impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> Iterator
-    for SearchIndexBinIter<'a, T, P>
-{
-    type Item = &'a IndexBin<T>;
+impl Display for IndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NonContiguousParentId(position, parent_id) => write!(
+                f,
+                "Parent at position {position} has id {parent_id}, parent ids must start at 0 and increase by 1"
+            ),
+            Self::UnknownParentId(parent_id, num_parents) => write!(
+                f,
+                "An entry references parent {parent_id} but the index only has {num_parents} parents"
+            ),
+            Self::InvalidMass(mass) => write!(f, "Masses must be non-negative, found {mass}"),
+        }
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.next_bin()
+impl Error for IndexError {}
+
+impl<T: IndexSortable + Default, P: IndexSortable + Default + SortIDMut> SearchIndex<T, P> {
+    /// Check that the parents are numbered by their position, that every entry refers to
+    /// one of them, and that no mass is negative. Searching an index that fails these
+    /// checks can return wrong parents or panic.
+    pub fn validate(&self) -> Result<(), IndexError> {
+        for (i, parent) in self.parents.iter().enumerate() {
+            if parent.sort_id() as usize != i {
+                return Err(IndexError::NonContiguousParentId(i, parent.sort_id()));
+            }
+            if parent.mass() < 0.0 || parent.mass().is_nan() {
+                return Err(IndexError::InvalidMass(parent.mass()));
+            }
+        }
+        let num_parents = self.parents.len();
+        for entry in self.iter_entries() {
+            if entry.parent_id() as usize >= num_parents {
+                return Err(IndexError::UnknownParentId(entry.parent_id(), num_parents));
+            }
+            if entry.mass() < 0.0 || entry.mass().is_nan() {
+                return Err(IndexError::InvalidMass(entry.mass()));
+            }
+        }
+        Ok(())
     }
 }
 
-impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> SearchIndexBinIter<'a, T, P> {
-    pub fn new(index: &'a SearchIndex<T, P>, query: MassType, error_tolerance: Tolerance) -> Self {
-        let (low_mass, high_mass) = error_tolerance.bounds(query);
-        let low_bin = index.bin_for_mass(low_mass);
-        let high_bin = (index.bin_for_mass(high_mass) + 1).min(index.bins.len());
-        Self {
-            index,
-            query,
-            error_tolerance,
-            low_bin,
-            high_bin,
-            current_bin: low_bin,
+impl<T: IndexSortable + Default + ParentIDMut, P: IndexSortable + Default + SortIDMut>
+    SearchIndex<T, P>
+{
+    /// Build an index from parents paired with their entries.
+    ///
+    /// Parents are placed in mass order and numbered by position, and each entry's parent
+    /// id is set to its parent's. The result is left unsorted, so the caller must call
+    /// [`SearchIndex::sort`] before searching.
+    pub fn from_parents_and_fragments(
+        mut data: Vec<(P, Vec<T>)>,
+        bins_per_dalton: u32,
+        max_item_mass: MassType,
+    ) -> Self {
+        let num_entries = data.iter().map(|(_, entries)| entries.len()).sum();
+        let mut inst = Self::with_capacity(bins_per_dalton, max_item_mass, num_entries, data.len());
+        data.sort_by(|(a, _), (b, _)| a.mass().total_cmp(&b.mass()));
+        for (i, (mut parent, entries)) in data.into_iter().enumerate() {
+            parent.set_sort_id(i as ParentID);
+            inst.add_parent(parent);
+            for mut entry in entries {
+                entry.set_parent_id(i as ParentID);
+                inst.add(entry);
+            }
         }
+        inst.parents.assume_sorted(SortType::ByMass);
+        inst.sort_type = SortType::Unsorted;
+        inst
     }
 
-    #[inline(always)]
-    fn next_bin(&mut self) -> Option<&'a IndexBin<T>> {
-        if self.current_bin < self.high_bin {
-            let bin = unsafe { self.index.bins.get_unchecked(self.current_bin) };
-            self.current_bin += 1;
-            Some(bin)
-        } else {
-            None
+    /// Combine `other` into this index.
+    ///
+    /// Parents from both indices are interleaved in mass order and re-numbered, and every
+    /// entry's parent id is remapped to match. The result is left unsorted, so the caller
+    /// must call [`SearchIndex::sort`] before searching.
+    pub fn merge(&mut self, other: SearchIndex<T, P>) -> Result<(), IndexMergeError> {
+        if self.bins_per_dalton != other.bins_per_dalton {
+            return Err(IndexMergeError::BinsPerDaltonMismatch(
+                self.bins_per_dalton,
+                other.bins_per_dalton,
+            ));
+        }
+        if self.bin_schedule != other.bin_schedule {
+            return Err(IndexMergeError::BinScheduleMismatch);
+        }
+        let offset = self.parents.len();
+
+        let mut parents: Vec<(usize, P)> = std::mem::take(&mut self.parents.entries)
+            .into_iter()
+            .chain(other.parents.entries)
+            .enumerate()
+            .collect();
+        parents.sort_by(|(_, a), (_, b)| a.mass().total_cmp(&b.mass()));
+
+        let mut id_map = vec![0 as ParentID; parents.len()];
+        let parents: Vec<P> = parents
+            .into_iter()
+            .enumerate()
+            .map(|(new_id, (old_id, mut parent))| {
+                id_map[old_id] = new_id as ParentID;
+                parent.set_sort_id(new_id as ParentID);
+                parent
+            })
+            .collect();
+        self.parents = IndexBin::from(parents);
+        self.parents.assume_sorted(SortType::ByMass);
+
+        let own_bins = std::mem::take(&mut self.bins);
+        self.max_item_mass = self.max_item_mass.max(other.max_item_mass);
+        self.initialize_bins();
+
+        for bin in own_bins {
+            for mut entry in bin.entries {
+                entry.set_parent_id(id_map[entry.parent_id() as usize]);
+                self.add(entry);
+            }
+        }
+        for bin in other.bins {
+            for mut entry in bin.entries {
+                entry.set_parent_id(id_map[entry.parent_id() as usize + offset]);
+                self.add(entry);
+            }
+        }
+        self.sort_type = SortType::Unsorted;
+        Ok(())
+    }
+
+    /// Remove the parent at `parent_id` and every entry that refers to it, returning the
+    /// removed parent if it existed.
+    ///
+    /// Later parents shift down to fill the gap and entries are remapped to match, so the
+    /// index must be re-sorted before searching.
+    pub fn remove_parent(&mut self, parent_id: ParentID) -> Option<P> {
+        let position = parent_id as usize;
+        if position >= self.parents.len() {
+            return None;
+        }
+        let removed = self.parents.entries.remove(position);
+        for (i, parent) in self.parents.entries.iter_mut().enumerate().skip(position) {
+            parent.set_sort_id(i as ParentID);
+        }
+
+        for bin in self.bins.iter_mut() {
+            bin.entries.retain(|entry| entry.parent_id() != parent_id);
+            for entry in bin.entries.iter_mut() {
+                if entry.parent_id() > parent_id {
+                    entry.set_parent_id(entry.parent_id() - 1);
+                }
+            }
         }
+        self.sort_type = SortType::Unsorted;
+        Some(removed)
     }
 }
 
-pub struct SearchIndexSearchIter<'a, T: IndexSortable + Default, P: IndexSortable + Default> {
-    pub query: MassType,
-    pub error_tolerance: Tolerance,
-    pub parent_range: Interval,
-    bin_iter: SearchIndexBinIter<'a, T, P>,
-    item_iter: Option<ParentSortedIndexBinSearchIter<'a, T>>,
+/// A search result paired with its signed mass error, `(entry - query) / query` in PPM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchHit<'a, T> {
+    pub entry: &'a T,
+    pub ppm_error: MassType,
 }
 
-impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> Iterator
-    for SearchIndexSearchIter<'a, T, P>
-{
-    type Item = &'a T;
+impl<'a, T: IndexSortable> SearchHit<'a, T> {
+    pub fn new(entry: &'a T, query: MassType) -> Self {
+        let ppm_error = (entry.mass() - query) / query * 1e6;
+        Self { entry, ppm_error }
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.next_entry()
+/// A read-only borrow of a sorted [`SearchIndex`] that only exposes searching and
+/// iteration. It is `Copy`, and `Send + Sync` whenever the entries and parents are, so
+/// it can be handed to any number of worker threads.
+#[derive(Debug)]
+pub struct SearchIndexView<'a, T: IndexSortable + Default, P: IndexSortable + Default> {
+    index: &'a SearchIndex<T, P>,
+}
+
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> Clone for SearchIndexView<'a, T, P> {
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
-impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> SearchIndexSearchIter<'a, T, P> {
-    pub fn new(
-        index: &'a SearchIndex<T, P>,
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> Copy for SearchIndexView<'a, T, P> {}
+
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> SearchIndexView<'a, T, P> {
+    pub fn new(index: &'a SearchIndex<T, P>) -> Self {
+        Self { index }
+    }
+
+    pub fn search(
+        &self,
         query: MassType,
         error_tolerance: Tolerance,
-        parent_range: Interval,
-    ) -> Self {
-        let bin_iter = SearchIndexBinIter::new(index, query, error_tolerance);
-        let mut this = Self {
-            query,
-            error_tolerance,
-            parent_range,
-            bin_iter,
-            item_iter: None,
-        };
-        this.next_bin_iterator();
-        this
+        parent_interval: Option<Interval>,
+    ) -> SearchIndexSearchIter<'a, T, P> {
+        self.index.search(query, error_tolerance, parent_interval)
     }
 
-    #[inline(always)]
-    fn next_bin_iterator(&mut self) -> bool {
-        self.item_iter = self.bin_iter.next().map(|bin| {
-            ParentSortedIndexBinSearchIter::new(
-                bin,
-                self.parent_range,
-                self.query,
-                self.error_tolerance,
-            )
-        });
-        self.item_iter.is_some()
+    pub fn parents_for(&self, mass: MassType, error_tolerance: Tolerance) -> Interval {
+        self.index.parents_for(mass, error_tolerance)
     }
 
-    #[inline(always)]
-    fn next_entry(&mut self) -> Option<&'a T> {
-        loop {
-            if let Some(t) = self.item_iter.as_mut().and_then(|it| it.next()) {
-                return Some(t);
-            } else {
-                if !self.next_bin_iterator() {
-                    return None;
-                }
-            }
-        }
+    pub fn parents_for_range(
+        &self,
+        low: MassType,
+        high: MassType,
+        error_tolerance: Tolerance,
+    ) -> Interval {
+        self.index.parents_for_range(low, high, error_tolerance)
+    }
+
+    pub fn get_parent(&self, parent_id: usize) -> Option<&'a P> {
+        self.index.parents.get(parent_id)
+    }
+
+    pub fn iter_parents(&self) -> std::slice::Iter<'a, P> {
+        self.index.parents.iter()
+    }
+
+    pub fn iter_entries(&self) -> impl Iterator<Item = &'a T> + 'a {
+        self.index.iter_entries()
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.index.num_entries()
+    }
+
+    pub fn num_parents(&self) -> usize {
+        self.index.num_parents()
     }
 }
 
-#[cfg(test)]
-mod test {
+#[cfg(feature = "binary_storage")]
+mod storage {
     use super::*;
-    use crate::parent::Spectrum;
-    use crate::peak::DeconvolutedPeak;
+    use arrow::{array::RecordBatch, error::ArrowError};
 
-    #[test]
-    fn test_build() {
-        let spectra = vec![
-            Spectrum::new(2300.0, 2, 0, 0, 0),
-            Spectrum::new(2301.0, 2, 0, 1, 1),
-            Spectrum::new(2401.0, 2, 1, 0, 2),
-            Spectrum::new(4100.0, 4, 0, 2, 3),
-        ];
-        let mut parent_list = IndexBin::new(spectra, SortType::Unsorted, 0.0, 0.0);
-        parent_list.sort(SortType::ByMass);
+    use crate::storage::{
+        appended_segments, existing_build_id, next_appended_segment, read_metadata_from,
+        remove_appended_segments, write_entries_into, write_metadata_into, write_parents_into,
+    };
+    use crate::MassFragmentIndexError;
 
-        let peaks = vec![
-            DeconvolutedPeak::new(251.5, 1, 0.0, 0),
-            DeconvolutedPeak::new(251.5, 1, 0.0, 2),
-            DeconvolutedPeak::new(251.6, 1, 0.0, 1),
-            DeconvolutedPeak::new(303.7, 1, 0.0, 0),
-            DeconvolutedPeak::new(501.2, 1, 0.0, 1),
-        ];
-        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
-        for peak in peaks {
-            index.add(peak);
+    impl<
+            'a,
+            T: IndexSortable + Default + ArrowStorage + 'a,
+            P: IndexSortable + Default + ArrowStorage + 'a,
+        > IndexBinaryStorage<'a, T, P, IndexMetadata> for SearchIndex<T, P>
+    {
+        fn parents(&self) -> &[P] {
+            self.parents.as_slice()
         }
 
-        let idx = index.bin_for_mass(251.5);
-        assert!(idx == 2515);
+        fn iter_entries(&'a self) -> impl Iterator<Item = &'a [T]> + 'a {
+            self.bins.iter().map(|b| b.as_slice())
+        }
+
+        fn to_metadata(&self) -> IndexMetadata {
+            let mut metadata = IndexMetadata::new(self.bins_per_dalton, self.max_item_mass);
+            metadata.num_entries = self.num_entries() as u64;
+            metadata.num_parents = self.num_parents() as u64;
+            metadata.sort_type = self.sort_type;
+            metadata.bin_schedule = self.bin_schedule.clone();
+            metadata
+        }
+
+        fn from_components(
+            metadata: IndexMetadata,
+            parents: Vec<P>,
+            entries: HashMap<u64, Vec<T>>,
+        ) -> Self {
+            let mut parents = IndexBin::from(parents);
+            parents.assume_sorted(SortType::ByMass);
+            let mut this = match metadata.bin_schedule {
+                Some(schedule) => Self::with_bin_schedule(schedule, metadata.max_item_mass),
+                None => Self::empty(metadata.bins_per_dalton, metadata.max_item_mass),
+            };
+            this.parents = parents;
+            entries.into_iter().for_each(|(k, b)| {
+                let mut bin = IndexBin::from(b);
+                bin.assume_sorted(metadata.sort_type);
+                this.bins[k as usize] = bin;
+            });
+            this.sort_type = metadata.sort_type;
+            this
+        }
+
+        fn check_segments(
+            metadata: &IndexMetadata,
+            entries: &HashMap<u64, Vec<T>>,
+        ) -> io::Result<()> {
+            let num_bins = match &metadata.bin_schedule {
+                Some(schedule) => schedule.bin_count(metadata.max_item_mass),
+                None => bin_count_for(metadata.bins_per_dalton, metadata.max_item_mass),
+            } as u64;
+            match entries.keys().find(|k| **k >= num_bins) {
+                Some(segment_id) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Segment {segment_id} is out of range for {num_bins} bins"),
+                )),
+                None => Ok(()),
+            }
+        }
+
+        fn validate_components(
+            metadata: &IndexMetadata,
+            parents: &[P],
+            entries: &HashMap<u64, Vec<T>>,
+        ) -> io::Result<()> {
+            Self::check_segments(metadata, entries)?;
+            metadata.check_counts(parents.len(), entries.values().map(|b| b.len()).sum())
+        }
+    }
+
+    impl<
+            'a,
+            T: IndexSortable + Default + ArrowStorage + 'a + Clone,
+            P: IndexSortable + Default + ArrowStorage + 'a,
+        > SplitIndexBinaryStorage<'a, T, P, IndexMetadata> for SearchIndex<T, P>
+    {
+    }
+
+    impl<
+            'a,
+            T: IndexSortable + Default + ArrowStorage + 'a,
+            P: IndexSortable + Default + ArrowStorage + 'a,
+        > IndexArchiveStorage<'a, T, P, IndexMetadata> for SearchIndex<T, P>
+    {
+    }
+
+    impl<
+            'a,
+            T: IndexSortable + Default + ArrowStorage + 'a,
+            P: IndexSortable + Default + ArrowStorage + 'a,
+        > IndexIpcStorage<'a, T, P, IndexMetadata> for SearchIndex<T, P>
+    {
+    }
+
+    impl<
+            'a,
+            T: IndexSortable + Default + ArrowStorage + 'a,
+            P: IndexSortable + Default + ArrowStorage + 'a,
+        > SearchIndex<T, P>
+    {
+        /// Write the index as parquet files in `directory`. `None` uses the library default
+        /// compression, while `Some(Compression::UNCOMPRESSED)` writes plain pages.
+        ///
+        /// Once the new files are written, segments added with [`SearchIndex::append_parquet`]
+        /// to the index they replace are removed.
+        pub fn write_parquet<D: AsRef<std::path::Path>>(
+            &'a self,
+            directory: &D,
+            compression_level: Option<parquet::basic::Compression>,
+        ) -> Result<(), MassFragmentIndexError> {
+            let replaced_build_id = existing_build_id(directory.as_ref());
+            self.write(directory, compression_level.map(IndexCompression::from))?;
+            if let Some(build_id) = replaced_build_id {
+                remove_appended_segments(directory.as_ref(), build_id)?;
+            }
+            Ok(())
+        }
+
+        fn read_index_metadata(
+            directory: &std::path::Path,
+        ) -> Result<IndexMetadata, MassFragmentIndexError> {
+            let meta_path = directory.join(IndexMetadata::archive_name());
+            read_metadata_from(io::BufReader::new(std::fs::File::open(meta_path)?))
+        }
+
+        fn read_build_id(directory: &std::path::Path) -> Result<Option<u64>, MassFragmentIndexError> {
+            Ok(Self::read_index_metadata(directory)?.build_id())
+        }
+
+        /// Replace only the parents file of the index written in `directory`, e.g. after
+        /// updating decoy flags, leaving its metadata and entries untouched.
+        ///
+        /// The files are only consistent if the parents keep their number and order, as
+        /// the entries refer to them by position and the metadata records how many there are.
+        pub fn write_parents_parquet<D: AsRef<std::path::Path>>(
+            &self,
+            directory: &D,
+            compression_level: Option<parquet::basic::Compression>,
+        ) -> Result<(), MassFragmentIndexError> {
+            let directory = directory.as_ref();
+            let build_id = Self::read_build_id(directory)?;
+            let compression_level =
+                compression_level.unwrap_or_else(|| IndexCompression::default().parents);
+            self.write_parents(directory, &compression_level, build_id)
+        }
+
+        /// Replace only the entries file of the index written in `directory`, leaving its
+        /// metadata and parents untouched.
+        ///
+        /// The files are only consistent if the number of entries and their sort order are
+        /// unchanged and every entry's parent id still refers to the same parent.
+        pub fn write_entries_parquet<D: AsRef<std::path::Path>>(
+            &'a self,
+            directory: &D,
+            compression_level: Option<parquet::basic::Compression>,
+        ) -> Result<(), MassFragmentIndexError> {
+            let directory = directory.as_ref();
+            let build_id = Self::read_build_id(directory)?;
+            let compression_level =
+                compression_level.unwrap_or_else(|| IndexCompression::default().entries);
+            self.write_entries(directory, &compression_level, build_id, None)
+        }
+
+        /// The entries as in-memory arrow batches, one per non-empty bin, with the bin's index
+        /// as the segment id just as in the entries file
+        pub fn to_entry_batches(&self) -> Result<Vec<RecordBatch>, ArrowError> {
+            let schema = T::schema();
+            self.bins
+                .iter()
+                .enumerate()
+                .filter(|(_, bin)| !bin.is_empty())
+                .map(|(i, bin)| T::to_batch(bin.as_slice(), schema.clone(), i as u64))
+                .collect()
+        }
+
+        /// The parents as a single in-memory arrow batch
+        pub fn to_parent_batch(&self) -> Result<RecordBatch, ArrowError> {
+            P::to_batch(self.parents.as_slice(), P::schema(), 0)
+        }
+
+        /// Build an index from in-memory batches like those of [`SearchIndex::to_entry_batches`]
+        /// and [`SearchIndex::to_parent_batch`], checking them against `metadata` as
+        /// [`SearchIndex::read_parquet`] would.
+        pub fn from_entry_batches(
+            entries: &[RecordBatch],
+            parents: &RecordBatch,
+            metadata: IndexMetadata,
+        ) -> Result<Self, MassFragmentIndexError> {
+            P::validate_batch(parents)?;
+            let parents: Vec<P> = P::from_batch(parents, P::schema()).map(|(p, _)| p).collect();
+            let schema = T::schema();
+            let mut bins: HashMap<u64, Vec<T>> = HashMap::new();
+            for batch in entries {
+                T::validate_batch(batch)?;
+                for (entry, segment_id) in T::from_batch(batch, schema.clone()) {
+                    bins.entry(segment_id).or_default().push(entry);
+                }
+            }
+            Self::validate_components(&metadata, &parents, &bins)?;
+            Ok(Self::from_components(metadata, parents, bins))
+        }
+    }
+
+    impl<
+            'a,
+            T: IndexSortable + Default + ArrowStorage + ParentIDMut + 'a,
+            P: IndexSortable + Default + ArrowStorage + SortIDMut + 'a,
+        > SearchIndex<T, P>
+    {
+        /// Read an index written with [`SearchIndex::write_parquet`], merging in any
+        /// segments added with [`SearchIndex::append_parquet`]. Segments that were
+        /// appended to a different index are rejected.
+        pub fn read_parquet<D: AsRef<std::path::Path>>(
+            directory: &D,
+        ) -> Result<Self, MassFragmentIndexError> {
+            let mut this = Self::read(directory)?;
+            let segments = appended_segments(directory.as_ref())?;
+            if segments.is_empty() {
+                return Ok(this);
+            }
+            let build_id = Self::read_index_metadata(directory.as_ref())?.build_id;
+            let sort_type = this.sort_type;
+            for segment in segments {
+                let base_build_id = Self::read_index_metadata(&segment)?.base_build_id;
+                if base_build_id != build_id {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{} was appended to the index with build id {base_build_id}, not {build_id}",
+                            segment.display()
+                        ),
+                    )
+                    .into());
+                }
+                this.merge(Self::read(&segment)?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+            this.sort(if sort_type == SortType::Unsorted {
+                SortType::ByParentId
+            } else {
+                sort_type
+            });
+            Ok(this)
+        }
+
+        /// Add this index's parents and entries to the index already written in `directory`
+        /// without rewriting it. They are stored as a separate segment that
+        /// [`SearchIndex::read_parquet`] merges with the rest, re-numbering parents by mass.
+        ///
+        /// [`SearchIndexOnDisk`](crate::storage::SearchIndexOnDisk) only sees the original index.
+        pub fn append_parquet<D: AsRef<std::path::Path>>(
+            &'a self,
+            directory: &D,
+            compression_level: Option<parquet::basic::Compression>,
+        ) -> Result<(), MassFragmentIndexError> {
+            let base_build_id = Self::read_index_metadata(directory.as_ref())?.build_id;
+            let segment = next_appended_segment(directory.as_ref())?;
+            std::fs::create_dir(&segment)?;
+            self.write_parquet(&segment, compression_level)?;
+
+            let mut metadata = Self::read_index_metadata(&segment)?;
+            metadata.base_build_id = base_build_id;
+            write_metadata_into(
+                metadata,
+                io::BufWriter::new(std::fs::File::create(
+                    segment.join(IndexMetadata::archive_name()),
+                )?),
+            )
+        }
+    }
+
+    impl<
+            'a,
+            T: IndexSortable + Default + ArrowStorage + 'a + Clone,
+            P: IndexSortable + Default + ArrowStorage + 'a,
+        > SearchIndex<T, P>
+    {
+        pub fn write_banded_parquet<D: AsRef<std::path::Path>>(
+            &'a self,
+            directory: &D,
+            bin_width: MassType,
+            compression_level: Option<parquet::basic::Compression>,
+        ) -> Result<(), MassFragmentIndexError> {
+            self.write_split(directory, bin_width, compression_level.map(IndexCompression::from))
+        }
+
+        pub fn read_banded_parquet<D: AsRef<std::path::Path>>(directory: &D) -> Result<Self, MassFragmentIndexError> {
+            Self::read_split(directory)
+        }
+
+        /// Estimate the number of bytes [`SearchIndex::write_parquet`] would write with
+        /// `compression_level`. A sample of the parents and entries is written in memory
+        /// and the size per record extrapolated to the whole index, so the estimate is
+        /// rough, but it reflects how well the data compresses.
+        pub fn estimated_parquet_size(
+            &'a self,
+            compression_level: Option<parquet::basic::Compression>,
+        ) -> Result<usize, MassFragmentIndexError>
+        where
+            P: Clone,
+        {
+            const SAMPLE_SIZE: usize = 4096;
+            fn sample<'b, X: Clone + 'b>(items: impl Iterator<Item = &'b X>, len: usize) -> Vec<X> {
+                items.step_by((len / SAMPLE_SIZE).max(1)).take(SAMPLE_SIZE).cloned().collect()
+            }
+            // The size of the file with no records plus the sample's size per record
+            fn extrapolate(empty: usize, sampled: usize, sample_len: usize, len: usize) -> usize {
+                if sample_len == 0 {
+                    return empty;
+                }
+                let per_record = sampled.saturating_sub(empty) as f64 / sample_len as f64;
+                empty + (per_record * len as f64).round() as usize
+            }
+
+            let compression = compression_level
+                .map(IndexCompression::from)
+                .unwrap_or_default();
+
+            let mut metadata = Vec::new();
+            write_metadata_into(self.to_metadata(), &mut metadata)?;
+
+            let parents = sample(self.parents.iter(), self.num_parents());
+            let mut empty = Vec::new();
+            write_parents_into::<P, _>(&[], &mut empty, &compression.parents, None)?;
+            let mut sampled = Vec::new();
+            write_parents_into(&parents, &mut sampled, &compression.parents, None)?;
+            let parents_size =
+                extrapolate(empty.len(), sampled.len(), parents.len(), self.num_parents());
+
+            let entries = sample(self.iter_entries(), self.num_entries());
+            let mut empty = Vec::new();
+            write_entries_into::<T, _>(std::iter::empty(), &mut empty, &compression.entries, None, None)?;
+            let mut sampled = Vec::new();
+            write_entries_into(
+                std::iter::once(entries.as_slice()),
+                &mut sampled,
+                &compression.entries,
+                None,
+                None,
+            )?;
+            let entries_size =
+                extrapolate(empty.len(), sampled.len(), entries.len(), self.num_entries());
+
+            Ok(metadata.len() + parents_size + entries_size)
+        }
+
+        /// Write as with [`SearchIndex::write_banded_parquet`], but give each band its own
+        /// entries file and write the bands concurrently.
+        #[cfg(feature = "parallelism")]
+        pub fn par_write_banded_parquet<D: AsRef<std::path::Path>>(
+            &'a self,
+            directory: &D,
+            bin_width: MassType,
+            compression_level: Option<parquet::basic::Compression>,
+        ) -> Result<(), MassFragmentIndexError>
+        where
+            T: Sync,
+            P: Sync,
+        {
+            self.par_write_split(directory, bin_width, compression_level.map(IndexCompression::from))
+        }
+    }
+}
+
+pub struct SearchIndexBinIter<'a, T: IndexSortable + Default, P: IndexSortable + Default> {
+    index: &'a SearchIndex<T, P>,
+    pub query: MassType,
+    pub error_tolerance: Tolerance,
+    pub low_bin: usize,
+    pub high_bin: usize,
+    pub current_bin: usize,
+}
+
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> ExactSizeIterator
+    for SearchIndexBinIter<'a, T, P>
+{
+    fn len(&self) -> usize {
+        self.high_bin.saturating_sub(self.low_bin)
+    }
+}
+
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> FusedIterator
+    for SearchIndexBinIter<'a, T, P>
+{
+}
+
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> Iterator
+    for SearchIndexBinIter<'a, T, P>
+{
+    type Item = &'a IndexBin<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_bin()
+    }
+}
+
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> SearchIndexBinIter<'a, T, P> {
+    pub fn new(index: &'a SearchIndex<T, P>, query: MassType, error_tolerance: Tolerance) -> Self {
+        let (low_mass, high_mass) = error_tolerance.bounds(query);
+        let low_bin = index.bin_for_mass(low_mass);
+        let high_bin = (index.bin_for_mass(high_mass) + 1).min(index.bins.len());
+        Self {
+            index,
+            query,
+            error_tolerance,
+            low_bin,
+            high_bin,
+            current_bin: low_bin,
+        }
+    }
+
+    #[inline(always)]
+    fn next_bin(&mut self) -> Option<&'a IndexBin<T>> {
+        if self.current_bin < self.high_bin {
+            let bin = unsafe { self.index.bins.get_unchecked(self.current_bin) };
+            self.current_bin += 1;
+            Some(bin)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct SearchIndexSearchIter<'a, T: IndexSortable + Default, P: IndexSortable + Default> {
+    pub query: MassType,
+    pub error_tolerance: Tolerance,
+    pub parent_range: Interval,
+    bin_iter: SearchIndexBinIter<'a, T, P>,
+    item_iter: Option<ParentSortedIndexBinSearchIter<'a, T>>,
+}
+
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> Iterator
+    for SearchIndexSearchIter<'a, T, P>
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry()
+    }
+}
+
+impl<'a, T: IndexSortable + Default, P: IndexSortable + Default> SearchIndexSearchIter<'a, T, P> {
+    pub fn new(
+        index: &'a SearchIndex<T, P>,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_range: Interval,
+    ) -> Self {
+        let bin_iter = SearchIndexBinIter::new(index, query, error_tolerance);
+        let mut this = Self {
+            query,
+            error_tolerance,
+            parent_range,
+            bin_iter,
+            item_iter: None,
+        };
+        this.next_bin_iterator();
+        this
+    }
+
+    #[inline(always)]
+    fn next_bin_iterator(&mut self) -> bool {
+        self.item_iter = self.bin_iter.next().map(|bin| {
+            ParentSortedIndexBinSearchIter::new(
+                bin,
+                self.parent_range,
+                self.query,
+                self.error_tolerance,
+            )
+        });
+        self.item_iter.is_some()
+    }
+
+    #[inline(always)]
+    fn next_entry(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(t) = self.item_iter.as_mut().and_then(|it| it.next()) {
+                return Some(t);
+            } else {
+                if !self.next_bin_iterator() {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parent::Spectrum;
+    use crate::peak::DeconvolutedPeak;
+    use crate::sort::PROTON;
+
+    #[test]
+    fn test_build() {
+        let spectra = vec![
+            Spectrum::new(2300.0, 2, 0, 0, 0),
+            Spectrum::new(2301.0, 2, 0, 1, 1),
+            Spectrum::new(2401.0, 2, 1, 0, 2),
+            Spectrum::new(4100.0, 4, 0, 2, 3),
+        ];
+        let mut parent_list = IndexBin::new(spectra, SortType::Unsorted, 0.0, 0.0);
+        parent_list.sort(SortType::ByMass);
+
+        let peaks = vec![
+            DeconvolutedPeak::new(251.5, 1, 0.0, 0),
+            DeconvolutedPeak::new(251.5, 1, 0.0, 2),
+            DeconvolutedPeak::new(251.6, 1, 0.0, 1),
+            DeconvolutedPeak::new(303.7, 1, 0.0, 0),
+            DeconvolutedPeak::new(501.2, 1, 0.0, 1),
+        ];
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        for peak in peaks {
+            index.add(peak);
+        }
+
+        let idx = index.bin_for_mass(251.5);
+        assert!(idx == 2515);
+    }
+
+    #[test]
+    fn test_da_tolerance() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(250.0, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(250.01, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(250.03, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+
+        // 20 PPM and 0.02 Da are the same width at 1000 Da, but not at 250 Da
+        let da_hits: Vec<_> = index.search(250.0, Tolerance::Da(0.02), None).collect();
+        let ppm_hits: Vec<_> = index.search(250.0, Tolerance::PPM(20.0), None).collect();
+        assert_eq!(da_hits.len(), 2);
+        assert_eq!(ppm_hits.len(), 1);
+        assert!(da_hits.iter().all(|p| (p.mass - 250.0).abs() <= 0.02));
+    }
+
+    #[test]
+    fn test_asymmetric_tolerance() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(1000.008, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(999.992, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::Asymmetric { low_ppm: 5.0, high_ppm: 10.0 };
+        let (lo, hi) = tol.bounds(1000.0);
+        assert!(lo > 999.99 && hi > 1000.009);
+
+        let hits: Vec<_> = index.search(1000.0, tol, None).collect();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].mass > 1000.0);
+
+        let hits: Vec<_> = index.search(1000.0, Tolerance::PPM(7.5), None).collect();
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn test_polynomial_tolerance() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(200.0009, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(1500.015, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+
+        // 2 PPM at 0 Da, widening by 1 PPM every 100 Da
+        let calibrated = Tolerance::Polynomial([2.0, 0.01, 0.0]);
+        let flat = Tolerance::PPM(5.0);
+
+        assert!(index.search(200.0, calibrated, None).next().is_none());
+        assert_eq!(index.search(200.0, flat, None).count(), 1);
+
+        assert_eq!(index.search(1500.0, calibrated, None).count(), 1);
+        assert!(index.search(1500.0, flat, None).next().is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        index.add_parent(Spectrum::new(500.0, 2, 0, 0, 0));
+        index.add_parent(Spectrum::new(800.0, 2, 0, 1, 1));
+        index.add(DeconvolutedPeak::new(250.0, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(300.0, 1, 0.0, 1));
+        index.sort(SortType::ByParentId);
+        assert_eq!(index.validate(), Ok(()));
+
+        let mut gapped = SearchIndex::<DeconvolutedPeak, Spectrum>::empty(10, 2000.0);
+        gapped.add_parent(Spectrum::new(500.0, 2, 0, 0, 0));
+        gapped.add_parent(Spectrum::new(800.0, 2, 0, 1, 2));
+        assert_eq!(gapped.validate(), Err(IndexError::NonContiguousParentId(1, 2)));
+
+        index.add(DeconvolutedPeak::new(350.0, 1, 0.0, 2));
+        assert_eq!(index.validate(), Err(IndexError::UnknownParentId(2, 2)));
+
+        let mut negative = SearchIndex::<DeconvolutedPeak, Spectrum>::empty(10, 2000.0);
+        negative.add_parent(Spectrum::new(500.0, 2, 0, 0, 0));
+        negative.add(DeconvolutedPeak::new(-1.0, 1, 0.0, 0));
+        assert_eq!(negative.validate(), Err(IndexError::InvalidMass(-1.0)));
+    }
+
+    #[test]
+    fn test_search_mz() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(1000.0, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(1500.0, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        let mz = 1000.0 + PROTON;
+        let by_mz: Vec<_> = index.search_mz(mz, 1, tol, None).collect();
+        let by_mass: Vec<_> = index.search(mz - PROTON, tol, None).collect();
+        assert_eq!(by_mz, by_mass);
+        assert_eq!(by_mz.len(), 1);
+
+        let mz = (1500.0 + 2.0 * PROTON) / 2.0;
+        assert_eq!(index.search_mz(mz, 2, tol, None).next().unwrap().mass, 1500.0);
+
+        let mz = 1500.0 - PROTON;
+        assert_eq!(index.search_mz(mz, -1, tol, None).count(), 1);
+    }
+
+    #[test]
+    fn test_search_while() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        for i in 0..4 {
+            index.add_parent(Spectrum::new(2300.0 + i as MassType, 2, 0, 0, 0));
+        }
+        for i in 0..20u32 {
+            index.add(DeconvolutedPeak::new(1000.0 + i as MassType * 0.01, 1, 0.0, i % 4));
+        }
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::Da(0.5);
+        assert_eq!(index.search(1000.0, tol, None).count(), 20);
+        for limit in [0, 1, 7, 20] {
+            let mut seen = 0;
+            let hits: Vec<_> = index
+                .search_while(1000.0, tol, None, |_| {
+                    seen += 1;
+                    seen <= limit
+                })
+                .collect();
+            assert_eq!(hits.len(), limit);
+            let expected: Vec<_> = index.search(1000.0, tol, None).take(limit).collect();
+            assert_eq!(hits, expected);
+        }
+
+        let hits: Vec<_> = index
+            .search_while(1000.0, tol, Some(Interval::new(1, 2)), |e| e.mass < 1000.1)
+            .collect();
+        assert!(!hits.is_empty());
+        assert!(hits.iter().all(|e| e.scan_ref == 1 && e.mass < 1000.1));
+    }
+
+    #[test]
+    fn test_iter_parents_sorted() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        for mass in [2300.0, 1800.0, 2600.0, 2300.0, 1900.0] {
+            index.add_parent(Spectrum::new(mass, 2, 0, 0, 0));
+        }
+
+        let sorted: Vec<_> = index.iter_parents_sorted().collect();
+        assert_eq!(sorted.len(), index.num_parents());
+        let mut ids: Vec<_> = sorted.iter().map(|(id, _)| *id).collect();
+        assert!(sorted.windows(2).all(|w| w[0].1.mass() <= w[1].1.mass()));
+        for (id, parent) in sorted.iter() {
+            assert!(std::ptr::eq(*parent, &index.parents[*id as usize]));
+        }
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids, (0..index.num_parents() as ParentID).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_from_parents_and_fragments() {
+        use crate::fragment::{Fragment, FragmentSeries};
+        use crate::parent::Peptide;
+
+        let data: Vec<(Peptide, Vec<Fragment>)> = [900.0, 500.0, 700.0]
+            .into_iter()
+            .map(|mass| {
+                let peptide = Peptide::new(mass, 99, 0, 0, String::new());
+                let fragments = (1..4)
+                    .map(|i| Fragment::new(mass / 4.0 * i as MassType, 99, FragmentSeries::b, i))
+                    .collect();
+                (peptide, fragments)
+            })
+            .collect();
+
+        let mut manual: SearchIndex<Fragment, Peptide> = SearchIndex::empty(10, 1000.0);
+        for (parent, entries) in data.clone() {
+            manual.add_parent(parent);
+            entries.into_iter().for_each(|e| {
+                manual.add(e);
+            });
+        }
+
+        let mut index = SearchIndex::from_parents_and_fragments(data, 10, 1000.0);
+        assert_eq!(index.num_entries(), manual.num_entries());
+        assert_eq!(index.num_parents(), manual.num_parents());
+        assert_eq!(index.sort_type(), SortType::Unsorted);
+        index.sort(SortType::ByParentId);
+        assert_eq!(index.validate(), Ok(()));
+
+        let masses: Vec<_> = index.parents.iter().map(|p| p.mass).collect();
+        assert_eq!(masses, vec![500.0, 700.0, 900.0]);
+        for (i, parent) in index.parents.iter().enumerate() {
+            let interval = index.parents_for(parent.mass, Tolerance::PPM(5.0));
+            assert_eq!(interval, Interval::new(i, i + 1));
+            let hit = index
+                .search(parent.mass / 2.0, Tolerance::PPM(5.0), Some(interval))
+                .next()
+                .unwrap();
+            assert_eq!(hit.parent_id as usize, i);
+        }
+    }
+
+    #[test]
+    fn test_view_shared_across_threads() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        for i in 0..4 {
+            index.add_parent(Spectrum::new(2000.0 + i as MassType * 100.0, 2, 0, 0, 0));
+        }
+        for i in 0..400u32 {
+            index.add(DeconvolutedPeak::new(100.0 + i as MassType * 4.5, 1, 0.0, i % 4));
+        }
+        index.sort(SortType::ByParentId);
+
+        fn assert_sync<S: Sync + Send>(_: &S) {}
+        let view = index.view();
+        assert_sync(&view);
+        assert_eq!(view.num_entries(), 400);
+        assert_eq!(view.num_parents(), 4);
+        assert_eq!(view.iter_entries().count(), 400);
+
+        let tol = Tolerance::Da(0.1);
+        let counts: Vec<usize> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4u32)
+                .map(|t| {
+                    scope.spawn(move || {
+                        let interval = view.parents_for(2000.0 + t as MassType * 100.0, tol);
+                        (0..400)
+                            .map(|i| view.search(100.0 + i as MassType * 4.5, tol, Some(interval)).count())
+                            .sum()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(counts, vec![100; 4]);
+
+        let parent = view.get_parent(2).unwrap();
+        assert_eq!(parent.precursor_mass, 2200.0);
+        assert!(view.get_parent(4).is_none());
+        assert_eq!(view.iter_parents().count(), 4);
+    }
+
+    #[test]
+    fn test_flat_layout() {
+        let build = |mut index: SearchIndex<DeconvolutedPeak, Spectrum>| {
+            for i in 0..20 {
+                index.add_parent(Spectrum::new(2000.0 + i as MassType * 10.0, 2, 0, 0, 0));
+            }
+            for i in 0..300u32 {
+                let mass = 100.0 + (i as MassType * 37.1) % 1800.0;
+                index.add(DeconvolutedPeak::new(mass, 1, 0.0, i % 20));
+            }
+            index.sort(SortType::ByParentId);
+            index
+        };
+        let binned = build(SearchIndex::empty(10, 2000.0));
+        let flat = build(SearchIndex::flat(2000.0));
+        assert!(flat.is_flat());
+        assert!(!binned.is_flat());
+        assert_eq!(flat.num_bins(), 1);
+        assert_eq!(flat.total_bins_for_mass(), 1);
+        assert_eq!(flat.num_entries(), binned.num_entries());
+        assert_eq!(flat.sort_type(), SortType::ByMass);
+
+        let key = |e: &&DeconvolutedPeak| (e.scan_ref, e.mass.to_bits());
+        let tol = Tolerance::Da(0.5);
+        for query in [100.0, 137.1, 211.3, 990.0, 1899.9, 2500.0] {
+            for interval in [None, Some(Interval::new(3, 11))] {
+                let mut expected: Vec<_> = binned.search(query, tol, interval).collect();
+                let mut found: Vec<_> = flat.search(query, tol, interval).collect();
+                expected.sort_by_key(key);
+                found.sort_by_key(key);
+                assert_eq!(found, expected);
+                assert_eq!(flat.count_matches(query, tol, interval), expected.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_mz_with_adduct() {
+        const SODIUM: MassType = 22.989_221;
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(1000.0, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(1500.0, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+
+        let mz = (1500.0 + 2.0 * SODIUM) / 2.0;
+        assert!((neutral_mass_with_adduct(mz, 2, SODIUM) - 1500.0).abs() < 1e-3);
+        assert_eq!(neutral_mass_with_adduct(mz, 2, PROTON), neutral_mass(mz, 2));
+
+        let tol = Tolerance::PPM(10.0);
+        let hits: Vec<_> = index.search_mz_with_adduct(mz, 2, SODIUM, tol, None).collect();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].mass, 1500.0);
+        assert_eq!(index.search_mz(mz, 2, tol, None).count(), 0);
+
+        let mz = 1000.0 + SODIUM;
+        assert_eq!(
+            index.search_mz_with_adduct(mz, 1, SODIUM, tol, None).next().unwrap().mass,
+            1000.0
+        );
+        assert_eq!(
+            index.search_mz_with_adduct(mz, 1, PROTON, tol, None).count(),
+            index.search_mz(mz, 1, tol, None).count()
+        );
+    }
+
+    #[test]
+    fn test_search_with_precursor() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        for mass in [1800.0, 2300.0, 2300.01, 2600.0] {
+            index.add_parent(Spectrum::new(mass, 2, 0, 0, 0));
+        }
+        for i in 0..4 {
+            index.add(DeconvolutedPeak::new(1000.0, 1, 0.0, i));
+        }
+        index.sort(SortType::ByParentId);
+
+        let frag_tol = Tolerance::PPM(10.0);
+        let prec_tol = Tolerance::PPM(10.0);
+        let hits: Vec<_> = index
+            .search_with_precursor(1000.0, frag_tol, 2300.005, prec_tol)
+            .collect();
+
+        let parent_interval = index.parents_for(2300.005, prec_tol);
+        let expected: Vec<_> = index
+            .search(1000.0, frag_tol, Some(parent_interval))
+            .collect();
+        assert_eq!(hits, expected);
+        assert_eq!(hits.len(), 2);
+        assert!(hits
+            .iter()
+            .all(|hit| prec_tol.test(index.parents[hit.scan_ref as usize].precursor_mass, 2300.005)));
+
+        assert_eq!(index.search_with_precursor(1000.0, frag_tol, 2000.0, prec_tol).count(), 0);
+    }
+
+    #[test]
+    fn test_nearest_parent() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        index.sort(SortType::ByParentId);
+        assert!(index.nearest_parent(1000.0).is_none());
+        for mass in [1000.0, 1010.0, 1030.0] {
+            index.add_parent(Spectrum::new(mass, 2, 0, 0, 0));
+        }
+        index.sort(SortType::ByParentId);
+
+        let (id, parent, delta) = index.nearest_parent(1008.0).unwrap();
+        assert_eq!((id, parent.precursor_mass, delta), (1, 1010.0, 2.0));
+        let (id, _, delta) = index.nearest_parent(1019.0).unwrap();
+        assert_eq!((id, delta), (1, 9.0));
+        let (id, _, delta) = index.nearest_parent(1021.0).unwrap();
+        assert_eq!((id, delta), (2, 9.0));
+        assert_eq!(index.nearest_parent(1020.0).unwrap().0, 1);
+        assert_eq!(index.nearest_parent(0.0).unwrap().0, 0);
+        assert_eq!(index.nearest_parent(5000.0).unwrap().2, 3970.0);
+    }
+
+    #[test]
+    fn test_search_instrumented() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        for mass in [999.6, 999.9, 1000.0, 1000.05, 1000.3, 1000.6] {
+            index.add(DeconvolutedPeak::new(mass, 1, 0.0, 0));
+        }
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::Da(0.35);
+        let mut scanned = Vec::new();
+        let hits = index.search_instrumented(1000.0, tol, None, &mut |bin, size| {
+            scanned.push((bin, size))
+        });
+        let expected: Vec<_> = index.search(1000.0, tol, None).collect();
+        assert_eq!(hits, expected);
+        assert_eq!(hits.len(), 4);
+
+        let (low, high) = tol.bounds(1000.0);
+        let bins: Vec<usize> = scanned.iter().map(|(bin, _)| *bin).collect();
+        assert_eq!(bins, (index.bin_for_mass(low)..=index.bin_for_mass(high)).collect::<Vec<_>>());
+        assert!(scanned.iter().all(|(bin, size)| index.bins[*bin].len() == *size));
+        assert_eq!(scanned.iter().map(|(_, size)| size).sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn test_search_owned() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        for i in 0..3 {
+            index.add_parent(Spectrum::new(2300.0 + i as MassType, 2, 0, 0, 0));
+            index.add(DeconvolutedPeak::new(1000.0, 1, 0.0, i));
+            index.add(DeconvolutedPeak::new(1200.0, 1, 0.0, i));
+        }
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        let borrowed: Vec<_> = index.search(1000.0, tol, Some(Interval::new(0, 2))).collect();
+        let owned = index.search_owned(1000.0, tol, Some(Interval::new(0, 2)));
+        assert_eq!(owned.len(), 2);
+        assert!(owned.iter().eq(borrowed));
+    }
+
+    #[test]
+    fn test_search_grouped() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        for i in 0..3 {
+            index.add_parent(Spectrum::new(2300.0 + i as MassType, 2, 0, 0, 0));
+        }
+        for i in 0..3 {
+            index.add(DeconvolutedPeak::new(1000.0, 1, 0.0, i));
+            index.add(DeconvolutedPeak::new(1000.002, 1, 0.0, i));
+        }
+        index.add(DeconvolutedPeak::new(1000.001, 1, 0.0, 1));
+        index.add(DeconvolutedPeak::new(1200.0, 1, 0.0, 2));
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        let groups = index.search_grouped(1000.0, tol, None);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[&0].len(), 2);
+        assert_eq!(groups[&1].len(), 3);
+        assert_eq!(groups[&2].len(), 2);
+        for (parent_id, entries) in groups.iter() {
+            assert!(entries.iter().all(|e| e.scan_ref == *parent_id));
+        }
+
+        let mut flat: Vec<_> = index.search(1000.0, tol, None).collect();
+        let mut grouped: Vec<_> = groups.into_values().flatten().collect();
+        flat.sort_by(|a, b| (a.scan_ref, a.mass).partial_cmp(&(b.scan_ref, b.mass)).unwrap());
+        grouped.sort_by(|a, b| (a.scan_ref, a.mass).partial_cmp(&(b.scan_ref, b.mass)).unwrap());
+        assert_eq!(flat, grouped);
+
+        let groups = index.search_grouped(1000.0, tol, Some(Interval::new(1, 2)));
+        assert_eq!(groups.keys().copied().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_search_with_error() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(1000.005, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(999.996, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(1000.5, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        let hits: Vec<_> = index.search_with_error(1000.0, tol, None).collect();
+        assert_eq!(hits.len(), 2);
+        for hit in hits {
+            assert!(hit.ppm_error.abs() <= 10.0);
+            assert_eq!(hit.ppm_error > 0.0, hit.entry.mass > 1000.0);
+        }
+    }
+
+    #[test]
+    fn test_search_batch() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add_parent(Spectrum::new(2301.0, 2, 0, 1, 1));
+        for (mass, scan_ref) in [(251.5, 0), (251.5, 1), (251.6, 1), (303.7, 0), (501.2, 1)] {
+            index.add(DeconvolutedPeak::new(mass, 1, 0.0, scan_ref));
+        }
+        index.sort(SortType::ByParentId);
+
+        let queries = [501.2, 251.5, 800.0, 303.7, 251.6];
+        let tol = Tolerance::PPM(10.0);
+        let batch = index.search_batch(&queries, tol, None);
+        assert_eq!(batch.len(), queries.len());
+        for (query, hits) in queries.iter().zip(batch.iter()) {
+            let expected: Vec<_> = index.search(*query, tol, None).collect();
+            assert_eq!(hits, &expected);
+        }
+        assert!(batch[2].is_empty());
+        assert_eq!(batch[1].len(), 2);
+    }
+
+    #[cfg(feature = "parallelism")]
+    #[test]
+    fn test_par_search_batch() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add_parent(Spectrum::new(2301.0, 2, 0, 1, 1));
+        for i in 0..500 {
+            index.add(DeconvolutedPeak::new(100.0 + i as MassType * 1.5, 1, 0.0, i % 2));
+        }
+        index.sort(SortType::ByParentId);
+
+        let queries: Vec<_> = (0..1000).map(|i| 850.0 - i as MassType * 0.75).collect();
+        let tol = Tolerance::Da(0.5);
+        let serial = index.search_batch(&queries, tol, None);
+        let parallel = index.par_search_batch(&queries, tol, None);
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn test_iter_entries() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add_parent(Spectrum::new(2301.0, 2, 0, 1, 1));
+        for (mass, scan_ref) in [(501.2, 1), (251.6, 0), (251.5, 1), (303.7, 0), (251.55, 0)] {
+            index.add(DeconvolutedPeak::new(mass, 1, 0.0, scan_ref));
+        }
+        index.sort(SortType::ByMass);
+
+        let masses: Vec<_> = index.iter_entries().map(|p| p.mass).collect();
+        assert_eq!(masses, vec![251.5, 251.55, 251.6, 303.7, 501.2]);
+
+        let masses: Vec<_> = index
+            .iter_entries_in_range(251.55, 400.0)
+            .map(|p| p.mass)
+            .collect();
+        assert_eq!(masses, vec![251.55, 251.6, 303.7]);
+        assert_eq!(index.iter_entries_in_range(400.0, 300.0).count(), 0);
+    }
+
+    #[test]
+    fn test_mass_sorted_search() {
+        let build = |ordering| {
+            let mut index: SearchIndex<DeconvolutedPeak, Spectrum> =
+                SearchIndex::empty(10, 1000.0);
+            for i in 0..4 {
+                index.add_parent(Spectrum::new(2300.0 + i as MassType, 2, 0, i, i));
+            }
+            for i in 0..400u32 {
+                let mass = 250.0 + (i % 97) as MassType * 0.013 + (i / 97) as MassType * 0.001;
+                index.add(DeconvolutedPeak::new(mass, 1, 0.0, i % 4));
+            }
+            index.sort(ordering);
+            index
+        };
+        let by_mass = build(SortType::ByMass);
+        let by_parent = build(SortType::ByParentId);
+
+        let key = |p: &&DeconvolutedPeak| (p.mass.to_bits(), p.scan_ref);
+        for query in [250.0, 250.5, 250.91, 251.3] {
+            for parent_interval in [None, Some(Interval::new(1, 3))] {
+                let tol = Tolerance::PPM(20.0);
+                let mut a: Vec<_> = by_mass.search(query, tol, parent_interval).collect();
+                let mut b: Vec<_> = by_parent.search(query, tol, parent_interval).collect();
+                a.sort_by_key(key);
+                b.sort_by_key(key);
+                assert_eq!(a, b);
+            }
+        }
+        let hits = by_mass.search(250.5, Tolerance::Da(0.05), None).count();
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn test_sort_type_tracking() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        assert_eq!(index.sort_type(), SortType::Unsorted);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(251.5, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+        assert_eq!(index.sort_type(), SortType::ByParentId);
+
+        index.add(DeconvolutedPeak::new(303.7, 1, 0.0, 0));
+        assert_eq!(index.sort_type(), SortType::Unsorted);
+
+        index.sort(SortType::ByMass);
+        assert_eq!(index.sort_type(), SortType::ByMass);
+        index.add_parent(Spectrum::new(2301.0, 2, 0, 1, 1));
+        assert_eq!(index.sort_type(), SortType::Unsorted);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "must be sorted")]
+    fn test_unsorted_search() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(251.5, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+        index.add(DeconvolutedPeak::new(251.52, 1, 0.0, 0));
+        let _ = index.search(251.5, Tolerance::PPM(10.0), None).count();
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut left: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        left.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        left.add_parent(Spectrum::new(2401.0, 2, 0, 1, 1));
+        left.add(DeconvolutedPeak::new(251.5, 1, 0.0, 0));
+        left.add(DeconvolutedPeak::new(303.7, 1, 0.0, 1));
+        left.sort(SortType::ByParentId);
+
+        let mut right: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1500.0);
+        right.add_parent(Spectrum::new(2350.0, 2, 1, 0, 0));
+        right.add(DeconvolutedPeak::new(251.5, 1, 0.0, 0));
+        right.add(DeconvolutedPeak::new(1200.0, 1, 0.0, 0));
+        right.sort(SortType::ByParentId);
+
+        left.merge(right).unwrap();
+        assert_eq!(left.sort_type(), SortType::Unsorted);
+        left.sort(SortType::ByParentId);
+
+        assert_eq!(left.parents.len(), 3);
+        assert_eq!(left.num_entries(), 4);
+        assert_eq!(left.max_item_mass(), 1500.0);
+        let masses: Vec<_> = left.parents.iter().map(|p| p.precursor_mass).collect();
+        assert_eq!(masses, vec![2300.0, 2350.0, 2401.0]);
+        for (i, p) in left.parents.iter().enumerate() {
+            assert_eq!(p.sort_id as usize, i);
+        }
+
+        let tol = Tolerance::PPM(10.0);
+        let hits: Vec<_> = left.search(251.5, tol, None).map(|p| p.scan_ref).collect();
+        assert_eq!(hits, vec![0, 1]);
+        let hits: Vec<_> = left.search(303.7, tol, None).map(|p| p.scan_ref).collect();
+        assert_eq!(hits, vec![2]);
+        let hits: Vec<_> = left.search(1200.0, tol, None).map(|p| p.scan_ref).collect();
+        assert_eq!(hits, vec![1]);
+
+        let mut bad: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(100, 1000.0);
+        bad.sort(SortType::ByParentId);
+        assert_eq!(
+            left.merge(bad),
+            Err(IndexMergeError::BinsPerDaltonMismatch(10, 100))
+        );
+    }
+
+    #[cfg(feature = "high_precision")]
+    #[test]
+    fn test_high_precision_search() {
+        // At 5000 Da an `f32` can only resolve ~0.0005 Da, so these two masses would round
+        // to values further apart than the tolerance allows.
+        let (query, stored) = (5000.00022, 5000.00026);
+        let tol = Tolerance::PPM(0.05);
+        assert!((query as f32 - stored as f32).abs() as MassType > 0.05 * 5000.0 / 1e6);
+
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 6000.0);
+        index.add_parent(Spectrum::new(7000.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(stored, 1, 0.0, 0));
+        index.sort(SortType::ByParentId);
+        assert_eq!(index.search(query, tol, None).count(), 1);
+    }
+
+    #[test]
+    fn test_remove_parent() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add_parent(Spectrum::new(2301.0, 2, 0, 1, 1));
+        index.add_parent(Spectrum::new(2401.0, 2, 1, 0, 2));
+        for (mass, scan_ref) in [(251.5, 0), (251.5, 1), (251.6, 1), (303.7, 2), (501.2, 1)] {
+            index.add(DeconvolutedPeak::new(mass, 1, 0.0, scan_ref));
+        }
+        index.sort(SortType::ByParentId);
+        assert_eq!(index.num_entries(), 5);
+
+        let removed = index.remove_parent(1).unwrap();
+        assert_eq!(removed.precursor_mass, 2301.0);
+        assert_eq!(index.sort_type(), SortType::Unsorted);
+        index.sort(SortType::ByParentId);
+
+        assert_eq!(index.num_entries(), 2);
+        assert_eq!(index.parents.len(), 2);
+        assert_eq!(index.parents[1].sort_id, 1);
+
+        let tol = Tolerance::PPM(10.0);
+        let hits: Vec<_> = index.search(251.5, tol, None).map(|p| p.scan_ref).collect();
+        assert_eq!(hits, vec![0]);
+        assert_eq!(index.search(501.2, tol, None).count(), 0);
+        let hits: Vec<_> = index.search(303.7, tol, None).map(|p| p.scan_ref).collect();
+        assert_eq!(hits, vec![1]);
+
+        assert!(index.remove_parent(5).is_none());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add_parent(Spectrum::new(2301.0, 2, 0, 1, 1));
+        let peaks = [(251.5, 0), (251.5, 1), (251.6, 1), (303.7, 0), (501.2, 1)];
+        for (mass, scan_ref) in peaks.iter().chain(peaks[..3].iter()) {
+            index.add(DeconvolutedPeak::new(*mass, 1, 0.0, *scan_ref));
+        }
+        index.add(DeconvolutedPeak::new(251.5, 2, 0.0, 0));
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        let before: Vec<_> = index.search(251.5, tol, None).cloned().collect();
+        assert_eq!(before.len(), 5);
+
+        assert_eq!(index.dedup(), 3);
+        assert_eq!(index.num_entries(), 6);
+        let mut after: Vec<_> = index.search(251.5, tol, None).cloned().collect();
+        after.sort_by_key(|a| (a.scan_ref, a.charge));
+        assert_eq!(
+            after,
+            vec![
+                DeconvolutedPeak::new(251.5, 1, 0.0, 0),
+                DeconvolutedPeak::new(251.5, 2, 0.0, 0),
+                DeconvolutedPeak::new(251.5, 1, 0.0, 1),
+            ]
+        );
+        assert_eq!(index.dedup(), 0);
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        assert!(index.is_empty());
+        assert_eq!(index.num_parents(), 0);
+
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        assert!(!index.is_empty());
+        assert_eq!(index.num_parents(), 1);
+        assert_eq!(index.num_entries(), 0);
+
+        index.add(DeconvolutedPeak::new(251.5, 1, 0.0, 0));
+        assert_eq!(index.num_entries(), 1);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        for i in 0..100 {
+            index.add(DeconvolutedPeak::new(250.0 + i as MassType * 0.001, 1, 0.0, 0));
+        }
+        index.bins.iter_mut().for_each(|bin| bin.entries.reserve(1000));
+        let before: usize = index.iter_bins().map(|b| b.capacity()).sum();
+
+        index.shrink_to_fit();
+        let after: usize = index.iter_bins().map(|b| b.capacity()).sum();
+        assert!(after < before);
+        assert_eq!(after, index.num_entries());
+        assert_eq!(index.num_entries(), 100);
+    }
+
+    #[test]
+    fn test_mass_sort_breaks_ties() {
+        let peaks = [
+            DeconvolutedPeak::new(500.0, 2, 10.0, 1),
+            DeconvolutedPeak::new(500.0, 1, 30.0, 1),
+            DeconvolutedPeak::new(500.0, 1, 20.0, 0),
+            DeconvolutedPeak::new(500.0, 1, 10.0, 1),
+        ];
+        let build = |peaks: &mut dyn Iterator<Item = &DeconvolutedPeak>| {
+            let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+            index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+            index.add_parent(Spectrum::new(2400.0, 2, 0, 0, 0));
+            peaks.for_each(|p| {
+                index.add(*p);
+            });
+            index.sort(SortType::ByMass);
+            index.iter_entries().copied().collect::<Vec<_>>()
+        };
+        let forward = build(&mut peaks.iter());
+        let backward = build(&mut peaks.iter().rev());
+        assert_eq!(forward, backward);
+        assert_eq!(forward[0].scan_ref, 0);
+        assert_eq!(
+            forward[1..].iter().map(|p| (p.charge, p.intensity)).collect::<Vec<_>>(),
+            vec![(1, 10.0), (1, 30.0), (2, 10.0)]
+        );
+    }
+
+    #[test]
+    fn test_clear() {
+        let build = |index: &mut SearchIndex<DeconvolutedPeak, Spectrum>, offset: MassType| {
+            index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+            for i in 0..100 {
+                index.add(DeconvolutedPeak::new(offset + i as MassType * 0.5, 1, 0.0, 0));
+            }
+            index.sort(SortType::ByParentId);
+        };
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        build(&mut index, 250.0);
+        let num_bins = index.num_bins();
+        let capacity: usize = index.iter_bins().map(|b| b.capacity()).sum();
+        let parent_capacity = index.parents.capacity();
+
+        index.clear();
+        assert_eq!(index.num_entries(), 0);
+        assert_eq!(index.num_parents(), 0);
+        assert!(index.is_empty());
+        assert_eq!(index.sort_type(), SortType::Unsorted);
+        assert_eq!(index.num_bins(), num_bins);
+        assert_eq!(index.bins_per_dalton, 10);
+        assert_eq!(index.max_item_mass, 1000.0);
+        assert_eq!(index.iter_bins().map(|b| b.capacity()).sum::<usize>(), capacity);
+        assert_eq!(index.parents.capacity(), parent_capacity);
+
+        build(&mut index, 500.0);
+        assert_eq!(index.num_entries(), 100);
+        assert_eq!(index.search(250.0, Tolerance::Da(0.1), None).count(), 0);
+        assert_eq!(index.search(500.0, Tolerance::Da(0.1), None).count(), 1);
+        let bin = &index.bins[index.bin_for_mass(500.0)];
+        assert_eq!((bin.min_mass(), bin.max_mass()), bin.find_min_max_masses());
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> =
+            SearchIndex::with_capacity(10, 1000.0, 50_000, 500);
+        let mut reference: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        assert_eq!(index.num_bins(), reference.num_bins());
+        assert!(index.parents.capacity() >= 500);
+        assert!(index.iter_bins().all(|b| b.capacity() >= 50_000 / index.num_bins()));
+
+        for i in 0..500u32 {
+            let parent = Spectrum::new(500.0 + i as MassType, 2, 0, i, i);
+            index.add_parent(parent);
+            reference.add_parent(parent);
+            for j in 0..100u32 {
+                let peak = DeconvolutedPeak::new(
+                    ((i * 100 + j) % 9000) as MassType * 0.1 + 50.0,
+                    1,
+                    j as f32,
+                    i,
+                );
+                index.add(peak);
+                reference.add(peak);
+            }
+        }
+        index.sort(SortType::ByParentId);
+        reference.sort(SortType::ByParentId);
+
+        assert_eq!(index.num_entries(), 50_000);
+        assert_eq!(index.num_parents(), reference.num_parents());
+        for query in [100.0, 250.5, 700.3] {
+            let a: Vec<_> = index.search(query, Tolerance::PPM(20.0), None).collect();
+            let b: Vec<_> = reference.search(query, Tolerance::PPM(20.0), None).collect();
+            assert!(!a.is_empty());
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn test_rebin() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        for i in 0..50u32 {
+            index.add_parent(Spectrum::new(500.0 + i as MassType, 2, 0, i, i));
+            for j in 0..40u32 {
+                let mass = 100.0 + ((i * 37 + j * 11) % 800) as MassType * 0.9973;
+                index.add(DeconvolutedPeak::new(mass, 1, j as f32, i));
+            }
+        }
+        index.sort(SortType::ByParentId);
+
+        let rebinned = index.rebin(100);
+        assert_eq!(rebinned.bins_per_dalton, 100);
+        assert!(rebinned.num_bins() > index.num_bins());
+        assert_eq!(rebinned.num_entries(), index.num_entries());
+        assert_eq!(rebinned.sort_type(), SortType::ByParentId);
+
+        let tol = Tolerance::PPM(20.0);
+        for query in [100.0, 250.3, 420.8, 899.0] {
+            let mut a: Vec<_> = index.search(query, tol, None).collect();
+            let mut b: Vec<_> = rebinned.search(query, tol, None).collect();
+            let key = |p: &&DeconvolutedPeak| (p.scan_ref, p.mass.to_bits());
+            a.sort_by_key(key);
+            b.sort_by_key(key);
+            assert_eq!(a, b);
+        }
+        assert!(rebinned.search(100.0, tol, None).next().is_some());
+    }
+
+    #[test]
+    fn test_reserve_parents() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.reserve_parents(200);
+        assert!(index.parents.capacity() >= 200);
+
+        for i in 0..200u32 {
+            index.add_parent(Spectrum::new(500.0 + i as MassType, 2, 0, i, i));
+            index.add(DeconvolutedPeak::new(250.0, 1, 0.0, i));
+        }
+        index.sort(SortType::ByParentId);
+        assert_eq!(index.num_parents(), 200);
+        assert_eq!(index.validate(), Ok(()));
+
+        let parents = index.parents_for_range(600.0, 609.0, Tolerance::Da(0.1));
+        assert_eq!(parents, Interval::new(100, 110));
+        let hits: Vec<_> = index.search(250.0, Tolerance::PPM(10.0), Some(parents)).collect();
+        assert_eq!(hits.len(), 10);
+        assert!(hits.iter().all(|p| (100..110).contains(&p.scan_ref)));
+    }
+
+    #[test]
+    fn test_search_first() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        for mass in [250.0, 250.0009, 249.9995, 250.002, 251.0] {
+            index.add(DeconvolutedPeak::new(mass, 1, 0.0, 0));
+        }
+        index.sort(SortType::ByParentId);
+
+        let query = 250.0004;
+        let tol = Tolerance::PPM(10.0);
+        let best = index.search_first(query, tol, None).unwrap();
+        let min_error = index
+            .search_with_error(query, tol, None)
+            .map(|hit| hit.ppm_error.abs())
+            .fold(MassType::INFINITY, MassType::min);
+        assert_eq!(((best.mass - query) / query * 1e6).abs(), min_error);
+        assert_eq!(best.mass, 250.0);
+
+        assert!(index.search_first(400.0, tol, None).is_none());
+    }
+
+    #[test]
+    fn test_count_matches() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        for i in 0..20u32 {
+            index.add_parent(Spectrum::new(1000.0 + i as MassType, 2, 0, i, i));
+            for j in 0..200u32 {
+                let mass = 100.0 + ((i * 37 + j * 13) % 500) as MassType * 0.05;
+                index.add(DeconvolutedPeak::new(mass, 1, 0.0, i));
+            }
+        }
+
+        for sort_type in [SortType::ByParentId, SortType::ByMass] {
+            index.sort(sort_type);
+            for query in [100.0, 105.05, 112.3, 124.95, 300.0] {
+                for tol in [Tolerance::PPM(20.0), Tolerance::Da(0.5), Tolerance::Da(2.0)] {
+                    for parent_interval in [None, Some(Interval::new(3, 11))] {
+                        assert_eq!(
+                            index.count_matches(query, tol, parent_interval),
+                            index.search(query, tol, parent_interval).count(),
+                            "{query} {tol:?} {parent_interval:?} {sort_type:?}"
+                        );
+                    }
+                }
+            }
+        }
+        assert!(index.count_matches(112.3, Tolerance::Da(2.0), None) > 0);
+    }
+
+    #[test]
+    fn test_has_match() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        for i in 0..20u32 {
+            index.add_parent(Spectrum::new(1000.0 + i as MassType, 2, 0, i, i));
+            for j in 0..50u32 {
+                let mass = 100.0 + ((i * 37 + j * 13) % 500) as MassType * 0.05 + i as MassType;
+                index.add(DeconvolutedPeak::new(mass, 1, 0.0, i));
+            }
+        }
+
+        let mut n_hits = 0;
+        for sort_type in [SortType::ByParentId, SortType::ByMass] {
+            index.sort(sort_type);
+            for query in [50.0, 100.0, 105.05, 112.3, 124.95, 140.0, 300.0] {
+                for tol in [Tolerance::PPM(20.0), Tolerance::Da(0.5), Tolerance::Da(2.0)] {
+                    for parent_interval in [
+                        None,
+                        Some(Interval::new(3, 11)),
+                        Some(Interval::new(18, 19)),
+                        Some(Interval::new(4, 4)),
+                    ] {
+                        let hits: Vec<_> = index.search(query, tol, parent_interval).collect();
+                        let expected = !hits.is_empty();
+                        assert_eq!(
+                            index.has_match(query, tol, parent_interval),
+                            expected,
+                            "{query} {tol:?} {parent_interval:?} {sort_type:?}"
+                        );
+                        n_hits += expected as usize;
+                    }
+                }
+            }
+        }
+        assert!(n_hits > 0);
+        assert!(!index.has_match(50.0, Tolerance::Da(2.0), None));
+    }
+
+    #[test]
+    fn test_iter_parents_for() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        for (i, mass) in [1000.0, 1000.004, 1000.5, 1200.0].into_iter().enumerate() {
+            index.add_parent(Spectrum::new(mass, 2, 0, i as u32, i as u32));
+        }
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        let iv = index.parents_for(1000.002, tol);
+        let parents: Vec<_> = index.iter_parents_for(1000.002, tol).collect();
+        assert_eq!(parents.len(), 2);
+        let expected: Vec<_> = index.parents.as_slice()[iv.start..iv.end].iter().collect();
+        assert_eq!(parents, expected);
+
+        assert_eq!(index.iter_parents_for(1500.0, tol).count(), 0);
+    }
+
+    #[test]
+    fn test_try_add() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+
+        let bin = index.try_add(DeconvolutedPeak::new(999.9, 1, 0.0, 0)).unwrap();
+        assert_eq!(bin, index.bin_for_mass(999.9));
+
+        let err = index
+            .try_add(DeconvolutedPeak::new(1500.0, 1, 0.0, 0))
+            .unwrap_err();
+        assert_eq!(err, IndexInsertError::MassExceedsMaximum(1500.0, 1000.0));
+        assert!(err.to_string().contains("1500"));
+        assert_eq!(index.num_entries(), 1);
+    }
+
+    #[test]
+    fn test_reject_non_finite_mass() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> =
+            SearchIndex::with_auto_grow(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+
+        let err = index
+            .try_add(DeconvolutedPeak::new(MassType::NAN, 1, 0.0, 0))
+            .unwrap_err();
+        assert!(matches!(err, IndexInsertError::NonFiniteMass(mass) if mass.is_nan()));
+        assert!(err.to_string().contains("non-finite"));
+        for mass in [MassType::INFINITY, MassType::NEG_INFINITY] {
+            assert_eq!(
+                index.try_add(DeconvolutedPeak::new(mass, 1, 0.0, 0)),
+                Err(IndexInsertError::NonFiniteMass(mass))
+            );
+        }
+        assert_eq!(index.num_entries(), 0);
+        assert_eq!(index.max_item_mass, 1000.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "non-finite")]
+    fn test_add_non_finite_mass_panics() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add(DeconvolutedPeak::new(MassType::NAN, 1, 0.0, 0));
+    }
+
+    #[test]
+    fn test_auto_grow() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> =
+            SearchIndex::with_auto_grow(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add(DeconvolutedPeak::new(250.0, 1, 0.0, 0));
+        index.add(DeconvolutedPeak::new(1000.0, 1, 0.0, 0));
+        let initial_bins = index.num_bins();
+
+        index.add(DeconvolutedPeak::new(2000.0, 1, 0.0, 0));
+        assert!(index.max_item_mass() >= 2000.0);
+        assert!(index.num_bins() > initial_bins);
+        assert!(index.try_add(DeconvolutedPeak::new(1999.5, 1, 0.0, 0)).is_ok());
+        assert_eq!(index.num_entries(), 4);
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        for mass in [250.0, 1000.0, 2000.0, 1999.5] {
+            let hits: Vec<_> = index.search(mass, tol, None).collect();
+            assert_eq!(hits.len(), 1, "{mass}");
+            assert_eq!(hits[0].mass, mass);
+        }
+
+        let mut fixed: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        fixed.add(DeconvolutedPeak::new(2000.0, 1, 0.0, 0));
+        assert_eq!(fixed.max_item_mass(), 1000.0);
+        assert!(!fixed.auto_grow());
+    }
+
+    #[test]
+    fn test_bin_mass_range() {
+        let index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(100, 2000.0);
+        let last = index.num_bins() - 1;
+        for bin in [0, 1, 7, 100, 12345, 99999, 150000, last - 1, last] {
+            let (low, high) = index.bin_mass_range(bin);
+            assert!(low < high);
+            assert_eq!(index.bin_for_mass(low), bin, "{bin} {low}");
+            if bin < last {
+                assert_eq!(index.bin_for_mass((low + high) / 2.0), bin);
+            }
+        }
+        assert_eq!(index.bin_mass_range(last).1, MassType::INFINITY);
+        assert_eq!(index.bin_mass_range(0).0, 0.0);
+    }
+
+    #[test]
+    fn test_bin_schedule() {
+        let schedule = BinSchedule::new(100).then(500.0, 5).then(1200.5, 1);
+        let mut uniform: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 2000.0);
+        let mut variable: SearchIndex<DeconvolutedPeak, Spectrum> =
+            SearchIndex::with_bin_schedule(schedule.clone(), 2000.0);
+        assert_eq!(variable.bin_schedule(), Some(&schedule));
+        assert_eq!(variable.bins_per_dalton(), 100);
+        assert_eq!(variable.num_bins(), 500 * 100 + 3503 + 800 + 1);
+
+        for index in [&mut uniform, &mut variable] {
+            for i in 0..4 {
+                index.add_parent(Spectrum::new(2300.0 + i as MassType, 2, 0, 0, 0));
+            }
+            for i in 0..5000u32 {
+                let mass = (i as MassType * 0.4137) % 2100.0;
+                index.add(DeconvolutedPeak::new(mass, 1, 0.0, i % 4));
+            }
+            index.sort(SortType::ByParentId);
+        }
+
+        for bin in [0, 49_999, 50_000, 53_502, 53_503, 54_303] {
+            let (low, high) = variable.bin_mass_range(bin);
+            assert_eq!(variable.bin_for_mass(low), bin);
+            assert!(high.is_infinite() || variable.bin_for_mass(high) == bin + 1);
+        }
+
+        fn sorted(mut hits: Vec<&DeconvolutedPeak>) -> Vec<&DeconvolutedPeak> {
+            hits.sort_by(|a, b| (a.mass, a.scan_ref).partial_cmp(&(b.mass, b.scan_ref)).unwrap());
+            hits
+        }
+        for query in [0.0, 120.3, 499.99, 500.0, 877.7, 1200.5, 1999.0, 2050.0] {
+            for tol in [Tolerance::PPM(50.0), Tolerance::Da(0.6)] {
+                let expected = sorted(uniform.search(query, tol, None).collect());
+                let found = sorted(variable.search(query, tol, None).collect());
+                assert_eq!(found, expected, "{query} {tol:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(100, 2000.0);
+        for i in 0..4 {
+            index.add_parent(Spectrum::new(2300.0 + i as MassType, 2, 0, 0, 0));
+        }
+        for i in 0..3000u32 {
+            // Dense below 300 Da, sparse above
+            let mass = if i < 2500 {
+                (i as MassType * 0.1137) % 300.0
+            } else {
+                300.0 + (i as MassType * 3.71) % 1800.0
+            };
+            index.add(DeconvolutedPeak::new(mass, 1, 0.0, i % 4));
+        }
+        index.sort(SortType::ByParentId);
+
+        let mut compacted = SearchIndex {
+            bins: index.bins.clone(),
+            parents: index.parents.clone(),
+            bins_per_dalton: index.bins_per_dalton,
+            max_item_mass: index.max_item_mass,
+            sort_type: index.sort_type,
+            auto_grow: index.auto_grow,
+            bin_schedule: None,
+            flat: index.flat,
+        };
+        compacted.compact(8);
+        assert!(compacted.num_bins() < index.num_bins() / 10);
+        assert_eq!(compacted.num_entries(), index.num_entries());
+        assert_eq!(compacted.sort_type(), SortType::ByParentId);
+        assert!(compacted.bins.iter().all(|bin| bin.sort_type == SortType::ByParentId));
+
+        fn sorted(mut hits: Vec<&DeconvolutedPeak>) -> Vec<&DeconvolutedPeak> {
+            hits.sort_by(|a, b| (a.mass, a.scan_ref).partial_cmp(&(b.mass, b.scan_ref)).unwrap());
+            hits
+        }
+        for query in [0.0, 57.02, 150.3, 299.99, 300.0, 877.7, 1500.5, 1999.0, 2050.0] {
+            for tol in [Tolerance::PPM(50.0), Tolerance::Da(0.6)] {
+                for parent_interval in [None, Some(Interval::new(1, 3))] {
+                    let expected = sorted(index.search(query, tol, parent_interval).collect());
+                    let found = sorted(compacted.search(query, tol, parent_interval).collect());
+                    assert_eq!(found, expected, "{query} {tol:?} {parent_interval:?}");
+                }
+            }
+        }
+
+        let mut flat: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::flat(2000.0);
+        flat.compact(8);
+        assert_eq!(flat.num_bins(), 1);
+    }
+
+    #[test]
+    fn test_bin_edges() {
+        for index in [
+            SearchIndex::<DeconvolutedPeak, Spectrum>::empty(10, 2000.0),
+            SearchIndex::empty(3, 17.5),
+            SearchIndex::flat(500.0),
+            SearchIndex::with_bin_schedule(BinSchedule::new(10).then(500.0, 2).then(1200.5, 3), 2000.0),
+        ] {
+            let edges = index.bin_edges();
+            assert_eq!(edges.len(), index.num_bins() + 1);
+            assert_eq!(edges[0], 0.0);
+            assert!(*edges.last().unwrap() >= index.max_item_mass);
+            assert!(edges.windows(2).all(|w| w[0] < w[1]));
+            for (bin, w) in edges.windows(2).enumerate().take(index.num_bins() - 1) {
+                assert_eq!((w[0], w[1]), index.bin_mass_range(bin));
+            }
+            assert_eq!(edges.len() - 1, index.bin_histogram().len());
+        }
     }
 }
@@ -5,16 +5,27 @@ pub mod fragment;
 pub mod parent;
 pub mod peak;
 pub mod r#match;
+pub mod error;
+pub mod residue;
 
 #[cfg(feature = "binary_storage")]
 pub mod storage;
 
-pub use crate::sort::{IndexSortable, MassType, Tolerance, ToleranceParsingError};
+#[cfg(feature = "bench-utils")]
+pub mod bench;
+
+pub use crate::sort::{
+    neutral_mass, neutral_mass_with_adduct, IndexSortable, MassType, ParentIDMut, SortIDMut, Tolerance,
+    ToleranceParsingError, PROTON,
+};
 pub use crate::interval::Interval;
-pub use crate::index::SearchIndex;
-pub use crate::fragment::{Fragment, FragmentSeriesParsingError};
-pub use crate::parent::{ParentMolecule, Peptide, Spectrum};
-pub use crate::peak::{DeconvolutedPeak, MZPeak};
+pub use crate::error::MassFragmentIndexError;
+pub use crate::index::{
+    BinSchedule, BinStats, IndexError, IndexInsertError, IndexMergeError, SearchHit, SearchIndex, SearchIndexView,
+};
+pub use crate::fragment::{AnnotatedFragment, Fragment, FragmentSeriesParsingError};
+pub use crate::parent::{ParentMolecule, Peptide, PeptideBuilder, Spectrum};
+pub use crate::peak::{DeconvolutedPeak, MZPeak, PeakIntensity, PeakMatch};
 
 pub type PeptideFragmentIndex = SearchIndex<Fragment, Peptide>;
 pub type SpectrumIndex = SearchIndex<MZPeak, Spectrum>;
@@ -39,6 +39,20 @@ impl From<HyperscoreMatcher> for f64 {
     }
 }
 
+/// The fragments matching a single experimental peak, grouped by parent
+#[derive(Debug, Clone, Default)]
+pub struct PeakFragmentMatches<'a> {
+    pub mass: MassType,
+    pub intensity: f32,
+    pub matches: HashMap<ParentID, Vec<&'a Fragment>>,
+}
+
+impl<'a> PeakFragmentMatches<'a> {
+    pub fn num_matches(&self) -> usize {
+        self.matches.values().map(|v| v.len()).sum()
+    }
+}
+
 impl HyperscoreMatcher {
     pub fn add(&mut self, fragment: Fragment, intensity: f32) {
         match fragment.series {
@@ -106,8 +120,65 @@ impl IndexMatcher {
         matches.into_iter().map(|(k, v)| (k, v.into())).collect()
     }
 
+    /// Match each `(mass, intensity)` peak against the fragment index, returning one
+    /// [`PeakFragmentMatches`] per peak in input order.
+    pub fn match_peaks<'a, P: IndexSortable + Default>(&self, peaks: &[(MassType, f32)],
+        parent_range: (MassType, MassType),
+        index: &'a SearchIndex<Fragment, P>) -> Vec<PeakFragmentMatches<'a>> {
+        let parent_interval = index.parents_for_range(parent_range.0, parent_range.1, self.tolerance);
+        let queries: Vec<MassType> = peaks.iter().map(|(mass, _)| *mass).collect();
+
+        index.search_batch(&queries, self.tolerance, Some(parent_interval))
+            .into_iter()
+            .zip(peaks)
+            .map(|(hits, (mass, intensity))| {
+                let mut matches: HashMap<ParentID, Vec<&Fragment>> = HashMap::new();
+                for hit in hits {
+                    matches.entry(hit.parent_id()).or_default().push(hit);
+                }
+                PeakFragmentMatches { mass: *mass, intensity: *intensity, matches }
+            })
+            .collect()
+    }
 }
 
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parent::Peptide;
+    use crate::sort::SortType;
 
-
+    #[test]
+    fn test_match_peaks() {
+        let mut index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 2000.0);
+        for i in 0..3u32 {
+            index.add_parent(Peptide::new(800.0 + i as MassType * 100.0, i, 0, 0, String::new()));
+        }
+        index.add(Fragment::new(175.119, 0, FragmentSeries::y, 1));
+        index.add(Fragment::new(175.119, 1, FragmentSeries::y, 1));
+        index.add(Fragment::new(175.119, 2, FragmentSeries::y, 1));
+        index.add(Fragment::new(262.151, 1, FragmentSeries::y, 2));
+        index.add(Fragment::new(262.151, 1, FragmentSeries::b, 3));
+        index.add(Fragment::new(400.2, 2, FragmentSeries::b, 4));
+        index.sort(SortType::ByParentId);
+
+        let matcher = IndexMatcher::new(Tolerance::PPM(10.0));
+        let peaks = [(262.151, 50.0), (175.119, 100.0), (500.0, 5.0)];
+        let result = matcher.match_peaks(&peaks, (750.0, 950.0), &index);
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].intensity, 50.0);
+        assert_eq!(result[0].num_matches(), 2);
+        assert_eq!(result[0].matches.len(), 1);
+        assert_eq!(result[0].matches[&1].len(), 2);
+
+        // Parent 2 at 1000 Da lies outside the parent range
+        assert_eq!(result[1].mass, 175.119);
+        assert_eq!(result[1].matches.len(), 2);
+        assert!(result[1].matches.contains_key(&0));
+        assert!(result[1].matches.contains_key(&1));
+
+        assert_eq!(result[2].num_matches(), 0);
+    }
+}
@@ -1,7 +1,11 @@
 #[cfg(feature = "serde")]
 use serde::{Serialize, Deserialize};
 
-use crate::sort::{IndexSortable, MassType, ParentID};
+use crate::fragment::{Fragment, FragmentSeries};
+use crate::index::SearchIndex;
+use crate::interval::Interval;
+use crate::residue::{Modification, ResidueMasses, CO, H, H2O, NH3};
+use crate::sort::{IndexSortable, MassType, ParentID, SortIDMut, Tolerance};
 
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -35,19 +39,197 @@ impl IndexSortable for ParentMolecule {
     }
 }
 
+impl SortIDMut for ParentMolecule {
+    fn sort_id(&self) -> ParentID {
+        self.id
+    }
+
+    fn set_sort_id(&mut self, sort_id: ParentID) {
+        self.id = sort_id
+    }
+}
+
 
+/// A peptide, the parent of the fragments of a peptide fragment index
 #[derive(Debug, Clone, Default, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Peptide {
+    /// The neutral mass of the peptide
     pub mass: MassType,
+    /// The peptide's position in the index's parent list, which its fragments refer to
     pub id: ParentID,
+    /// The protein the peptide was digested from, its own parent
     pub protein_id: ParentID,
+    /// The offset of the peptide's first residue in that protein's sequence
     pub start_position: u16,
     pub sequence: String,
+    pub is_decoy: bool,
+    pub group_id: ParentID,
 }
 
 impl Peptide {
-    pub fn new(mass: MassType, id: ParentID, protein_id: ParentID, start_position: u16, sequence: String) -> Self { Self { mass, id, protein_id, start_position, sequence } }
+    /// Create a peptide from its fields in order. [`Peptide::builder`] names them instead,
+    /// leaving the protein and start position at zero when they are not known.
+    pub fn new(mass: MassType, id: ParentID, protein_id: ParentID, start_position: u16, sequence: String) -> Self { Self { mass, id, protein_id, start_position, sequence, is_decoy: false, group_id: 0 } }
+
+    /// Start building a peptide field by field
+    pub fn builder() -> PeptideBuilder {
+        PeptideBuilder::default()
+    }
+
+    pub fn mass(&self) -> MassType {
+        self.mass
+    }
+
+    pub fn sequence(&self) -> &str {
+        &self.sequence
+    }
+
+    /// The protein this peptide came from, see [`Peptide::protein_id`]
+    pub fn parent_id(&self) -> ParentID {
+        self.protein_id
+    }
+
+    /// Compute the neutral masses of this peptide's backbone fragments in each of `series`
+    /// from its sequence, using the standard residue masses of [`ResidueMasses`].
+    ///
+    /// Only the `a`, `b`, `c`, `x`, `y` and `z` series are generated, any others are
+    /// skipped. Residues without a known mass, like `X`, contribute no mass.
+    pub fn fragments(&self, series: &[FragmentSeries]) -> Vec<Fragment> {
+        self.fragments_with_mods(series, &ResidueMasses::default(), &[])
+    }
+
+    /// Compute fragment masses as with [`Peptide::fragments`], looking residues up in
+    /// `residue_masses` and adding the mass shift of every matching modification in
+    /// `modifications`.
+    pub fn fragments_with_mods(
+        &self,
+        series: &[FragmentSeries],
+        residue_masses: &ResidueMasses,
+        modifications: &[Modification],
+    ) -> Vec<Fragment> {
+        let residues: Vec<MassType> = self
+            .sequence
+            .chars()
+            .enumerate()
+            .map(|(position, residue)| {
+                residue_masses.get(residue).unwrap_or_default()
+                    + modifications
+                        .iter()
+                        .map(|modification| modification.shift_for(residue, position))
+                        .sum::<MassType>()
+            })
+            .collect();
+        let n = residues.len();
+        let mut prefix_masses = Vec::with_capacity(n);
+        let mut total: MassType = 0.0;
+        for mass in residues.iter() {
+            total += mass;
+            prefix_masses.push(total);
+        }
+
+        let mut fragments = Vec::new();
+        for series in series.iter().copied() {
+            let (c_terminal, shift) = match series {
+                FragmentSeries::a => (false, -CO),
+                FragmentSeries::b => (false, 0.0),
+                FragmentSeries::c => (false, NH3),
+                FragmentSeries::x => (true, H2O + CO - 2.0 * H),
+                FragmentSeries::y => (true, H2O),
+                FragmentSeries::z => (true, H2O - NH3),
+                _ => continue,
+            };
+            for ordinal in 1..n {
+                let residue_sum = if c_terminal {
+                    total - prefix_masses[n - ordinal - 1]
+                } else {
+                    prefix_masses[ordinal - 1]
+                };
+                fragments.push(Fragment::new(
+                    residue_sum + shift,
+                    self.id,
+                    series,
+                    ordinal as u16,
+                ));
+            }
+        }
+        fragments
+    }
+}
+
+/// Builds a [`Peptide`], see [`Peptide::builder`]. Unset fields keep their defaults.
+#[derive(Debug, Clone, Default)]
+pub struct PeptideBuilder {
+    peptide: Peptide,
+}
+
+impl PeptideBuilder {
+    pub fn mass(mut self, mass: MassType) -> Self {
+        self.peptide.mass = mass;
+        self
+    }
+
+    pub fn id(mut self, id: ParentID) -> Self {
+        self.peptide.id = id;
+        self
+    }
+
+    /// The protein the peptide was digested from
+    pub fn protein_id(mut self, protein_id: ParentID) -> Self {
+        self.peptide.protein_id = protein_id;
+        self
+    }
+
+    /// The offset of the peptide's first residue in its protein's sequence
+    pub fn start_position(mut self, start_position: u16) -> Self {
+        self.peptide.start_position = start_position;
+        self
+    }
+
+    pub fn sequence(mut self, sequence: impl Into<String>) -> Self {
+        self.peptide.sequence = sequence.into();
+        self
+    }
+
+    pub fn is_decoy(mut self, is_decoy: bool) -> Self {
+        self.peptide.is_decoy = is_decoy;
+        self
+    }
+
+    pub fn group_id(mut self, group_id: ParentID) -> Self {
+        self.peptide.group_id = group_id;
+        self
+    }
+
+    pub fn build(self) -> Peptide {
+        self.peptide
+    }
+}
+
+impl<T: IndexSortable + Default> SearchIndex<T, Peptide> {
+    /// Search as with [`SearchIndex::search`], optionally skipping entries whose parent
+    /// peptide is marked as a decoy.
+    pub fn search_with_decoys(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+        exclude_decoys: bool,
+    ) -> impl Iterator<Item = &T> + '_ {
+        self.search(query, error_tolerance, parent_interval)
+            .filter(move |entry| {
+                !exclude_decoys
+                    || !self
+                        .parents
+                        .get(entry.parent_id() as usize)
+                        .is_some_and(|p| p.is_decoy)
+            })
+    }
+
+    /// Iterate over the peptides belonging to the protein group `group_id`
+    pub fn parents_in_group(&self, group_id: ParentID) -> impl Iterator<Item = &Peptide> + '_ {
+        self.parents.iter().filter(move |p| p.group_id == group_id)
+    }
 }
 
 
@@ -61,6 +243,16 @@ impl IndexSortable for Peptide {
     }
 }
 
+impl SortIDMut for Peptide {
+    fn sort_id(&self) -> ParentID {
+        self.id
+    }
+
+    fn set_sort_id(&mut self, sort_id: ParentID) {
+        self.id = sort_id
+    }
+}
+
 
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -99,3 +291,182 @@ impl IndexSortable for Spectrum {
         self.source_file_id
     }
 }
+
+impl SortIDMut for Spectrum {
+    fn sort_id(&self) -> ParentID {
+        self.sort_id
+    }
+
+    fn set_sort_id(&mut self, sort_id: ParentID) {
+        self.sort_id = sort_id
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sort::SortType;
+
+    #[test]
+    fn test_fragments_with_mods() {
+        const CARBAMIDOMETHYL: MassType = 57.02146;
+        const OXIDATION: MassType = 15.99491;
+        let peptide = Peptide::new(0.0, 0, 0, 0, "ACMDK".to_string());
+        let series = [FragmentSeries::b, FragmentSeries::y];
+        let residues = ResidueMasses::default();
+        let mass_of = |fragments: &[Fragment], series: FragmentSeries, ordinal: u16| {
+            fragments
+                .iter()
+                .find(|f| f.series == series && f.ordinal == ordinal)
+                .unwrap()
+                .mass
+        };
+
+        let plain = peptide.fragments(&series);
+        assert_eq!(plain, peptide.fragments_with_mods(&series, &residues, &[]));
+
+        let fixed = [Modification::Fixed { residue: 'C', mass_shift: CARBAMIDOMETHYL }];
+        let modified = peptide.fragments_with_mods(&series, &residues, &fixed);
+        // b1 (A) and y1..y3 (K, DK, MDK) do not contain the cysteine
+        assert_eq!(mass_of(&modified, FragmentSeries::b, 1), mass_of(&plain, FragmentSeries::b, 1));
+        for ordinal in 1..4 {
+            let shift = mass_of(&modified, FragmentSeries::y, ordinal)
+                - mass_of(&plain, FragmentSeries::y, ordinal);
+            assert!(shift.abs() < 1e-3);
+        }
+        for ordinal in 2..5 {
+            let shift = mass_of(&modified, FragmentSeries::b, ordinal)
+                - mass_of(&plain, FragmentSeries::b, ordinal);
+            assert!((shift - CARBAMIDOMETHYL).abs() < 1e-3);
+        }
+        let shift =
+            mass_of(&modified, FragmentSeries::y, 4) - mass_of(&plain, FragmentSeries::y, 4);
+        assert!((shift - CARBAMIDOMETHYL).abs() < 1e-3);
+
+        let mods = [
+            fixed[0],
+            Modification::Variable { position: 2, mass_shift: OXIDATION },
+        ];
+        let modified = peptide.fragments_with_mods(&series, &residues, &mods);
+        let shift =
+            mass_of(&modified, FragmentSeries::b, 3) - mass_of(&plain, FragmentSeries::b, 3);
+        assert!((shift - CARBAMIDOMETHYL - OXIDATION).abs() < 1e-3);
+        let shift =
+            mass_of(&modified, FragmentSeries::y, 3) - mass_of(&plain, FragmentSeries::y, 3);
+        assert!((shift - OXIDATION).abs() < 1e-3);
+
+        let mut custom = ResidueMasses::default();
+        custom.set('C', residues.get('C').unwrap() + CARBAMIDOMETHYL);
+        assert_eq!(custom.get('X'), None);
+        let from_table = peptide.fragments_with_mods(&series, &custom, &[]);
+        let from_mods = peptide.fragments_with_mods(&series, &residues, &fixed);
+        for (a, b) in from_table.iter().zip(from_mods.iter()) {
+            assert!((a.mass - b.mass).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_fragments_from_sequence() {
+        let peptide = Peptide::new(432.23325, 3, 0, 0, "TGAGK".to_string());
+        let fragments =
+            peptide.fragments(&[FragmentSeries::b, FragmentSeries::y, FragmentSeries::Oxonium]);
+        assert_eq!(fragments.len(), 8);
+        assert!(fragments.iter().all(|f| f.parent_id == 3 && f.charge == 1));
+
+        let mass_of = |series: FragmentSeries, ordinal: u16| {
+            fragments
+                .iter()
+                .find(|f| f.series == series && f.ordinal == ordinal)
+                .unwrap()
+                .mass
+        };
+        let expected = [
+            (FragmentSeries::b, 1, 101.04768),
+            (FragmentSeries::b, 2, 158.06914),
+            (FragmentSeries::b, 4, 286.12772),
+            (FragmentSeries::y, 1, 146.10553),
+            (FragmentSeries::y, 4, 331.18557),
+        ];
+        for (series, ordinal, mass) in expected {
+            assert!((mass_of(series, ordinal) - mass).abs() < 1e-3, "{series}{ordinal}");
+        }
+
+        // Complementary b and y ions add up to the precursor
+        let b2_y3 = mass_of(FragmentSeries::b, 2) + mass_of(FragmentSeries::y, 3);
+        assert!((b2_y3 - peptide.mass).abs() < 1e-3);
+
+        let fragments =
+            peptide.fragments(&[FragmentSeries::a, FragmentSeries::c, FragmentSeries::z]);
+        assert_eq!(fragments.len(), 12);
+        assert!((fragments[0].mass - (101.04768 - CO)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_peptide_builder() {
+        let peptide = Peptide::builder()
+            .mass(799.36)
+            .id(3)
+            .protein_id(12)
+            .start_position(40)
+            .sequence("PEPTIDE")
+            .is_decoy(true)
+            .group_id(2)
+            .build();
+        assert_eq!(peptide.mass(), 799.36);
+        assert_eq!(peptide.sequence(), "PEPTIDE");
+        assert_eq!(peptide.parent_id(), 12);
+        assert_eq!(IndexSortable::parent_id(&peptide), peptide.parent_id());
+        assert_eq!(peptide.id, 3);
+        assert_eq!(peptide.start_position, 40);
+        assert!(peptide.is_decoy);
+        assert_eq!(peptide.group_id, 2);
+
+        let positional = Peptide::new(799.36, 3, 12, 40, "PEPTIDE".into());
+        let named = Peptide::builder()
+            .mass(799.36)
+            .id(3)
+            .protein_id(12)
+            .start_position(40)
+            .sequence("PEPTIDE")
+            .build();
+        assert_eq!(named, positional);
+        assert_eq!(Peptide::builder().build(), Peptide::default());
+    }
+
+    #[test]
+    fn test_exclude_decoys() {
+        let mut index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 2000.0);
+        for i in 0..4u32 {
+            let mut peptide = Peptide::new(800.0 + i as MassType, i, 0, 0, String::new());
+            peptide.is_decoy = i % 2 == 1;
+            index.add_parent(peptide);
+            index.add(Fragment::new(300.15, i, FragmentSeries::y, 3));
+        }
+        index.sort(SortType::ByParentId);
+
+        let tol = Tolerance::PPM(10.0);
+        assert_eq!(index.search_with_decoys(300.15, tol, None, false).count(), 4);
+        let targets: Vec<_> = index.search_with_decoys(300.15, tol, None, true).collect();
+        assert_eq!(targets.len(), 2);
+        assert!(targets
+            .iter()
+            .all(|f| !index.parents[f.parent_id as usize].is_decoy));
+    }
+
+    #[test]
+    fn test_parents_in_group() {
+        let mut index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 2000.0);
+        for i in 0..6u32 {
+            let mut peptide = Peptide::new(800.0 + i as MassType, i, i, 0, String::new());
+            peptide.group_id = i % 3;
+            index.add_parent(peptide);
+        }
+
+        let group: Vec<_> = index.parents_in_group(1).collect();
+        assert_eq!(group.len(), 2);
+        assert!(group.iter().all(|p| p.group_id == 1));
+        assert_eq!(group.iter().map(|p| p.id).collect::<Vec<_>>(), vec![1, 4]);
+        assert_eq!(index.parents_in_group(7).count(), 0);
+    }
+}
@@ -1,7 +1,14 @@
 #[cfg(feature = "serde")]
 use serde::{Serialize, Deserialize};
 
-use crate::sort::{IndexSortable, ParentID, MassType};
+use crate::index::SearchIndex;
+use crate::interval::Interval;
+use crate::sort::{IndexSortable, ParentID, ParentIDMut, MassType, Tolerance};
+
+/// An indexed item which carries a signal intensity
+pub trait PeakIntensity: IndexSortable {
+    fn intensity(&self) -> f32;
+}
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
@@ -21,6 +28,26 @@ impl IndexSortable for DeconvolutedPeak {
     fn parent_id(&self) -> ParentID {
         self.scan_ref
     }
+
+    fn charge(&self) -> i8 {
+        self.charge as i8
+    }
+
+    fn sort_key(&self) -> u64 {
+        ((self.charge as u16 as u64) << 32) | self.intensity.to_bits() as u64
+    }
+}
+
+impl ParentIDMut for DeconvolutedPeak {
+    fn set_parent_id(&mut self, parent_id: ParentID) {
+        self.scan_ref = parent_id
+    }
+}
+
+impl PeakIntensity for DeconvolutedPeak {
+    fn intensity(&self) -> f32 {
+        self.intensity
+    }
 }
 
 impl PartialOrd for DeconvolutedPeak {
@@ -57,6 +84,22 @@ impl IndexSortable for MZPeak {
     fn parent_id(&self) -> ParentID {
         self.scan_ref
     }
+
+    fn sort_key(&self) -> u64 {
+        self.intensity.to_bits() as u64
+    }
+}
+
+impl ParentIDMut for MZPeak {
+    fn set_parent_id(&mut self, parent_id: ParentID) {
+        self.scan_ref = parent_id
+    }
+}
+
+impl PeakIntensity for MZPeak {
+    fn intensity(&self) -> f32 {
+        self.intensity
+    }
 }
 
 impl PartialOrd for MZPeak {
@@ -76,7 +119,33 @@ impl MZPeak {
 }
 
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakMatch<'a, T> {
+    pub peak: &'a T,
+    pub intensity: f32,
+}
+
+impl<'a, T: PeakIntensity> PeakMatch<'a, T> {
+    pub fn new(peak: &'a T) -> Self {
+        Self {
+            peak,
+            intensity: peak.intensity(),
+        }
+    }
+}
 
+impl<T: PeakIntensity + Default, P: IndexSortable + Default> SearchIndex<T, P> {
+    /// Search as with [`SearchIndex::search`], pairing each matched peak with its intensity
+    pub fn search_peaks(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> impl Iterator<Item = PeakMatch<'_, T>> + '_ {
+        self.search(query, error_tolerance, parent_interval)
+            .map(PeakMatch::new)
+    }
+}
 
 
 #[cfg(test)]
@@ -91,4 +160,25 @@ mod test {
         assert!(peak.scan_ref == 300);
         assert!(peak.parent_id() == 300);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_search_peaks() {
+        use crate::parent::Spectrum;
+        use crate::sort::SortType;
+
+        let mut index: SearchIndex<DeconvolutedPeak, Spectrum> = SearchIndex::empty(10, 1000.0);
+        index.add_parent(Spectrum::new(2300.0, 2, 0, 0, 0));
+        index.add_parent(Spectrum::new(2400.0, 2, 0, 1, 1));
+        index.add(DeconvolutedPeak::new(256.03, 1, 1500.0, 0));
+        index.add(DeconvolutedPeak::new(256.03, 2, 320.5, 1));
+        index.add(DeconvolutedPeak::new(300.0, 1, 10.0, 1));
+        index.sort(SortType::ByParentId);
+
+        let mut matches: Vec<_> = index
+            .search_peaks(256.03, Tolerance::PPM(10.0), None)
+            .map(|m| (m.peak.scan_ref, m.intensity))
+            .collect();
+        matches.sort_by_key(|(scan_ref, _)| *scan_ref);
+        assert_eq!(matches, vec![(0, 1500.0), (1, 320.5)]);
+    }
+}
@@ -0,0 +1,106 @@
+// Masses are given to full precision for the `high_precision` feature
+#![allow(clippy::excessive_precision)]
+
+use crate::sort::MassType;
+
+/// The monoisotopic mass of water
+pub const H2O: MassType = 18.010_564_684;
+/// The monoisotopic mass of ammonia
+pub const NH3: MassType = 17.026_549_101;
+/// The monoisotopic mass of carbon monoxide
+pub const CO: MassType = 27.994_914_620;
+/// The monoisotopic mass of a hydrogen atom
+pub const H: MassType = 1.007_825_032;
+
+/// The monoisotopic mass of an amino acid residue by its one letter code, or `None` for
+/// codes without a fixed mass like `X`
+pub const fn residue_mass(residue: char) -> Option<MassType> {
+    let mass = match residue {
+        'G' => 57.021_463_72,
+        'A' => 71.037_113_79,
+        'S' => 87.032_028_41,
+        'P' => 97.052_763_85,
+        'V' => 99.068_413_91,
+        'T' => 101.047_678_47,
+        'C' => 103.009_184_78,
+        'L' | 'I' => 113.084_063_98,
+        'N' => 114.042_927_44,
+        'D' => 115.026_943_03,
+        'Q' => 128.058_577_51,
+        'K' => 128.094_963_02,
+        'E' => 129.042_593_09,
+        'M' => 131.040_484_91,
+        'H' => 137.058_911_86,
+        'F' => 147.068_413_91,
+        'U' => 150.953_636,
+        'R' => 156.101_111_03,
+        'Y' => 163.063_328_53,
+        'W' => 186.079_312_95,
+        'O' => 237.147_726_9,
+        _ => return None,
+    };
+    Some(mass)
+}
+
+/// A table of residue masses by one letter code, starting from the standard amino acids
+/// of [`residue_mass`]. Entries can be overridden or added for non-standard residues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResidueMasses {
+    masses: [Option<MassType>; 26],
+}
+
+impl Default for ResidueMasses {
+    fn default() -> Self {
+        let mut masses = [None; 26];
+        for (i, residue) in ('A'..='Z').enumerate() {
+            masses[i] = residue_mass(residue);
+        }
+        Self { masses }
+    }
+}
+
+impl ResidueMasses {
+    fn slot(residue: char) -> Option<usize> {
+        residue
+            .is_ascii_uppercase()
+            .then(|| residue as usize - 'A' as usize)
+    }
+
+    /// The mass of `residue`, or `None` if it has no mass in this table
+    pub fn get(&self, residue: char) -> Option<MassType> {
+        Self::slot(residue).and_then(|i| self.masses[i])
+    }
+
+    /// Set the mass of `residue`, which must be an uppercase ASCII letter
+    pub fn set(&mut self, residue: char, mass: MassType) {
+        let i = Self::slot(residue).expect("Residue codes must be uppercase ASCII letters");
+        self.masses[i] = Some(mass);
+    }
+}
+
+/// A mass shift applied to residues when computing fragment masses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Modification {
+    /// Applied to every occurrence of `residue`, e.g. carbamidomethylation of `C`
+    Fixed { residue: char, mass_shift: MassType },
+    /// Applied only to the residue at `position` in the sequence, counting from 0, e.g. an
+    /// oxidized `M` in one particular peptide
+    Variable { position: usize, mass_shift: MassType },
+}
+
+impl Modification {
+    /// The mass shift this modification adds to `residue` at `position`
+    pub fn shift_for(&self, residue: char, position: usize) -> MassType {
+        match *self {
+            Self::Fixed {
+                residue: target,
+                mass_shift,
+            } if target == residue => mass_shift,
+            Self::Variable {
+                position: target,
+                mass_shift,
+            } if target == position => mass_shift,
+            _ => 0.0,
+        }
+    }
+}
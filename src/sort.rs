@@ -8,7 +8,31 @@ use serde::{Deserialize, Serialize};
 use crate::interval::Interval;
 
 pub type ParentID = u32;
+
+/// The floating point type used to store masses.
+///
+/// This defaults to `f32`, which keeps indices compact but only carries ~7 significant
+/// digits, about 0.1 PPM at 5000 Da. Enable the `high_precision` feature to use `f64`
+/// when tighter tolerances are needed at high mass.
+#[cfg(not(feature = "high_precision"))]
 pub type MassType = f32;
+#[cfg(feature = "high_precision")]
+pub type MassType = f64;
+
+/// The mass of a proton in Daltons
+pub const PROTON: MassType = 1.007_276_5;
+
+/// Convert an observed m/z to a neutral mass, assuming the charge is carried by
+/// protons (or their loss when `charge` is negative)
+pub fn neutral_mass(mz: MassType, charge: i8) -> MassType {
+    neutral_mass_with_adduct(mz, charge, PROTON)
+}
+
+/// Convert an observed m/z to a neutral mass, assuming the charge is carried by
+/// `adduct_mass`, e.g. a sodium or metal cation
+pub fn neutral_mass_with_adduct(mz: MassType, charge: i8, adduct_mass: MassType) -> MassType {
+    mz * charge.unsigned_abs() as MassType - charge as MassType * adduct_mass
+}
 
 pub fn _isclose(x: MassType, y: MassType, rtol: MassType, atol: MassType) -> bool {
     (x - y).abs() <= (atol + rtol * y.abs())
@@ -41,6 +65,10 @@ impl Default for SortType {
 pub enum Tolerance {
     PPM(MassType),
     Da(MassType),
+    Asymmetric { low_ppm: MassType, high_ppm: MassType },
+    /// A symmetric PPM window whose width is the polynomial `c0 + c1 * m + c2 * m^2`
+    /// evaluated at the reference mass `m`, for instruments whose accuracy varies with mass
+    Polynomial([MassType; 3]),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -51,7 +79,11 @@ pub enum ToleranceParsingError {
 
 impl Display for ToleranceParsingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!("{:?}", self))
+        let text = match self {
+            Self::UnknownUnit => "Unknown tolerance unit, expected one of \"ppm\", \"Da\" or \"Th\"",
+            Self::InvalidMagnitude => "Invalid tolerance magnitude, should be a number",
+        };
+        f.write_str(text)
     }
 }
 
@@ -61,30 +93,32 @@ impl FromStr for Tolerance {
     type Err = ToleranceParsingError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let n = s.len();
-        if n <= 2 {
-            return Err(ToleranceParsingError::InvalidMagnitude);
-        }
-        let s = s.to_lowercase();
-        if s.ends_with("da") {
-            if let Ok(magnitude) = s[0..n - 2].parse::<MassType>() {
-                Ok(Self::Da(magnitude))
-            } else {
-                Err(ToleranceParsingError::InvalidMagnitude)
-            }
-        } else if s.ends_with("ppm") {
-            if let Ok(magnitude) = s[0..n - 3].parse::<MassType>() {
-                Ok(Self::PPM(magnitude))
-            } else {
-                Err(ToleranceParsingError::InvalidMagnitude)
-            }
-        } else {
-            Err(ToleranceParsingError::UnknownUnit)
+        let s = s.trim().to_lowercase();
+        let magnitude = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let unit = &s[magnitude.len()..];
+        let magnitude = match magnitude.trim().parse::<MassType>() {
+            Ok(magnitude) => magnitude,
+            Err(_) => return Err(ToleranceParsingError::InvalidMagnitude),
+        };
+        match unit {
+            "ppm" => Ok(Self::PPM(magnitude)),
+            // Thomson is the m/z analog of Dalton, so treat it as an absolute window
+            "da" | "th" => Ok(Self::Da(magnitude)),
+            _ => Err(ToleranceParsingError::UnknownUnit),
         }
     }
 }
 
 impl Tolerance {
+    /// Compute the inclusive lower and upper mass limits of the window around `query`
+    ///
+    /// ```
+    /// use mass_fragment_index::Tolerance;
+    ///
+    /// assert_eq!(Tolerance::PPM(10.0).bounds(1000.0), (999.99, 1000.01));
+    /// assert_eq!(Tolerance::Da(0.5).bounds(1000.0), (999.5, 1000.5));
+    /// assert_eq!(Tolerance::Polynomial([5.0, 0.005, 0.0]).bounds(1000.0), (999.99, 1000.01));
+    /// ```
     pub fn bounds(&self, query: MassType) -> (MassType, MassType) {
         match self {
             Tolerance::PPM(tol) => {
@@ -92,17 +126,56 @@ impl Tolerance {
                 (query - width, query + width)
             }
             Tolerance::Da(tol) => (query - *tol, query + *tol),
+            Tolerance::Asymmetric { low_ppm, high_ppm } => (
+                query - query * *low_ppm / 1e6,
+                query + query * *high_ppm / 1e6,
+            ),
+            Tolerance::Polynomial([c0, c1, c2]) => {
+                let ppm = c0 + query * (c1 + query * c2);
+                let width = query * ppm / 1e6;
+                (query - width, query + width)
+            }
         }
     }
 
     pub fn test(&self, query: MassType, reference: MassType) -> bool {
-        let (lower_bound, upper_bound) = self.bounds(reference);
-        query >= lower_bound && query <= upper_bound
+        match self {
+            // The window is not symmetric, so it must be anchored on the query the same
+            // way `bounds` is when searching.
+            Tolerance::Asymmetric { .. } => {
+                let (lower_bound, upper_bound) = self.bounds(query);
+                reference >= lower_bound && reference <= upper_bound
+            }
+            _ => {
+                let (lower_bound, upper_bound) = self.bounds(reference);
+                query >= lower_bound && query <= upper_bound
+            }
+        }
+    }
+
+    /// Scale the width of the window by `factor`, e.g. to retry a search more loosely.
+    /// This is the same as multiplying the tolerance by `factor`.
+    pub fn widen(&self, factor: MassType) -> Tolerance {
+        *self * factor
+    }
+
+    /// Whether the window of this tolerance around `at_mass` encloses that of `other`
+    ///
+    /// ```
+    /// use mass_fragment_index::Tolerance;
+    ///
+    /// assert!(Tolerance::Da(0.02).contains(&Tolerance::PPM(10.0), 1000.0));
+    /// assert!(!Tolerance::Da(0.02).contains(&Tolerance::PPM(10.0), 3000.0));
+    /// ```
+    pub fn contains(&self, other: &Tolerance, at_mass: MassType) -> bool {
+        let (low, high) = self.bounds(at_mass);
+        let (other_low, other_high) = other.bounds(at_mass);
+        low <= other_low && other_high <= high
     }
 
     pub fn format_error(&self, query: MassType, reference: MassType) -> String {
         match self {
-            Self::PPM(_tol) => {
+            Self::PPM(_) | Self::Asymmetric { .. } | Self::Polynomial(_) => {
                 let magnitude = (query - reference) / reference * 1e6;
                 format!("{}PPM", magnitude).to_string()
             }
@@ -121,6 +194,11 @@ impl Mul<MassType> for Tolerance {
         match self {
             Self::Da(val) => Self::Da(rhs * val),
             Self::PPM(val) => Self::PPM(rhs * val),
+            Self::Asymmetric { low_ppm, high_ppm } => Self::Asymmetric {
+                low_ppm: rhs * low_ppm,
+                high_ppm: rhs * high_ppm,
+            },
+            Self::Polynomial(coefficients) => Self::Polynomial(coefficients.map(|c| rhs * c)),
         }
     }
 }
@@ -128,6 +206,28 @@ impl Mul<MassType> for Tolerance {
 pub trait IndexSortable {
     fn mass(&self) -> MassType;
     fn parent_id(&self) -> ParentID;
+
+    /// The charge state of the entry, used to convert between neutral mass and m/z
+    fn charge(&self) -> i8 {
+        1
+    }
+
+    /// Breaks ties between entries with the same mass and parent when sorting by mass, so
+    /// the order does not depend on the order entries were added in
+    fn sort_key(&self) -> u64 {
+        0
+    }
+}
+
+/// An index entry whose reference to its parent can be reassigned, e.g. when parents are re-numbered
+pub trait ParentIDMut: IndexSortable {
+    fn set_parent_id(&mut self, parent_id: ParentID);
+}
+
+/// A parent whose own position in the index's parent list can be reassigned
+pub trait SortIDMut: IndexSortable {
+    fn sort_id(&self) -> ParentID;
+    fn set_sort_id(&mut self, sort_id: ParentID);
 }
 
 #[derive(Debug, Clone, Default)]
@@ -177,8 +277,12 @@ impl<T: IndexSortable> IndexBin<T> {
     pub fn sort(&mut self, ordering: SortType) {
         match ordering {
             SortType::ByMass => {
-                self.entries
-                    .sort_by(|a, b| a.mass().partial_cmp(&b.mass()).unwrap());
+                self.entries.sort_by(|a, b| {
+                    a.mass()
+                        .total_cmp(&b.mass())
+                        .then_with(|| a.parent_id().cmp(&b.parent_id()))
+                        .then_with(|| a.sort_key().cmp(&b.sort_key()))
+                });
                 if let Some(f) = self.entries.first() {
                     self.min_mass = f.mass()
                 }
@@ -198,6 +302,37 @@ impl<T: IndexSortable> IndexBin<T> {
         self.sort_type = ordering;
     }
 
+    /// Remove entries equal to an earlier entry in the bin, keeping the first copy and the
+    /// current order. Returns the number of entries removed.
+    ///
+    /// Duplicates share a mass and a parent, so when the bin is sorted only the run of
+    /// entries sharing the sort key needs to be checked.
+    pub fn dedup(&mut self) -> usize
+    where
+        T: PartialEq,
+    {
+        let before = self.entries.len();
+        let sort_type = self.sort_type;
+        let same_run = |a: &T, b: &T| match sort_type {
+            SortType::ByParentId => a.parent_id() == b.parent_id(),
+            SortType::ByMass => a.mass() == b.mass(),
+            SortType::Unsorted => true,
+        };
+        let mut kept: Vec<T> = Vec::with_capacity(before);
+        for entry in self.entries.drain(..) {
+            let duplicate = kept
+                .iter()
+                .rev()
+                .take_while(|k| same_run(k, &entry))
+                .any(|k| *k == entry);
+            if !duplicate {
+                kept.push(entry);
+            }
+        }
+        self.entries = kept;
+        before - self.entries.len()
+    }
+
     pub fn assume_sorted(&mut self, sort_type: SortType) {
         (self.min_mass, self.max_mass) = self.find_min_max_masses();
         self.sort_type = sort_type;
@@ -211,6 +346,26 @@ impl<T: IndexSortable> IndexBin<T> {
         self.entries.is_empty()
     }
 
+    pub fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.entries.shrink_to_fit()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional)
+    }
+
+    /// Remove all entries, keeping the allocated capacity
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.sort_type = SortType::Unsorted;
+        self.min_mass = 0.0;
+        self.max_mass = 0.0;
+    }
+
     pub fn iter(&self) -> std::slice::Iter<T> {
         self.entries.iter()
     }
@@ -305,11 +460,11 @@ impl<T: IndexSortable> IndexBin<T> {
         result
     }
 
-    pub fn min_mass(&self) -> f32 {
+    pub fn min_mass(&self) -> MassType {
         self.min_mass
     }
 
-    pub fn max_mass(&self) -> f32 {
+    pub fn max_mass(&self) -> MassType {
         self.max_mass
     }
 
@@ -357,7 +512,7 @@ impl<T: IndexSortable> From<Vec<T>> for IndexBin<T> {
 pub struct ParentSortedIndexBinSearchIter<'a, T: IndexSortable> {
     bin_iter: std::slice::Iter<'a, T>,
     parent_range: Interval,
-    query: f32,
+    query: MassType,
     error_tolerance: Tolerance,
     spanned: bool,
 }
@@ -376,12 +531,20 @@ impl<'a, T: IndexSortable> ParentSortedIndexBinSearchIter<'a, T> {
     pub fn new(
         bin: &'a IndexBin<T>,
         parent_range: Interval,
-        query: f32,
+        query: MassType,
         error_tolerance: Tolerance,
     ) -> Self {
-        let bin_iter = bin.iter();
         let (lo, hi) = error_tolerance.bounds(query);
         let spanned = lo <= bin.min_mass && hi >= bin.max_mass;
+        // When the bin is ordered by mass, only the slice inside the mass window
+        // needs to be visited instead of scanning the whole bin.
+        let bin_iter = if bin.sort_type == SortType::ByMass && !spanned {
+            let start = bin.entries.partition_point(|e| e.mass() < lo);
+            let end = start + bin.entries[start..].partition_point(|e| e.mass() <= hi);
+            bin.entries[start..end].iter()
+        } else {
+            bin.iter()
+        };
         Self {
             bin_iter,
             parent_range,
@@ -432,4 +595,36 @@ mod test {
         assert!(search_out.start == 2);
         assert!(search_out.end == 3);
     }
+
+    #[test]
+    fn test_widen_and_contains() {
+        let tol = Tolerance::PPM(10.0);
+        let wide = tol.widen(2.0);
+        assert_eq!(wide, Tolerance::PPM(20.0));
+        assert_eq!(Tolerance::Da(0.5).widen(0.5), Tolerance::Da(0.25));
+
+        assert!(wide.contains(&tol, 500.0));
+        assert!(!tol.contains(&wide, 500.0));
+        assert!(tol.contains(&tol, 500.0));
+
+        let asymmetric = Tolerance::Asymmetric { low_ppm: 5.0, high_ppm: 15.0 };
+        assert!(!tol.contains(&asymmetric, 500.0));
+        assert!(wide.contains(&asymmetric, 500.0));
+    }
+
+    #[test]
+    fn test_parse_tolerance() {
+        assert_eq!("10ppm".parse::<Tolerance>(), Ok(Tolerance::PPM(10.0)));
+        assert_eq!("10 ppm".parse::<Tolerance>(), Ok(Tolerance::PPM(10.0)));
+        assert_eq!(" 5 PPM ".parse::<Tolerance>(), Ok(Tolerance::PPM(5.0)));
+        assert_eq!("0.02 Da".parse::<Tolerance>(), Ok(Tolerance::Da(0.02)));
+        assert_eq!("0.02da".parse::<Tolerance>(), Ok(Tolerance::Da(0.02)));
+        assert_eq!("0.5 Th".parse::<Tolerance>(), Ok(Tolerance::Da(0.5)));
+
+        assert_eq!("10 furlongs".parse::<Tolerance>(), Err(ToleranceParsingError::UnknownUnit));
+        assert_eq!("10".parse::<Tolerance>(), Err(ToleranceParsingError::UnknownUnit));
+        assert_eq!("ten ppm".parse::<Tolerance>(), Err(ToleranceParsingError::InvalidMagnitude));
+        assert_eq!("ppm".parse::<Tolerance>(), Err(ToleranceParsingError::InvalidMagnitude));
+        assert_eq!("1e1 ppm".parse::<Tolerance>(), Ok(Tolerance::PPM(10.0)));
+    }
 }
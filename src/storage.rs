@@ -1,14 +1,87 @@
 #![cfg(feature = "binary_storage")]
 
+mod archive;
+mod csv_index;
+mod ipc;
 mod peak_parquet;
 mod fragment_parquet;
 mod util;
 mod split;
+mod stream;
+#[cfg(feature = "object_store")]
+mod object_storage;
 
 pub use peak_parquet::{read_peak_index, write_peak_index};
 pub use fragment_parquet::{read_fragment_index, write_fragment_index};
-pub use util::{ArrowStorage, IndexMetadata, IndexBinaryStorage};
+pub use util::{
+    ArrowStorage, IndexBinReader, IndexMetadata, IndexBinaryStorage, IndexCompression, SearchIndexOnDisk,
+};
 pub use split::{SplitIndexBinaryStorage, SplitBand};
+pub use archive::IndexArchiveStorage;
+pub use ipc::IndexIpcStorage;
+pub(crate) use util::{
+    appended_segments, existing_build_id, next_appended_segment, read_metadata_from,
+    remove_appended_segments, write_entries_into, write_metadata_into, write_parents_into,
+};
+pub use csv_index::read_csv_index;
+pub use stream::SearchIndexWriter;
+#[cfg(feature = "object_store")]
+pub use object_storage::read_from_object_store;
 
 #[doc(hidden)]
-pub use parquet::basic::{Compression, ZstdLevel, GzipLevel, BrotliLevel};
\ No newline at end of file
+pub use parquet::basic::{Compression, ZstdLevel, GzipLevel, BrotliLevel};
+#[doc(hidden)]
+pub use bytes::Bytes;
+
+use std::path::{Path, PathBuf};
+
+use arrow::datatypes::SchemaRef;
+
+use crate::{AnnotatedFragment, DeconvolutedPeak, Fragment, Peptide, Spectrum};
+
+/// The schema of the entries file of a [`Fragment`] index
+pub fn fragment_schema() -> SchemaRef {
+    Fragment::schema()
+}
+
+/// The schema of the entries file of an [`AnnotatedFragment`] index
+pub fn annotated_fragment_schema() -> SchemaRef {
+    AnnotatedFragment::schema()
+}
+
+/// The schema of the parents file of a [`Peptide`] index
+pub fn peptide_schema() -> SchemaRef {
+    Peptide::schema()
+}
+
+/// The schema of the entries file of a [`DeconvolutedPeak`] index
+pub fn peak_schema() -> SchemaRef {
+    DeconvolutedPeak::schema()
+}
+
+/// The schema of the parents file of a [`Spectrum`] index
+pub fn spectrum_schema() -> SchemaRef {
+    Spectrum::schema()
+}
+
+/// The schema of the metadata written alongside every index
+pub fn metadata_schema() -> SchemaRef {
+    IndexMetadata::schema()
+}
+/// The files [`IndexBinaryStorage::write`] creates for an index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexPaths {
+    pub meta: PathBuf,
+    pub parents: PathBuf,
+    pub entries: PathBuf,
+}
+
+/// The paths of the files an index with entries `T` and parents `P` is written to in
+/// `directory`, e.g. `index_file_paths::<Fragment, Peptide>(dir)`
+pub fn index_file_paths<T: ArrowStorage, P: ArrowStorage>(directory: &Path) -> IndexPaths {
+    IndexPaths {
+        meta: directory.join(IndexMetadata::archive_name()),
+        parents: directory.join(P::archive_name()),
+        entries: directory.join(T::archive_name()),
+    }
+}
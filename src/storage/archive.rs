@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, prelude::*},
+    path::Path,
+};
+
+use bytes::Bytes;
+use super::{
+    util::{new_build_id, write_entries_into, write_metadata_into, write_parents_into},
+    ArrowStorage, IndexBinaryStorage, IndexCompression,
+};
+use crate::MassFragmentIndexError;
+
+fn append_member<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)
+}
+
+fn take_member(members: &mut HashMap<String, Vec<u8>>, name: &str) -> io::Result<Vec<u8>> {
+    members.remove(name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Archive member {name} not found"),
+        )
+    })
+}
+
+/// Store an index as a single tar archive whose members are the files
+/// [`IndexBinaryStorage::write`] would place in a directory.
+pub trait IndexArchiveStorage<'a, T: ArrowStorage + 'a, P: ArrowStorage, M: ArrowStorage>:
+    IndexBinaryStorage<'a, T, P, M>
+{
+    fn write_archive<D: AsRef<Path>>(
+        &'a self,
+        path: &D,
+        compression_level: Option<IndexCompression>,
+    ) -> Result<(), MassFragmentIndexError> {
+        let compression_level = compression_level.unwrap_or_default();
+
+        let mut builder = tar::Builder::new(io::BufWriter::new(fs::File::create(path)?));
+
+        let build_id = new_build_id();
+        let mut metadata = self.to_metadata();
+        metadata.set_build_id(build_id);
+
+        let mut buffer = Vec::new();
+        write_metadata_into(metadata, &mut buffer)?;
+        append_member(&mut builder, &M::archive_name(), &buffer)?;
+
+        let mut buffer = Vec::new();
+        write_parents_into(self.parents(), &mut buffer, &compression_level.parents, Some(build_id))?;
+        append_member(&mut builder, &P::archive_name(), &buffer)?;
+
+        let mut buffer = Vec::new();
+        write_entries_into(
+            self.iter_entries(),
+            &mut buffer,
+            &compression_level.entries,
+            Some(build_id),
+            None,
+        )?;
+        append_member(&mut builder, &T::archive_name(), &buffer)?;
+
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+
+    fn read_archive<D: AsRef<Path>>(path: &D) -> Result<Self, MassFragmentIndexError>
+    where
+        Self: Sized,
+    {
+        let mut archive = tar::Archive::new(io::BufReader::new(fs::File::open(path)?));
+        let mut members = HashMap::new();
+        for member in archive.entries()? {
+            let mut member = member?;
+            let name = member.path()?.to_string_lossy().to_string();
+            let mut buffer = Vec::with_capacity(member.size() as usize);
+            member.read_to_end(&mut buffer)?;
+            members.insert(name, buffer);
+        }
+
+        Self::read_from_sources(
+            io::Cursor::new(take_member(&mut members, &M::archive_name())?),
+            Bytes::from(take_member(&mut members, &P::archive_name())?),
+            Bytes::from(take_member(&mut members, &T::archive_name())?),
+        )
+    }
+}
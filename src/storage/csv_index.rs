@@ -0,0 +1,120 @@
+use std::{fmt::Display, io, str::FromStr};
+
+use crate::{
+    fragment::FragmentName,
+    sort::{ParentID, SortType},
+    Fragment, MassType, Peptide, SearchIndex,
+};
+
+fn invalid_record(line_number: usize, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Line {}: {message}", line_number + 1),
+    )
+}
+
+fn parse_mass(field: Option<&str>, line_number: usize) -> io::Result<MassType> {
+    let field = field.ok_or_else(|| invalid_record(line_number, "Missing mass".into()))?;
+    field
+        .trim()
+        .parse()
+        .map_err(|e| invalid_record(line_number, format!("Invalid mass {field:?}: {e}")))
+}
+
+/// Parse the optional `column` of a row, if the header named it and the row has a value
+fn parse_column<V: FromStr>(
+    columns: &[&str],
+    column: Option<usize>,
+    line_number: usize,
+) -> io::Result<Option<V>>
+where
+    V::Err: Display,
+{
+    match column.and_then(|i| columns.get(i)).map(|field| field.trim()) {
+        Some(field) if !field.is_empty() => field
+            .parse()
+            .map(Some)
+            .map_err(|e| invalid_record(line_number, format!("Invalid value {field:?}: {e}"))),
+        _ => Ok(None),
+    }
+}
+
+/// Read a fragment index from comma-separated text made up of `PEPTIDE,sequence,mass`
+/// rows, each followed by the `FRAGMENT,name,mass` rows derived from it. Additional
+/// columns are ignored, and a header row is skipped if it is the first non-empty line.
+///
+/// If the header has `protein_id` and `start_position` columns, they fill in the
+/// [`Peptide::protein_id`] and [`Peptide::start_position`] of each peptide, which are
+/// otherwise zero.
+///
+/// Parents are numbered in the order they appear. The returned index is sorted.
+pub fn read_csv_index<R: io::BufRead>(
+    reader: R,
+    bins_per_dalton: u32,
+    max_item_mass: MassType,
+) -> io::Result<SearchIndex<Fragment, Peptide>> {
+    let mut index = SearchIndex::empty(bins_per_dalton, max_item_mass);
+    let mut parent_id: Option<ParentID> = None;
+    let mut protein_column = None;
+    let mut start_column = None;
+    let mut first_record = true;
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() {
+            continue;
+        }
+        let is_first_record = std::mem::replace(&mut first_record, false);
+        let columns: Vec<&str> = line.split(',').collect();
+        let mut fields = columns.iter().copied();
+        match fields.next().unwrap_or_default() {
+            "PEPTIDE" => {
+                let sequence = fields.next().unwrap_or_default().to_string();
+                let mass = parse_mass(fields.next(), line_number)?;
+                let next_id = parent_id.map(|i| i + 1).unwrap_or_default();
+                let mut peptide = Peptide::builder().mass(mass).id(next_id).sequence(sequence);
+                if let Some(protein_id) = parse_column(&columns, protein_column, line_number)? {
+                    peptide = peptide.protein_id(protein_id);
+                }
+                if let Some(start_position) = parse_column(&columns, start_column, line_number)? {
+                    peptide = peptide.start_position(start_position);
+                }
+                index.add_parent(peptide.build());
+                parent_id = Some(next_id);
+            }
+            "FRAGMENT" => {
+                let current_parent = parent_id.ok_or_else(|| {
+                    invalid_record(line_number, "Fragment found before any peptide".into())
+                })?;
+                let name = fields.next().unwrap_or_default();
+                let name: FragmentName = name.parse().map_err(|e| {
+                    invalid_record(line_number, format!("Invalid fragment name {name:?}: {e}"))
+                })?;
+                let mass = parse_mass(fields.next(), line_number)?;
+                let mut fragment = Fragment::new(mass, current_parent, name.0, name.1);
+                if let Some(charge) = name.3 {
+                    let charge = i8::try_from(charge).map_err(|_| {
+                        invalid_record(line_number, format!("Charge {charge} is out of range"))
+                    })?;
+                    fragment = fragment.with_charge(charge);
+                }
+                index.add(fragment);
+            }
+            _ if is_first_record => {
+                let column_of = |name: &str| columns.iter().position(|c| c.trim() == name);
+                protein_column = column_of("protein_id");
+                start_column = column_of("start_position");
+            }
+            record_type => {
+                return Err(invalid_record(
+                    line_number,
+                    format!("Unknown record type {record_type:?}"),
+                ))
+            }
+        }
+    }
+
+    index.sort(SortType::ByParentId);
+    Ok(index)
+}
@@ -10,14 +10,14 @@ use arrow::array::DictionaryArray;
 use arrow::array::StringArray;
 use arrow::array::UInt16Array;
 use arrow::array::{
-    ArrayRef, AsArray, Float32Array, Float32Builder, Int16Array, Int16Builder, Int32Builder,
-    StringBuilder, StringDictionaryBuilder, UInt16Builder, UInt32Array, UInt32Builder, UInt64Array,
-    UInt64Builder,
+    ArrayRef, AsArray, BinaryBuilder, BooleanBuilder, Float32Array, Float32Builder, Int16Array, Int16Builder, Int32Builder, Int8Builder,
+    StringBuilder, UInt16Builder, UInt32Array, UInt32Builder, UInt64Array,
+    UInt64Builder, UInt8Builder,
 };
 use arrow::datatypes::SchemaRef;
 use arrow::datatypes::Utf8Type;
 use arrow::datatypes::{
-    DataType, Field, Float32Type, Int32Type, Schema, UInt16Type, UInt32Type, UInt8Type,
+    DataType, Field, Float32Type, Int32Type, Int8Type, Schema, UInt16Type, UInt32Type, UInt8Type,
 };
 use arrow::json::{LineDelimitedWriter as JSONArrayLineWriter, ReaderBuilder as JSONReaderBuilder};
 use arrow::record_batch::RecordBatch;
@@ -29,33 +29,45 @@ use parquet::basic::Compression;
 use parquet::basic::ZstdLevel;
 use parquet::{arrow::ArrowWriter, file::properties::*};
 
-use super::util::{ArrowStorage, afield, as_array_ref, field_of};
+use super::util::{
+    afield, as_array_ref, check_batch_schema, field_of, mass_values, ArrowStorage, MassArray,
+    MassArrowType, MassBuilder, MASS_DATA_TYPE,
+};
 use crate::index::SearchIndex;
 use crate::sort::IndexBin;
 use crate::sort::SortType;
+use crate::fragment::{AnnotatedFragment, FragmentSeries};
 use crate::Fragment;
 use crate::Peptide;
 
 pub fn make_fragment_schema() -> Arc<Schema> {
-    let mass = afield!("mass", DataType::Float32);
+    let mass = afield!("mass", MASS_DATA_TYPE);
     let parent_id = afield!("parent_id", DataType::UInt32);
-    let series = afield!(
-        "series",
-        DataType::Dictionary(Box::new(DataType::UInt8), Box::new(DataType::Utf8))
-    );
+    // Stored as `FragmentSeries::to_code`, older files hold dictionary-encoded labels
+    let series = afield!("series", DataType::UInt8);
     let ordinal = afield!("ordinal", DataType::UInt16);
+    let charge = Arc::new(Field::new("charge", DataType::Int8, true));
     let segment_id = afield!("segment_id", DataType::UInt64);
     Arc::new(Schema::new(vec![
-        mass, parent_id, series, ordinal, segment_id,
+        mass, parent_id, series, ordinal, charge, segment_id,
     ]))
 }
 
+/// The fragment schema with a trailing nullable `payload` column of opaque bytes
+pub fn make_annotated_fragment_schema() -> Arc<Schema> {
+    let mut fields: Vec<_> = make_fragment_schema().fields().iter().cloned().collect();
+    fields.push(Arc::new(Field::new("payload", DataType::Binary, true)));
+    Arc::new(Schema::new(fields))
+}
+
 pub fn make_peptide_schema() -> Arc<Schema> {
-    let mass = afield!("mass", DataType::Float32);
+    let mass = afield!("mass", MASS_DATA_TYPE);
     let id = afield!("id", DataType::UInt32);
     let protein_id = afield!("protein_id", DataType::UInt32);
     let start_position = afield!("start_position", DataType::UInt16);
     let sequence = afield!("sequence", DataType::Utf8);
+    let is_decoy = Arc::new(Field::new("is_decoy", DataType::Boolean, true));
+    let group_id = Arc::new(Field::new("group_id", DataType::UInt32, true));
 
     Arc::new(Schema::new(vec![
         mass,
@@ -63,12 +75,14 @@ pub fn make_peptide_schema() -> Arc<Schema> {
         protein_id,
         start_position,
         sequence,
+        is_decoy,
+        group_id,
     ]))
 }
 
 pub fn make_meta_schema() -> Arc<Schema> {
     let bins_per_dalton = afield!("bins_per_dalton", DataType::UInt32);
-    let max_mass = afield!("max_item_mass", DataType::Float32);
+    let max_mass = afield!("max_item_mass", MASS_DATA_TYPE);
     Arc::new(Schema::new(vec![bins_per_dalton, max_mass]))
 }
 
@@ -90,10 +104,7 @@ impl ArrowStorage for Peptide {
     }
 
     fn from_batch<'a>(batch: &'a RecordBatch, schema: SchemaRef) -> impl Iterator<Item=(Self, u64)> + 'a {
-        let mass = batch
-            .column_by_name("mass")
-            .unwrap()
-            .as_primitive::<Float32Type>();
+        let mass = mass_values(field_of!(batch, "mass"));
         let start_position = batch
             .column_by_name("start_position")
             .unwrap()
@@ -104,18 +115,27 @@ impl ArrowStorage for Peptide {
             .as_primitive::<UInt32Type>();
         let sequence = batch.column_by_name("sequence").unwrap().as_string::<i32>();
         let id = batch.column_by_name("id").unwrap().as_primitive::<UInt32Type>();
+        // Files written before decoy marking and grouping were added lack these columns
+        let is_decoy = batch.column_by_name("is_decoy").map(|col| col.as_boolean());
+        let group_id = batch
+            .column_by_name("group_id")
+            .map(|col| col.as_primitive::<UInt32Type>());
         izip!(mass, start_position, protein_id, sequence, id)
-            .map(|(mass, start_position, protein_id, sequence, id)| {
-                (
-                    Peptide::new(
-                        mass.unwrap(),
-                        id.unwrap(),
-                        protein_id.unwrap(),
-                        start_position.unwrap(),
-                        sequence.unwrap().to_string(),
-                    ),
-                    0
-                )
+            .enumerate()
+            .map(move |(i, (mass, start_position, protein_id, sequence, id))| {
+                let mut peptide = Peptide::new(
+                    mass.unwrap(),
+                    id.unwrap(),
+                    protein_id.unwrap(),
+                    start_position.unwrap(),
+                    sequence.unwrap().to_string(),
+                );
+                peptide.is_decoy = is_decoy.is_some_and(|col| col.is_valid(i) && col.value(i));
+                peptide.group_id = group_id
+                    .filter(|col| col.is_valid(i))
+                    .map(|col| col.value(i))
+                    .unwrap_or_default();
+                (peptide, 0)
             })
     }
 
@@ -154,19 +174,58 @@ impl ArrowStorage for Fragment {
         fragment_to_arrow(batch, schema, segment_id)
     }
 
+    fn validate_batch(batch: &RecordBatch) -> io::Result<()> {
+        check_batch_schema(batch, &Self::schema(), &["series"])?;
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let series_col = batch
+            .column_by_name("series")
+            .ok_or_else(|| invalid("Fragment batch is missing the series column".into()))?;
+        match series_col.data_type() {
+            DataType::UInt8 => {
+                for code in series_col.as_primitive::<UInt8Type>().iter() {
+                    if code.and_then(FragmentSeries::from_code).is_none() {
+                        return Err(invalid(format!("Invalid fragment series code {code:?}")));
+                    }
+                }
+            }
+            _ => {
+                let labels = series_col
+                    .as_dictionary_opt::<UInt8Type>()
+                    .and_then(|col| col.downcast_dict::<StringArray>())
+                    .ok_or_else(|| {
+                        invalid(format!("Unsupported fragment series type {}", series_col.data_type()))
+                    })?;
+                for label in labels {
+                    if label.and_then(|label| label.parse::<FragmentSeries>().ok()).is_none() {
+                        return Err(invalid(format!("Invalid fragment series label {label:?}")));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn from_batch<'a>(batch: &'a RecordBatch, schema: SchemaRef) -> impl Iterator<Item=(Self, u64)> + 'a {
-        let mass = field_of!(batch, "mass")
-            .as_any()
-            .downcast_ref::<Float32Array>()
-            .unwrap();
+        let mass = mass_values(field_of!(batch, "mass"));
         let ordinal = field_of!(batch, "ordinal")
             .as_any()
             .downcast_ref::<UInt16Array>()
             .unwrap();
-        let series = field_of!(batch, "series")
-            .as_dictionary::<UInt8Type>()
-            .downcast_dict::<StringArray>()
-            .unwrap();
+        let series_col = field_of!(batch, "series");
+        let series: Vec<FragmentSeries> = match series_col.data_type() {
+            DataType::UInt8 => series_col
+                .as_primitive::<UInt8Type>()
+                .iter()
+                .map(|code| FragmentSeries::from_code(code.unwrap()).unwrap())
+                .collect(),
+            _ => series_col
+                .as_dictionary::<UInt8Type>()
+                .downcast_dict::<StringArray>()
+                .unwrap()
+                .into_iter()
+                .map(|label| label.unwrap().parse().unwrap())
+                .collect(),
+        };
         let parent_id = field_of!(batch, "parent_id")
             .as_any()
             .downcast_ref::<UInt32Array>()
@@ -175,15 +234,22 @@ impl ArrowStorage for Fragment {
             .as_any()
             .downcast_ref::<UInt64Array>()
             .unwrap();
-
-        izip!(mass, series, ordinal, parent_id, segment_id).map(
-            |(mass, series, ordinal, parent_id, segment_id)| {
-                let peak = Fragment::new(
+        // Files written before fragments carried a charge lack this column
+        let charge = batch
+            .column_by_name("charge")
+            .map(|col| col.as_primitive::<Int8Type>());
+
+        izip!(mass, series, ordinal, parent_id, segment_id).enumerate().map(
+            move |(i, (mass, series, ordinal, parent_id, segment_id))| {
+                let mut peak = Fragment::new(
                     mass.unwrap(),
                     parent_id.unwrap(),
-                    series.unwrap().parse().unwrap(),
+                    series,
                     ordinal.unwrap() as u16,
                 );
+                if let Some(charge) = charge.filter(|col| col.is_valid(i)) {
+                    peak.charge = charge.value(i);
+                }
                 (peak, segment_id.unwrap())
             }
         )
@@ -196,15 +262,68 @@ impl ArrowStorage for Fragment {
     }
 }
 
+impl ArrowStorage for AnnotatedFragment {
+    fn schema() -> SchemaRef {
+        make_annotated_fragment_schema()
+    }
+
+    fn mass_column() -> Option<usize> {
+        Self::schema().column_with_name("mass").map(|(i, field)| i)
+    }
+
+    fn parent_id_column() -> Option<usize> {
+        Self::schema().column_with_name("parent_id").map(|(i, field)| i)
+    }
+
+    /// Shared with [`Fragment`], so either kind of index reads the other's entries, plain
+    /// fragments reading as annotated fragments without a payload
+    fn archive_name() -> String {
+        Fragment::archive_name()
+    }
+
+    fn to_batch(batch: &[Self], schema: SchemaRef, segment_id: u64) -> Result<RecordBatch, arrow::error::ArrowError> {
+        let fragments: Vec<Fragment> = batch.iter().map(|f| f.fragment).collect();
+        let fragment_batch = fragment_to_arrow(&fragments, make_fragment_schema(), segment_id)?;
+        let mut payload_builder = BinaryBuilder::new();
+        batch.iter().for_each(|f| payload_builder.append_option(f.payload.as_deref()));
+        let mut columns = fragment_batch.columns().to_vec();
+        columns.push(as_array_ref!(payload_builder));
+        RecordBatch::try_new(schema, columns)
+    }
+
+    fn from_batch<'a>(batch: &'a RecordBatch, schema: SchemaRef) -> impl Iterator<Item=(Self, u64)> + 'a {
+        let payload = batch.column_by_name("payload").map(|col| col.as_binary::<i32>());
+        Fragment::from_batch(batch, make_fragment_schema()).enumerate().map(
+            move |(i, (fragment, segment_id))| {
+                let payload = payload
+                    .filter(|col| col.is_valid(i))
+                    .map(|col| col.value(i).to_vec());
+                (AnnotatedFragment::new(fragment, payload), segment_id)
+            }
+        )
+    }
+
+    fn validate_batch(batch: &RecordBatch) -> io::Result<()> {
+        check_batch_schema(batch, &Self::schema(), &["series"])?;
+        Fragment::validate_batch(batch)
+    }
+
+    fn writer_properties() -> WriterPropertiesBuilder {
+        Fragment::writer_properties()
+    }
+}
+
 pub fn peptide_to_arrow(
     peptides: &[Peptide],
     schema: Arc<Schema>,
 ) -> Result<RecordBatch, arrow::error::ArrowError> {
-    let mut mass_builder = Float32Builder::new();
+    let mut mass_builder = MassBuilder::new();
     let mut sequence_builder = StringBuilder::new();
     let mut protein_id_builder = UInt32Builder::new();
     let mut start_position_builder = UInt16Builder::new();
     let mut id_builder = UInt32Builder::new();
+    let mut is_decoy_builder = BooleanBuilder::new();
+    let mut group_id_builder = UInt32Builder::new();
 
     peptides.iter().for_each(|s| {
         mass_builder.append_value(s.mass);
@@ -212,6 +331,8 @@ pub fn peptide_to_arrow(
         protein_id_builder.append_value(s.protein_id);
         sequence_builder.append_value(s.sequence.clone());
         start_position_builder.append_value(s.start_position);
+        is_decoy_builder.append_value(s.is_decoy);
+        group_id_builder.append_value(s.group_id);
     });
 
     let columns = vec![
@@ -220,6 +341,8 @@ pub fn peptide_to_arrow(
         as_array_ref!(protein_id_builder),
         as_array_ref!(start_position_builder),
         as_array_ref!(sequence_builder),
+        as_array_ref!(is_decoy_builder),
+        as_array_ref!(group_id_builder),
     ];
 
     let batch = RecordBatch::try_new(schema, columns);
@@ -231,16 +354,18 @@ pub fn fragment_to_arrow(
     schema: Arc<Schema>,
     segment_id: u64,
 ) -> Result<RecordBatch, arrow::error::ArrowError> {
-    let mut mass_builder = Float32Builder::new();
-    let mut series_builder = StringDictionaryBuilder::<UInt8Type>::new();
+    let mut mass_builder = MassBuilder::new();
+    let mut series_builder = UInt8Builder::new();
     let mut ordinal_builder = UInt16Builder::new();
+    let mut charge_builder = Int8Builder::new();
     let mut parent_id_builder = UInt32Builder::new();
     let mut segment_id_builder = UInt64Builder::new();
 
     fragments.iter().for_each(|p| {
         mass_builder.append_value(p.mass);
-        series_builder.append_value(p.series.series_name());
+        series_builder.append_value(p.series.to_code());
         ordinal_builder.append_value(p.ordinal);
+        charge_builder.append_value(p.charge);
         parent_id_builder.append_value(p.parent_id);
         segment_id_builder.append_value(segment_id);
     });
@@ -252,6 +377,7 @@ pub fn fragment_to_arrow(
             as_array_ref!(parent_id_builder),
             as_array_ref!(series_builder),
             as_array_ref!(ordinal_builder),
+            as_array_ref!(charge_builder),
             as_array_ref!(segment_id_builder),
         ],
     )
@@ -305,7 +431,7 @@ pub fn write_fragment_index<P: AsRef<Path>>(
     {
         let meta_fh = io::BufWriter::new(fs::File::create(meta_path)?);
         let bins_per_dalton = UInt32Array::from(vec![index.bins_per_dalton]);
-        let max_item_mass = Float32Array::from(vec![index.max_item_mass]);
+        let max_item_mass = MassArray::from(vec![index.max_item_mass]);
         let mut writer = JSONArrayLineWriter::new(meta_fh);
 
         writer
@@ -341,7 +467,7 @@ pub fn read_fragment_index<P: AsRef<Path>>(
     let max_item_mass = meta_rec
         .column_by_name("max_item_mass")
         .unwrap()
-        .as_primitive::<Float32Type>()
+        .as_primitive::<MassArrowType>()
         .into_iter()
         .flatten()
         .next()
@@ -358,38 +484,13 @@ pub fn read_fragment_index<P: AsRef<Path>>(
     let peptides_fh = fs::File::open(peptides_path)?;
 
     let reader = ArrowReaderBuilder::try_new(peptides_fh)?.build()?;
-    let mut peptides: IndexBin<_> = reader
-        .map(|b| {
-            let b = b.unwrap();
-            let mass = b
-                .column_by_name("mass")
-                .unwrap()
-                .as_primitive::<Float32Type>();
-            let start_position = b
-                .column_by_name("start_position")
-                .unwrap()
-                .as_primitive::<UInt16Type>();
-            let protein_id = b
-                .column_by_name("protein_id")
-                .unwrap()
-                .as_primitive::<UInt32Type>();
-            let sequence = b.column_by_name("sequence").unwrap().as_string::<i32>();
-            let id = b.column_by_name("id").unwrap().as_primitive::<UInt32Type>();
-            let b: Vec<_> = izip!(mass, start_position, protein_id, sequence, id)
-                .map(|(mass, start_position, protein_id, sequence, id)| {
-                    Peptide::new(
-                        mass.unwrap(),
-                        id.unwrap(),
-                        protein_id.unwrap(),
-                        start_position.unwrap(),
-                        sequence.unwrap().to_string(),
-                    )
-                })
-                .collect();
-            b.into_iter()
-        })
-        .flatten()
-        .collect();
+    let mut peptides = Vec::new();
+    for b in reader {
+        let b = b.map_err(io::Error::other)?;
+        Peptide::validate_batch(&b)?;
+        peptides.extend(Peptide::from_batch(&b, make_peptide_schema()).map(|(p, _)| p));
+    }
+    let mut peptides: IndexBin<_> = peptides.into_iter().collect();
 
     peptides.sort_type = SortType::ByMass;
 
@@ -397,46 +498,13 @@ pub fn read_fragment_index<P: AsRef<Path>>(
     let fragments_fh = fs::File::open(fragments_path)?;
     let reader = ArrowReaderBuilder::try_new(fragments_fh)?.build()?;
 
-    reader.enumerate().for_each(|(_, b)| {
-        let b = b.unwrap();
-        let mass = field_of!(b, "mass")
-            .as_any()
-            .downcast_ref::<Float32Array>()
-            .unwrap();
-        let ordinal = field_of!(b, "ordinal")
-            .as_any()
-            .downcast_ref::<UInt16Array>()
-            .unwrap();
-        let series = field_of!(b, "series")
-            .as_dictionary::<UInt8Type>()
-            .values()
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .unwrap();
-        let parent_id = field_of!(b, "parent_id")
-            .as_any()
-            .downcast_ref::<UInt32Array>()
-            .unwrap();
-        let segment_id = field_of!(b, "segment_id")
-            .as_any()
-            .downcast_ref::<UInt64Array>()
-            .unwrap();
-
-        izip!(mass, series, ordinal, parent_id, segment_id).for_each(
-            |(mass, series, ordinal, parent_id, segment_id)| {
-                let peak = Fragment::new(
-                    mass.unwrap(),
-                    parent_id.unwrap(),
-                    series.unwrap().parse().unwrap(),
-                    ordinal.unwrap() as u16,
-                );
-                bin_collector
-                    .entry(segment_id.unwrap())
-                    .or_default()
-                    .push(peak);
-            },
-        )
-    });
+    for b in reader {
+        let b = b.map_err(io::Error::other)?;
+        Fragment::validate_batch(&b)?;
+        for (peak, segment_id) in Fragment::from_batch(&b, make_fragment_schema()) {
+            bin_collector.entry(segment_id).or_default().push(peak);
+        }
+    }
 
     let mut index = SearchIndex::empty(bins_per_dalton, max_item_mass);
     index.parents = peptides;
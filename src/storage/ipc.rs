@@ -0,0 +1,89 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use arrow::ipc::{reader::FileReader, writer::FileWriter};
+
+use super::{util::read_metadata_from, ArrowStorage, IndexBinaryStorage};
+use crate::MassFragmentIndexError;
+
+/// The name of the IPC file holding the records of the parquet file `archive_name`
+fn ipc_name(archive_name: &str) -> String {
+    match archive_name.strip_suffix(".parquet") {
+        Some(stem) => format!("{stem}.arrow"),
+        None => format!("{archive_name}.arrow"),
+    }
+}
+
+/// Store an index as Arrow IPC (Feather) files instead of parquet. The parents and entries
+/// use the same schemas and segment ids as the parquet files, but are named `*.arrow`,
+/// while the metadata is the same JSON file [`IndexBinaryStorage::write`] writes.
+///
+/// IPC files are not compressed, so they trade size for lower per-file overhead.
+pub trait IndexIpcStorage<'a, T: ArrowStorage + 'a, P: ArrowStorage, M: ArrowStorage>:
+    IndexBinaryStorage<'a, T, P, M>
+{
+    fn write_ipc<D: AsRef<Path>>(&'a self, directory: &D) -> Result<(), MassFragmentIndexError> {
+        let directory = directory.as_ref();
+        self.write_metadata(directory, None)?;
+
+        let parent_schema = P::schema();
+        let mut writer = FileWriter::try_new(
+            fs::File::create(directory.join(ipc_name(&P::archive_name())))?,
+            &parent_schema,
+        )?;
+        writer.write(&P::to_batch(self.parents(), parent_schema.clone(), 0)?)?;
+        writer.into_inner()?;
+
+        let entries_schema = T::schema();
+        let mut writer = FileWriter::try_new(
+            fs::File::create(directory.join(ipc_name(&T::archive_name())))?,
+            &entries_schema,
+        )?;
+        for (i, bin) in self.iter_entries().enumerate() {
+            if bin.is_empty() {
+                continue;
+            }
+            writer.write(&T::to_batch(bin, entries_schema.clone(), i as u64)?)?;
+        }
+        writer.into_inner()?;
+        Ok(())
+    }
+
+    fn read_ipc<D: AsRef<Path>>(directory: &D) -> Result<Self, MassFragmentIndexError>
+    where
+        Self: Sized,
+    {
+        let directory = directory.as_ref();
+        let metadata: M = read_metadata_from(io::BufReader::new(fs::File::open(
+            directory.join(M::archive_name()),
+        )?))?;
+
+        let parent_schema = P::schema();
+        let reader = FileReader::try_new(
+            io::BufReader::new(fs::File::open(directory.join(ipc_name(&P::archive_name())))?),
+            None,
+        )?;
+        let mut parents = Vec::new();
+        for batch in reader {
+            let batch = batch?;
+            P::validate_batch(&batch)?;
+            parents.extend(P::from_batch(&batch, parent_schema.clone()).map(|(p, _)| p));
+        }
+
+        let entries_schema = T::schema();
+        let reader = FileReader::try_new(
+            io::BufReader::new(fs::File::open(directory.join(ipc_name(&T::archive_name())))?),
+            None,
+        )?;
+        let mut entries: HashMap<u64, Vec<T>> = HashMap::new();
+        for batch in reader {
+            let batch = batch?;
+            T::validate_batch(&batch)?;
+            for (entry, segment_id) in T::from_batch(&batch, entries_schema.clone()) {
+                entries.entry(segment_id).or_default().push(entry);
+            }
+        }
+
+        Self::validate_components(&metadata, &parents, &entries)?;
+        Ok(Self::from_components(metadata, parents, entries))
+    }
+}
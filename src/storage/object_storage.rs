@@ -0,0 +1,26 @@
+use object_store::{path::Path as ObjectPath, ObjectStore};
+
+use super::{ArrowStorage, IndexBinaryStorage, IndexMetadata};
+use crate::{IndexSortable, MassFragmentIndexError, SearchIndex};
+
+/// Read an index whose files were uploaded under `prefix` of an object store, such as
+/// S3 or GCS, as [`SearchIndex::write_parquet`] would have written them to a directory.
+///
+/// Each file is fetched whole before the index is assembled.
+pub async fn read_from_object_store<T, P>(
+    store: &dyn ObjectStore,
+    prefix: &ObjectPath,
+) -> Result<SearchIndex<T, P>, MassFragmentIndexError>
+where
+    T: IndexSortable + Default + ArrowStorage,
+    P: IndexSortable + Default + ArrowStorage,
+{
+    let fetch = |name: String| async move {
+        let location = prefix.child(name);
+        store.get(&location).await?.bytes().await
+    };
+    let metadata = fetch(IndexMetadata::archive_name()).await?;
+    let parents = fetch(P::archive_name()).await?;
+    let entries = fetch(T::archive_name()).await?;
+    SearchIndex::read_from_sources(metadata.as_ref(), parents, entries)
+}
@@ -9,7 +9,7 @@ use arrow::array::UInt32Array;
 use arrow::array::UInt64Array;
 use arrow::array::UInt64Builder;
 use arrow::array::{ArrayRef, AsArray, Float32Builder, Int16Builder, Int32Builder, UInt32Builder};
-use arrow::datatypes::{DataType, Field, Float32Type, Int32Type, Schema, UInt32Type};
+use arrow::datatypes::{DataType, Field, Int32Type, Schema, UInt32Type};
 use arrow::json::{LineDelimitedWriter as JSONArrayLineWriter, ReaderBuilder as JSONReaderBuilder};
 use arrow::record_batch::RecordBatch;
 
@@ -27,10 +27,13 @@ use crate::sort::SortType;
 use crate::{parent::Spectrum, peak::DeconvolutedPeak};
 
 use super::ArrowStorage;
-use super::util::{afield, as_array_ref, field_of};
+use super::util::{
+    afield, as_array_ref, field_of, mass_values, MassArray, MassArrowType, MassBuilder,
+    MASS_DATA_TYPE,
+};
 
 pub fn make_peak_schema() -> Arc<Schema> {
-    let mass = afield!("mass", DataType::Float32);
+    let mass = afield!("mass", MASS_DATA_TYPE);
     let charge = afield!("charge", DataType::Int16);
     let intensity = afield!("intensity", DataType::Float32);
     let scan_ref = afield!("scan_ref", DataType::UInt32);
@@ -41,7 +44,7 @@ pub fn make_peak_schema() -> Arc<Schema> {
 }
 
 pub fn make_spectrum_schema() -> Arc<Schema> {
-    let mass = afield!("precursor_mass", DataType::Float32);
+    let mass = afield!("precursor_mass", MASS_DATA_TYPE);
     let charge = afield!("precursor_charge", DataType::Int32);
     let source_file_id = afield!("source_file_id", DataType::UInt32);
     let scan_number = afield!("scan_number", DataType::UInt32);
@@ -58,7 +61,7 @@ pub fn make_spectrum_schema() -> Arc<Schema> {
 
 pub fn make_meta_schema() -> Arc<Schema> {
     let bins_per_dalton = afield!("bins_per_dalton", DataType::UInt32);
-    let max_mass = afield!("max_item_mass", DataType::Float32);
+    let max_mass = afield!("max_item_mass", MASS_DATA_TYPE);
     Arc::new(Schema::new(vec![bins_per_dalton, max_mass]))
 }
 
@@ -66,7 +69,7 @@ pub fn spectra_to_arrow(
     spectra: &[Spectrum],
     schema: Arc<Schema>,
 ) -> Result<RecordBatch, arrow::error::ArrowError> {
-    let mut mass_builder = Float32Builder::new();
+    let mut mass_builder = MassBuilder::new();
     let mut charge_builder = Int32Builder::new();
     let mut source_file_id_builder = UInt32Builder::new();
     let mut scan_number_builder = UInt32Builder::new();
@@ -97,7 +100,7 @@ pub fn peaks_to_arrow(
     schema: Arc<Schema>,
     segment_id: u64,
 ) -> Result<RecordBatch, arrow::error::ArrowError> {
-    let mut mass_builder = Float32Builder::new();
+    let mut mass_builder = MassBuilder::new();
     let mut charge_builder = Int16Builder::new();
     let mut intensity_builder = Float32Builder::new();
     let mut scan_ref_builder = UInt32Builder::new();
@@ -140,10 +143,7 @@ impl ArrowStorage for DeconvolutedPeak {
         batch: &'a RecordBatch,
         _schema: arrow::datatypes::SchemaRef,
     ) -> impl Iterator<Item = (Self, u64)> + 'a {
-         let mass = field_of!(batch, "mass")
-            .as_any()
-            .downcast_ref::<Float32Array>()
-            .unwrap();
+        let mass = mass_values(field_of!(batch, "mass"));
         let intensity = field_of!(batch, "intensity")
             .as_any()
             .downcast_ref::<Float32Array>()
@@ -213,10 +213,7 @@ impl ArrowStorage for Spectrum {
         batch: &'a RecordBatch,
         _schema: arrow::datatypes::SchemaRef,
     ) -> impl Iterator<Item = (Self, u64)> + 'a {
-        let mass = batch
-            .column_by_name("precursor_mass")
-            .unwrap()
-            .as_primitive::<Float32Type>();
+        let mass = mass_values(field_of!(batch, "precursor_mass"));
         let charge = batch
             .column_by_name("precursor_charge")
             .unwrap()
@@ -315,7 +312,7 @@ pub fn write_peak_index<P: AsRef<Path>>(
         let meta_fh = io::BufWriter::new(fs::File::create(meta_path)?);
         let mut writer = JSONArrayLineWriter::new(meta_fh);
         let bins_per_dalton = UInt32Array::from(vec![index.bins_per_dalton]);
-        let max_item_mass = Float32Array::from(vec![index.max_item_mass]);
+        let max_item_mass = MassArray::from(vec![index.max_item_mass]);
 
         writer
             .write_batches(&vec![&RecordBatch::try_new(
@@ -350,7 +347,7 @@ pub fn read_peak_index<P: AsRef<Path>>(
     let max_item_mass = meta_rec
         .column_by_name("max_item_mass")
         .unwrap()
-        .as_primitive::<Float32Type>()
+        .as_primitive::<MassArrowType>()
         .into_iter()
         .flatten()
         .next()
@@ -367,87 +364,24 @@ pub fn read_peak_index<P: AsRef<Path>>(
     let spectra_fh = fs::File::open(spectra_path)?;
 
     let reader = ArrowReaderBuilder::try_new(spectra_fh)?.build()?;
-    let mut spectra: IndexBin<_> = reader
-        .map(|b| {
-            let b = b.unwrap();
-            let mass = b
-                .column_by_name("precursor_mass")
-                .unwrap()
-                .as_primitive::<Float32Type>();
-            let charge = b
-                .column_by_name("precursor_charge")
-                .unwrap()
-                .as_primitive::<Int32Type>();
-            let source_file_id = b
-                .column_by_name("source_file_id")
-                .unwrap()
-                .as_primitive::<UInt32Type>();
-            let scan_number = b
-                .column_by_name("scan_number")
-                .unwrap()
-                .as_primitive::<UInt32Type>();
-            let sort_id = b
-                .column_by_name("sort_id")
-                .unwrap()
-                .as_primitive::<UInt32Type>();
-            let b: Vec<_> = izip!(mass, charge, source_file_id, scan_number, sort_id)
-                .map(
-                    |(precursor_mass, precursor_charge, source_file_id, scan_number, sort_id)| {
-                        Spectrum::new(
-                            precursor_mass.unwrap(),
-                            precursor_charge.unwrap(),
-                            source_file_id.unwrap(),
-                            scan_number.unwrap(),
-                            sort_id.unwrap(),
-                        )
-                    },
-                )
-                .collect();
-            b.into_iter()
-        })
-        .flatten()
-        .collect();
+    let mut spectra = Vec::new();
+    for b in reader {
+        let b = b.map_err(io::Error::other)?;
+        Spectrum::validate_batch(&b)?;
+        spectra.extend(Spectrum::from_batch(&b, make_spectrum_schema()).map(|(s, _)| s));
+    }
+    let mut spectra: IndexBin<_> = spectra.into_iter().collect();
     spectra.sort_type = SortType::ByMass;
     let mut bin_collector: HashMap<u64, Vec<DeconvolutedPeak>> = HashMap::default();
     let peaks_fh = fs::File::open(peaks_path)?;
     let reader = ArrowReaderBuilder::try_new(peaks_fh)?.build()?;
-    reader.enumerate().for_each(|(_, b)| {
-        let b = b.unwrap();
-        let mass = field_of!(b, "mass")
-            .as_any()
-            .downcast_ref::<Float32Array>()
-            .unwrap();
-        let intensity = field_of!(b, "intensity")
-            .as_any()
-            .downcast_ref::<Float32Array>()
-            .unwrap();
-        let charge = field_of!(b, "charge")
-            .as_any()
-            .downcast_ref::<Int16Array>()
-            .unwrap();
-        let scan_ref = field_of!(b, "scan_ref")
-            .as_any()
-            .downcast_ref::<UInt32Array>()
-            .unwrap();
-        let segment_id = field_of!(b, "segment_id")
-            .as_any()
-            .downcast_ref::<UInt64Array>()
-            .unwrap();
-        izip!(mass, charge, intensity, scan_ref, segment_id).for_each(
-            |(mass, charge, intensity, scan_ref, segment_id)| {
-                let peak = DeconvolutedPeak::new(
-                    mass.unwrap(),
-                    charge.unwrap() as i16,
-                    intensity.unwrap(),
-                    scan_ref.unwrap(),
-                );
-                bin_collector
-                    .entry(segment_id.unwrap())
-                    .or_default()
-                    .push(peak);
-            },
-        )
-    });
+    for b in reader {
+        let b = b.map_err(io::Error::other)?;
+        DeconvolutedPeak::validate_batch(&b)?;
+        for (peak, segment_id) in DeconvolutedPeak::from_batch(&b, make_peak_schema()) {
+            bin_collector.entry(segment_id).or_default().push(peak);
+        }
+    }
 
     let mut index = SearchIndex::empty(bins_per_dalton, max_item_mass);
     index.parents = spectra;
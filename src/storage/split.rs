@@ -7,25 +7,41 @@ use std::{
 
 use arrow::{
     array::{
-        ArrayRef, Float32Array, Float32Builder, RecordBatch, StructArray,
+        ArrayRef, AsArray, RecordBatch, StructArray,
         UInt32Array, UInt32Builder,
     },
-    datatypes::{DataType, Field, Schema, SchemaBuilder},
+    datatypes::{DataType, Field, Schema, SchemaBuilder, UInt32Type},
     json::{LineDelimitedWriter, ReaderBuilder as JSONReaderBuilder},
 };
 use itertools::izip;
 use parquet::{
     arrow::{arrow_reader::ArrowReaderBuilder, ArrowWriter},
-    basic::{Compression, ZstdLevel},
+    basic::Compression,
+    file::properties::WriterProperties,
 };
 
-use crate::{sort::ParentID, IndexSortable, Interval, MassType};
+#[cfg(feature = "parallelism")]
+use rayon::prelude::*;
+
+use crate::{sort::ParentID, IndexSortable, Interval, MassFragmentIndexError, MassType};
 
 use super::{
-    util::{afield, as_array_ref},
-    ArrowStorage, IndexBinaryStorage,
+    util::{
+        afield, as_array_ref, field_of, mass_values, read_entries_from, read_metadata_from, MassBuilder,
+        MASS_DATA_TYPE,
+    },
+    ArrowStorage, IndexBinaryStorage, IndexCompression,
 };
 
+pub(crate) fn split_archive_name_for<T: ArrowStorage>(segment: u32) -> String {
+    let arch_base = T::archive_name();
+    if let Some((prefix, suffix)) = arch_base.rsplit_once('.') {
+        format!("{prefix}_{segment}.{suffix}")
+    } else {
+        format!("{arch_base}_{segment}")
+    }
+}
+
 #[allow(unused)]
 pub trait SplitArrowStorage: ArrowStorage {
     fn split_archive_name_prefix() -> String {
@@ -38,12 +54,7 @@ pub trait SplitArrowStorage: ArrowStorage {
     }
 
     fn split_archive_name_for(segment: u32) -> String {
-        let arch_base = Self::archive_name();
-        if let Some((prefix, suffix)) = arch_base.rsplit_once('.') {
-            format!("{prefix}_{segment}.{suffix}")
-        } else {
-            format!("{arch_base}_{segment}")
-        }
+        split_archive_name_for::<Self>(segment)
     }
 
     fn collect_segments_from_path(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
@@ -95,8 +106,8 @@ impl ArrowStorage for SplitBand {
         let band_id = afield!("band_id", DataType::UInt32);
         let start_id = afield!("start_id", DataType::UInt32);
         let end_id = afield!("end_id", DataType::UInt32);
-        let start_mass = afield!("start_mass", DataType::Float32);
-        let end_mass = afield!("end_mass", DataType::Float32);
+        let start_mass = afield!("start_mass", MASS_DATA_TYPE);
+        let end_mass = afield!("end_mass", MASS_DATA_TYPE);
         Arc::new(Schema::new(vec![
             band_id, start_id, end_id, start_mass, end_mass,
         ]))
@@ -109,8 +120,8 @@ impl ArrowStorage for SplitBand {
         let band_ids: &UInt32Array = batch.column(0).as_any().downcast_ref().unwrap();
         let start_ids: &UInt32Array = batch.column(1).as_any().downcast_ref().unwrap();
         let end_ids: &UInt32Array = batch.column(2).as_any().downcast_ref().unwrap();
-        let start_masses: &Float32Array = batch.column(3).as_any().downcast_ref().unwrap();
-        let end_masses: &Float32Array = batch.column(4).as_any().downcast_ref().unwrap();
+        let start_masses = mass_values(batch.column(3));
+        let end_masses = mass_values(batch.column(4));
 
         izip!(band_ids, start_ids, end_ids, start_masses, end_masses).map(
             |(band_id, start_id, end_id, start_mass, end_mass)| {
@@ -136,8 +147,8 @@ impl ArrowStorage for SplitBand {
         let mut band_ids = UInt32Builder::new();
         let mut start_ids = UInt32Builder::new();
         let mut end_ids = UInt32Builder::new();
-        let mut start_masses = Float32Builder::new();
-        let mut end_masses = Float32Builder::new();
+        let mut start_masses = MassBuilder::new();
+        let mut end_masses = MassBuilder::new();
 
         for item in batch {
             band_ids.append_value(item.band_id);
@@ -213,62 +224,123 @@ pub trait SplitIndexBinaryStorage<
         bands
     }
 
+    fn split_writer_properties(compression_level: &Compression) -> WriterProperties {
+        T::writer_properties()
+            .set_compression(*compression_level)
+            .set_column_encoding("band_id".into(), parquet::basic::Encoding::RLE)
+            .build()
+    }
+
+    fn write_band_entries<W: io::Write + Send>(
+        &'a self,
+        writer: &mut ArrowWriter<W>,
+        band: &SplitBand,
+    ) -> Result<(), MassFragmentIndexError> {
+        let entries_schema = T::schema();
+        let ext_schema = Self::make_item_schema();
+        let interval = Interval::new(band.start_id as usize, band.end_id as usize + 1);
+        for (i, bin) in self.iter_entries().enumerate() {
+            let entries_of: Vec<T> = bin
+                .iter()
+                .filter(|b| interval.contains(b.parent_id() as usize))
+                .cloned()
+                .collect();
+            if entries_of.is_empty() {
+                continue;
+            }
+
+            let batch = T::to_batch(&entries_of, entries_schema.clone(), i as u64)?;
+            let (_fields, mut arrays, _null_buffer) = StructArray::from(batch).into_parts();
+            let band_id_col = vec![band.band_id; entries_of.len()];
+            let band_id_col = Arc::new(UInt32Array::from(band_id_col));
+            arrays.push(band_id_col);
+            let batch = RecordBatch::try_new(ext_schema.clone(), arrays)?;
+
+            writer.write(&batch)?;
+        }
+        Ok(())
+    }
+
     fn write_entries_split(
         &'a self,
         directory: &Path,
         bands: &[SplitBand],
         compression_level: &Compression,
-    ) -> io::Result<()> {
+    ) -> Result<(), MassFragmentIndexError> {
         let entries_path = directory.join(T::archive_name());
-        let entries_schema = T::schema();
-        let props = T::writer_properties()
-            .set_compression(compression_level.clone())
-            .set_column_encoding("band_id".into(), parquet::basic::Encoding::RLE)
-            .build();
-        let ext_schema = Self::make_item_schema();
         let mut writer = ArrowWriter::try_new(
             fs::File::create(entries_path)?,
-            ext_schema.clone(),
-            Some(props),
+            Self::make_item_schema(),
+            Some(Self::split_writer_properties(compression_level)),
         )?;
         for band in bands.iter() {
-            let interval = Interval::new(band.start_id as usize, band.end_id as usize + 1);
-            for (i, bin) in self.iter_entries().enumerate() {
-                let entries_of: Vec<T> = bin
-                    .iter()
-                    .filter(|b| interval.contains(b.parent_id() as usize))
-                    .cloned()
-                    .collect();
-
-                let batch = T::to_batch(&entries_of, entries_schema.clone(), i as u64).unwrap();
-                let (_fields, mut arrays, _null_buffer) = StructArray::from(batch).into_parts();
-                let band_id_col = vec![band.band_id; entries_of.len()];
-                let band_id_col = Arc::new(UInt32Array::from(band_id_col));
-                arrays.push(band_id_col);
-                let batch = RecordBatch::try_new(ext_schema.clone(), arrays).unwrap();
-
-                writer.write(&batch)?;
-            }
+            self.write_band_entries(&mut writer, band)?;
         }
         writer.close()?;
         Ok(())
     }
 
+    /// Write each band's entries to its own file concurrently instead of into a
+    /// single entries file. [`SplitIndexBinaryStorage::read_split`] reads either layout.
+    #[cfg(feature = "parallelism")]
+    fn par_write_entries_split(
+        &'a self,
+        directory: &Path,
+        bands: &[SplitBand],
+        compression_level: &Compression,
+    ) -> Result<(), MassFragmentIndexError>
+    where
+        Self: Sync,
+    {
+        bands.par_iter().try_for_each(|band| -> Result<(), MassFragmentIndexError> {
+            let band_path = directory.join(split_archive_name_for::<T>(band.band_id));
+            let mut writer = ArrowWriter::try_new(
+                fs::File::create(band_path)?,
+                Self::make_item_schema(),
+                Some(Self::split_writer_properties(compression_level)),
+            )?;
+            self.write_band_entries(&mut writer, band)?;
+            writer.close()?;
+            Ok(())
+        })
+    }
+
     fn write_split<D: AsRef<Path>>(
         &'a self,
         directory: &D,
         bin_width: MassType,
-        compression_level: Option<Compression>,
-    ) -> io::Result<()> {
+        compression_level: Option<IndexCompression>,
+    ) -> Result<(), MassFragmentIndexError> {
         let directory = directory.as_ref();
 
-        let compression_level =
-            compression_level.unwrap_or_else(|| Compression::ZSTD(ZstdLevel::try_new(9).unwrap()));
+        let compression_level = compression_level.unwrap_or_default();
         let bands = self.compute_parent_bands(bin_width);
 
-        self.write_metadata(directory)?;
-        self.write_parents(directory, &compression_level)?;
-        self.write_entries_split(directory, &bands, &compression_level)?;
+        self.write_metadata(directory, None)?;
+        self.write_parents(directory, &compression_level.parents, None)?;
+        self.write_entries_split(directory, &bands, &compression_level.entries)?;
+        self.write_split_log(directory, &bands)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "parallelism")]
+    fn par_write_split<D: AsRef<Path>>(
+        &'a self,
+        directory: &D,
+        bin_width: MassType,
+        compression_level: Option<IndexCompression>,
+    ) -> Result<(), MassFragmentIndexError>
+    where
+        Self: Sync,
+    {
+        let directory = directory.as_ref();
+
+        let compression_level = compression_level.unwrap_or_default();
+        let bands = self.compute_parent_bands(bin_width);
+
+        self.write_metadata(directory, None)?;
+        self.write_parents(directory, &compression_level.parents, None)?;
+        self.par_write_entries_split(directory, &bands, &compression_level.entries)?;
         self.write_split_log(directory, &bands)?;
         Ok(())
     }
@@ -278,19 +350,19 @@ pub trait SplitIndexBinaryStorage<
         prefix
     }
 
-    fn write_split_log(&self, directory: &Path, split_log: &[SplitBand]) -> io::Result<()> {
+    fn write_split_log(&self, directory: &Path, split_log: &[SplitBand]) -> Result<(), MassFragmentIndexError> {
         let split_log_path = directory.join(Self::band_log_name());
 
         let split_log_fh = io::BufWriter::new(fs::File::create(split_log_path)?);
         let mut writer = LineDelimitedWriter::new(split_log_fh);
-        let split_log = SplitBand::to_batch(split_log, SplitBand::schema(), 0).unwrap();
+        let split_log = SplitBand::to_batch(split_log, SplitBand::schema(), 0)?;
 
-        writer.write(&split_log).unwrap();
-        writer.finish().unwrap();
+        writer.write(&split_log)?;
+        writer.finish()?;
         Ok(())
     }
 
-    fn read_split<D: AsRef<Path>>(directory: &D) -> io::Result<Self>
+    fn read_split<D: AsRef<Path>>(directory: &D) -> Result<Self, MassFragmentIndexError>
     where
         Self: Sized,
     {
@@ -298,46 +370,94 @@ pub trait SplitIndexBinaryStorage<
 
         let metadata = Self::read_metadata(root)?;
         let parents = Self::read_parents(root)?;
-        // let split_log = Self::read_split_log(root)?;
 
-        let entries = {
+        let entries_path = root.join(T::archive_name());
+        let entries = if entries_path.exists() {
+            read_entries_from(fs::File::open(entries_path)?, None)?
+        } else {
+            Self::read_band_files(root, &Self::read_split_log(root)?)?
+        };
+
+        Self::validate_components(&metadata, &parents, &entries)?;
+        let this = Self::from_components(metadata, parents, entries);
+        Ok(this)
+    }
+
+    /// Read the per-band entry files written by `par_write_split` for `bands`
+    fn read_band_files(directory: &Path, bands: &[SplitBand]) -> Result<HashMap<u64, Vec<T>>, MassFragmentIndexError> {
+        let mut bin_collector: HashMap<u64, Vec<T>> = HashMap::default();
+        for band in bands {
+            let band_path = directory.join(split_archive_name_for::<T>(band.band_id));
+            for (segment_id, bin) in read_entries_from::<T, _>(fs::File::open(band_path)?, None)? {
+                bin_collector.entry(segment_id).or_default().extend(bin);
+            }
+        }
+        Ok(bin_collector)
+    }
+
+    /// The bands whose parent mass range overlaps `[low, high]`
+    fn bands_for(directory: &Path, low: MassType, high: MassType) -> Result<Vec<SplitBand>, MassFragmentIndexError> {
+        Ok(Self::read_split_log(directory)?
+            .into_iter()
+            .filter(|band| band.start_mass <= high && band.end_mass >= low)
+            .collect())
+    }
+
+    /// Read every parent but only the entries belonging to bands whose parent mass range
+    /// overlaps `[low, high]`. Searches restricted to parents in that range find the same
+    /// hits as against the full index, while other parents have no entries.
+    ///
+    /// With the per-band layout only the overlapping band files are opened.
+    fn read_bands_for<D: AsRef<Path>>(directory: &D, low: MassType, high: MassType) -> Result<Self, MassFragmentIndexError>
+    where
+        Self: Sized,
+    {
+        let root = directory.as_ref();
+
+        let metadata = Self::read_metadata(root)?;
+        let parents = Self::read_parents(root)?;
+        let bands = Self::bands_for(root, low, high)?;
+
+        let entries_path = root.join(T::archive_name());
+        let entries = if entries_path.exists() {
+            let band_ids: Vec<ParentID> = bands.iter().map(|band| band.band_id).collect();
             let mut bin_collector: HashMap<u64, Vec<T>> = HashMap::default();
-            let entries_fh = fs::File::open(root.join(T::archive_name()))?;
-            let reader = ArrowReaderBuilder::try_new(entries_fh)?.build()?;
+            let reader = ArrowReaderBuilder::try_new(fs::File::open(entries_path)?)?.build()?;
             let entry_schema = T::schema();
-
             for batch in reader {
-                for (entry, segment_id) in T::from_batch(&batch.unwrap(), entry_schema.clone()) {
-                    bin_collector.entry(segment_id).or_default().push(entry);
+                let batch = batch?;
+                T::validate_batch(&batch)?;
+                let batch_band_ids = field_of!(batch, "band_id").as_primitive::<UInt32Type>();
+                for ((entry, segment_id), band_id) in T::from_batch(&batch, entry_schema.clone())
+                    .zip(batch_band_ids.iter())
+                {
+                    if band_id.is_some_and(|i| band_ids.contains(&i)) {
+                        bin_collector.entry(segment_id).or_default().push(entry);
+                    }
                 }
             }
-
             bin_collector
+        } else {
+            Self::read_band_files(root, &bands)?
         };
 
-        let this = Self::from_components(metadata, parents, entries);
-        Ok(this)
+        Self::check_segments(&metadata, &entries)?;
+        Ok(Self::from_components(metadata, parents, entries))
     }
 
-    fn read_split_log(directory: &Path) -> io::Result<Vec<SplitBand>> {
+    fn read_split_log(directory: &Path) -> Result<Vec<SplitBand>, MassFragmentIndexError> {
         let split_log_path = directory.join(Self::band_log_name());
         let split_log_fh = io::BufReader::new(fs::File::open(split_log_path)?);
 
-        let split_log = JSONReaderBuilder::new(SplitBand::schema())
-            .build(split_log_fh)
-            .unwrap()
-            .map(|batch| {
-                let batch: Vec<_> = SplitBand::from_batch(&batch.unwrap(), SplitBand::schema())
-                    .map(|(x, _)| x)
-                    .collect();
-                batch
-            })
-            .flatten()
-            .collect();
+        let reader = JSONReaderBuilder::new(SplitBand::schema()).build(split_log_fh)?;
+        let mut split_log = Vec::new();
+        for batch in reader {
+            split_log.extend(SplitBand::from_batch(&batch?, SplitBand::schema()).map(|(x, _)| x));
+        }
         Ok(split_log)
     }
 
-    fn read_parents(directory: &Path) -> io::Result<Vec<P>> {
+    fn read_parents(directory: &Path) -> Result<Vec<P>, MassFragmentIndexError> {
         let parents_path = directory.join(P::archive_name());
         let parent_schema = P::schema();
         let parents_fh = fs::File::open(parents_path)?;
@@ -345,30 +465,17 @@ pub trait SplitIndexBinaryStorage<
         let reader = ArrowReaderBuilder::try_new(parents_fh)?.build()?;
         let mut parents = Vec::new();
         for batch in reader {
-            parents.extend(P::from_batch(&batch.unwrap(), parent_schema.clone()).map(|(p, _)| p));
+            let batch = batch?;
+            P::validate_batch(&batch)?;
+            parents.extend(P::from_batch(&batch, parent_schema.clone()).map(|(p, _)| p));
         }
 
         Ok(parents)
     }
 
-    fn read_metadata(directory: &Path) -> io::Result<M> {
+    fn read_metadata(directory: &Path) -> Result<M, MassFragmentIndexError> {
         let meta_path = directory.join(M::archive_name());
 
-        let metadata = {
-            let meta_schema = M::schema();
-            let meta_fh = io::BufReader::new(fs::File::open(meta_path)?);
-            let meta_rec = JSONReaderBuilder::new(meta_schema.clone())
-                .build(meta_fh)
-                .unwrap()
-                .next()
-                .unwrap()
-                .unwrap();
-
-            let (metadata, _) = M::from_batch(&meta_rec, meta_schema.clone())
-                .next()
-                .unwrap();
-            metadata
-        };
-        Ok(metadata)
+        read_metadata_from(io::BufReader::new(fs::File::open(meta_path)?))
     }
 }
@@ -0,0 +1,161 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use arrow::datatypes::SchemaRef;
+use parquet::arrow::ArrowWriter;
+
+use crate::index::{bin_count_for, bin_index_for};
+use crate::sort::{IndexSortable, MassType, SortType};
+
+use super::util::{
+    existing_build_id, new_build_id, remove_appended_segments, with_build_id, write_metadata_to,
+    write_parents_to, ArrowStorage, IndexCompression, IndexMetadata,
+};
+
+/// Write an index to disk incrementally without holding every bin in memory.
+///
+/// Entries must be added in ascending mass order, at least to the resolution of a bin. Each
+/// time an entry lands in a new bin, the previous bin is sorted by parent id and flushed to
+/// the entries archive. Parents must be added in ascending mass order too, since entries
+/// refer to them by position and readers search them by mass. They are buffered and
+/// written along with the metadata when
+/// [`SearchIndexWriter::finish`] is called, producing the same layout as
+/// [`SearchIndex::write_parquet`](crate::SearchIndex::write_parquet).
+pub struct SearchIndexWriter<T: ArrowStorage + IndexSortable, P: ArrowStorage + IndexSortable> {
+    directory: PathBuf,
+    metadata: IndexMetadata,
+    replaced_build_id: Option<u64>,
+    compression_level: IndexCompression,
+    num_bins: usize,
+    parents: Vec<P>,
+    entries_schema: SchemaRef,
+    entries_writer: ArrowWriter<fs::File>,
+    current_bin: usize,
+    buffer: Vec<T>,
+}
+
+impl<T: ArrowStorage + IndexSortable, P: ArrowStorage + IndexSortable> SearchIndexWriter<T, P> {
+    pub fn new<D: AsRef<Path>>(
+        directory: &D,
+        bins_per_dalton: u32,
+        max_item_mass: MassType,
+        compression_level: Option<IndexCompression>,
+    ) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let compression_level = compression_level.unwrap_or_default();
+        let replaced_build_id = existing_build_id(&directory);
+
+        let mut metadata = IndexMetadata::new(bins_per_dalton, max_item_mass);
+        metadata.build_id = new_build_id();
+        metadata.sort_type = SortType::ByParentId;
+
+        let entries_schema = T::schema();
+        let props = with_build_id(T::writer_properties(), Some(metadata.build_id))
+            .set_compression(compression_level.entries)
+            .build();
+        let entries_writer = ArrowWriter::try_new(
+            fs::File::create(directory.join(T::archive_name()))?,
+            entries_schema.clone(),
+            Some(props),
+        )?;
+
+        Ok(Self {
+            directory,
+            metadata,
+            replaced_build_id,
+            compression_level,
+            num_bins: bin_count_for(bins_per_dalton, max_item_mass),
+            parents: Vec::new(),
+            entries_schema,
+            entries_writer,
+            current_bin: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Buffer `parent`, which must not be lighter than the parent added before it
+    pub fn add_parent(&mut self, parent: P) -> io::Result<()> {
+        if !parent.mass().is_finite() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Parent with non-finite mass {} cannot be written", parent.mass()),
+            ));
+        }
+        if let Some(last) = self.parents.last() {
+            if parent.mass() < last.mass() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Parent with mass {} is lighter than the previous parent with mass {}, parents must be added in ascending mass order",
+                        parent.mass(),
+                        last.mass()
+                    ),
+                ));
+            }
+        }
+        self.parents.push(parent);
+        Ok(())
+    }
+
+    pub fn add(&mut self, entry: T) -> io::Result<()> {
+        if !entry.mass().is_finite() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Entry with non-finite mass {} cannot be written", entry.mass()),
+            ));
+        }
+        let bin_index = bin_index_for(entry.mass(), self.metadata.bins_per_dalton, self.num_bins);
+        if bin_index < self.current_bin {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Entry with mass {} belongs to bin {bin_index} but bin {} has already been written, entries must be added in ascending mass order",
+                    entry.mass(),
+                    self.current_bin
+                ),
+            ));
+        }
+        if bin_index > self.current_bin {
+            self.flush_bin()?;
+            self.current_bin = bin_index;
+        }
+        self.buffer.push(entry);
+        self.metadata.num_entries += 1;
+        Ok(())
+    }
+
+    fn flush_bin(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.buffer.sort_by_key(|e| e.parent_id());
+        let batch = T::to_batch(
+            &self.buffer,
+            self.entries_schema.clone(),
+            self.current_bin as u64,
+        )
+        .map_err(io::Error::other)?;
+        self.entries_writer.write(&batch)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush_bin()?;
+        self.entries_writer.close()?;
+        write_parents_to(
+            &self.parents,
+            &self.directory,
+            &self.compression_level.parents,
+            Some(self.metadata.build_id),
+        )?;
+        self.metadata.num_parents = self.parents.len() as u64;
+        write_metadata_to(self.metadata, &self.directory)?;
+        if let Some(build_id) = self.replaced_build_id {
+            remove_appended_segments(&self.directory, build_id)?;
+        }
+        Ok(())
+    }
+}
@@ -1,24 +1,66 @@
 use std::{
-    collections::HashMap,
-    fs, io,
+    collections::{hash_map::RandomState, HashMap},
+    fs,
+    hash::BuildHasher,
+    io,
     marker::PhantomData,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
 use arrow::{
-    array::{ArrayRef, AsArray, Float32Array, RecordBatch, UInt32Array},
-    datatypes::{DataType, Field, Float32Type, Schema, SchemaRef, UInt32Type},
+    array::{ArrayRef, AsArray, RecordBatch, StringArray, UInt32Array, UInt64Array},
+    datatypes::{DataType, Field, Schema, SchemaRef, UInt32Type, UInt64Type},
     error::ArrowError,
     json::{LineDelimitedWriter, ReaderBuilder as JSONReaderBuilder},
 };
 use parquet::{
-    arrow::{arrow_reader::ArrowReaderBuilder, ArrowWriter},
+    arrow::{
+        arrow_reader::{ArrowReaderBuilder, ParquetRecordBatchReader},
+        ArrowWriter,
+    },
     basic::{Compression, ZstdLevel},
-    file::properties::{WriterProperties, WriterPropertiesBuilder},
+    file::{
+        metadata::{KeyValue, ParquetMetaData},
+        properties::{WriterProperties, WriterPropertiesBuilder},
+        reader::ChunkReader,
+        statistics::Statistics,
+    },
 };
 
-use crate::{IndexSortable, Interval, MassType, SearchIndex, Tolerance};
+use crate::{
+    index::BinSchedule,
+    sort::{IndexBin, SortType},
+    IndexSortable, Interval, MassFragmentIndexError, MassType, SearchIndex, Tolerance,
+};
+
+#[cfg(not(feature = "high_precision"))]
+mod mass_types {
+    use arrow::{
+        array::{Float32Array, Float32Builder},
+        datatypes::{DataType, Float32Type},
+    };
+
+    pub type MassArrowType = Float32Type;
+    pub type MassArray = Float32Array;
+    pub type MassBuilder = Float32Builder;
+    pub const MASS_DATA_TYPE: DataType = DataType::Float32;
+}
+
+#[cfg(feature = "high_precision")]
+mod mass_types {
+    use arrow::{
+        array::{Float64Array, Float64Builder},
+        datatypes::{DataType, Float64Type},
+    };
+
+    pub type MassArrowType = Float64Type;
+    pub type MassArray = Float64Array;
+    pub type MassBuilder = Float64Builder;
+    pub const MASS_DATA_TYPE: DataType = DataType::Float64;
+}
+
+pub(crate) use mass_types::{MassArray, MassArrowType, MassBuilder, MASS_DATA_TYPE};
 
 pub trait ArrowStorage: Sized {
     fn schema() -> SchemaRef;
@@ -49,12 +91,140 @@ pub trait ArrowStorage: Sized {
     fn sort_id_column() -> Option<usize> {
         None
     }
+
+    /// Check that a freshly read record can be used by this version of the library
+    fn validate(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Check that a freshly read batch can be decoded by [`ArrowStorage::from_batch`]
+    fn validate_batch(batch: &RecordBatch) -> io::Result<()> {
+        check_batch_schema(batch, &Self::schema(), &[])
+    }
+
+    /// The id shared by the files written together with this metadata record, if any
+    fn build_id(&self) -> Option<u64> {
+        None
+    }
+
+    fn set_build_id(&mut self, _build_id: u64) {}
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+/// The build parameters and record counts written alongside an index's parents and entries.
+///
+/// **Breaking change:** this type is no longer `Copy`, because
+/// [`IndexMetadata::bin_schedule`] holds a variable number of regions. Code that copied a
+/// metadata record must call `clone` instead.
+#[derive(Debug, Default, Clone)]
 pub struct IndexMetadata {
     pub bins_per_dalton: u32,
     pub max_item_mass: MassType,
+    /// The storage format version the index was written with. Files written before
+    /// versioning was introduced read as version 0.
+    pub format_version: u32,
+    /// The number of entries written, checked on read from format version 2 onward
+    pub num_entries: u64,
+    /// The number of parents written, checked on read from format version 2 onward
+    pub num_parents: u64,
+    /// A random id also stored in the parents and entries files written alongside this
+    /// metadata, or zero if none was recorded
+    pub build_id: u64,
+    /// The order of the entries within each bin. Files written before this was recorded
+    /// were always sorted by parent id.
+    pub sort_type: SortType,
+    /// The variable bin resolution the index was built with, recorded from format
+    /// version 3 onward. `None` means uniform binning at `bins_per_dalton`.
+    pub bin_schedule: Option<BinSchedule>,
+    /// For a segment added with [`SearchIndex::append_parquet`](crate::SearchIndex::append_parquet),
+    /// the build id of the index it was appended to, or zero otherwise
+    pub base_build_id: u64,
+}
+
+impl IndexMetadata {
+    /// The newest storage format version this library can read and the one it writes
+    pub const FORMAT_VERSION: u32 = 3;
+
+    pub fn new(bins_per_dalton: u32, max_item_mass: MassType) -> Self {
+        Self {
+            bins_per_dalton,
+            max_item_mass,
+            format_version: Self::FORMAT_VERSION,
+            num_entries: 0,
+            num_parents: 0,
+            build_id: 0,
+            sort_type: SortType::Unsorted,
+            bin_schedule: None,
+            base_build_id: 0,
+        }
+    }
+
+    /// Check the number of parents and entries actually loaded against the counts
+    /// recorded at write time, catching truncated or partially copied files.
+    pub fn check_counts(&self, num_parents: usize, num_entries: usize) -> io::Result<()> {
+        if self.format_version < 2 {
+            return Ok(());
+        }
+        if self.num_parents != num_parents as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Expected {} parents but read {num_parents}",
+                    self.num_parents
+                ),
+            ));
+        }
+        if self.num_entries != num_entries as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Expected {} entries but read {num_entries}",
+                    self.num_entries
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn sort_type_name(sort_type: SortType) -> &'static str {
+    match sort_type {
+        SortType::ByMass => "ByMass",
+        SortType::ByParentId => "ByParentId",
+        SortType::Unsorted => "Unsorted",
+    }
+}
+
+/// Encode a bin schedule as comma separated `start_mass:bins_per_dalton` pairs
+fn bin_schedule_to_string(schedule: &BinSchedule) -> String {
+    schedule
+        .regions()
+        .iter()
+        .map(|(start, bins_per_dalton)| format!("{start}:{bins_per_dalton}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Decode a bin schedule, leaving it empty if the text is malformed so that
+/// [`IndexMetadata::validate`] rejects it
+fn bin_schedule_from_string(text: &str) -> BinSchedule {
+    let regions = text
+        .split(',')
+        .map(|region| {
+            let (start, bins_per_dalton) = region.split_once(':')?;
+            Some((start.parse().ok()?, bins_per_dalton.parse().ok()?))
+        })
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default();
+    BinSchedule::from_regions_unchecked(regions)
+}
+
+fn sort_type_from_name(name: &str) -> SortType {
+    match name {
+        "ByMass" => SortType::ByMass,
+        "ByParentId" => SortType::ByParentId,
+        // Don't assume an ordering the reader can't verify
+        _ => SortType::Unsorted,
+    }
 }
 
 macro_rules! afield {
@@ -79,11 +249,72 @@ pub(crate) use afield;
 pub(crate) use as_array_ref;
 pub(crate) use field_of;
 
+/// The values of a mass column as [`MassType`], converting masses written by a build
+/// with the other `high_precision` setting
+pub(crate) fn mass_values(column: &ArrayRef) -> Vec<Option<MassType>> {
+    let column = arrow::compute::cast(column, &MASS_DATA_TYPE)
+        .expect("mass column type should be checked by ArrowStorage::validate_batch");
+    column.as_primitive::<MassArrowType>().iter().collect()
+}
+
+/// Check that `batch` holds every column of `schema` that `from_batch` would decode, except
+/// those in `skip`. Nullable columns may be missing from files written by older versions,
+/// and masses may be stored at either floating point precision.
+pub(crate) fn check_batch_schema(
+    batch: &RecordBatch,
+    schema: &Schema,
+    skip: &[&str],
+) -> io::Result<()> {
+    for field in schema.fields().iter().filter(|f| !skip.contains(&f.name().as_str())) {
+        let Some(column) = batch.column_by_name(field.name()) else {
+            if field.is_nullable() {
+                continue;
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Record batch is missing the {} column", field.name()),
+            ));
+        };
+        let compatible = column.data_type() == field.data_type()
+            || (field.data_type() == &MASS_DATA_TYPE
+                && matches!(column.data_type(), DataType::Float32 | DataType::Float64));
+        if !compatible {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Expected the {} column to be {} but found {}",
+                    field.name(),
+                    field.data_type(),
+                    column.data_type()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
 impl ArrowStorage for IndexMetadata {
     fn schema() -> SchemaRef {
         let bins_per_dalton = afield!("bins_per_dalton", DataType::UInt32);
-        let max_mass = afield!("max_item_mass", DataType::Float32);
-        Arc::new(Schema::new(vec![bins_per_dalton, max_mass]))
+        let max_mass = afield!("max_item_mass", MASS_DATA_TYPE);
+        let format_version = Arc::new(Field::new("format_version", DataType::UInt32, true));
+        let num_entries = Arc::new(Field::new("num_entries", DataType::UInt64, true));
+        let num_parents = Arc::new(Field::new("num_parents", DataType::UInt64, true));
+        let build_id = Arc::new(Field::new("build_id", DataType::UInt64, true));
+        let sort_type = Arc::new(Field::new("sort_type", DataType::Utf8, true));
+        let bin_schedule = Arc::new(Field::new("bin_schedule", DataType::Utf8, true));
+        let base_build_id = Arc::new(Field::new("base_build_id", DataType::UInt64, true));
+        Arc::new(Schema::new(vec![
+            bins_per_dalton,
+            max_mass,
+            format_version,
+            num_entries,
+            num_parents,
+            build_id,
+            sort_type,
+            bin_schedule,
+            base_build_id,
+        ]))
     }
 
     fn from_batch<'a>(
@@ -93,7 +324,7 @@ impl ArrowStorage for IndexMetadata {
         let max_item_mass = batch
             .column_by_name("max_item_mass")
             .unwrap()
-            .as_primitive::<Float32Type>()
+            .as_primitive::<MassArrowType>()
             .into_iter()
             .flatten()
             .next()
@@ -106,9 +337,50 @@ impl ArrowStorage for IndexMetadata {
             .flatten()
             .next()
             .unwrap();
+        let format_version = batch
+            .column_by_name("format_version")
+            .and_then(|col| {
+                col.as_primitive::<UInt32Type>()
+                    .into_iter()
+                    .flatten()
+                    .next()
+            })
+            .unwrap_or_default();
+        let num_entries = batch
+            .column_by_name("num_entries")
+            .and_then(|col| col.as_primitive::<UInt64Type>().into_iter().flatten().next())
+            .unwrap_or_default();
+        let num_parents = batch
+            .column_by_name("num_parents")
+            .and_then(|col| col.as_primitive::<UInt64Type>().into_iter().flatten().next())
+            .unwrap_or_default();
+        let build_id = batch
+            .column_by_name("build_id")
+            .and_then(|col| col.as_primitive::<UInt64Type>().into_iter().flatten().next())
+            .unwrap_or_default();
+        let sort_type = batch
+            .column_by_name("sort_type")
+            .and_then(|col| col.as_string::<i32>().into_iter().flatten().next())
+            .map(sort_type_from_name)
+            .unwrap_or(SortType::ByParentId);
+        let bin_schedule = batch
+            .column_by_name("bin_schedule")
+            .and_then(|col| col.as_string::<i32>().into_iter().flatten().next())
+            .map(bin_schedule_from_string);
+        let base_build_id = batch
+            .column_by_name("base_build_id")
+            .and_then(|col| col.as_primitive::<UInt64Type>().into_iter().flatten().next())
+            .unwrap_or_default();
         let this = Self {
             max_item_mass,
             bins_per_dalton,
+            format_version,
+            num_entries,
+            num_parents,
+            build_id,
+            sort_type,
+            bin_schedule,
+            base_build_id,
         };
         [(this, 0)].into_iter()
     }
@@ -120,12 +392,27 @@ impl ArrowStorage for IndexMetadata {
     ) -> Result<RecordBatch, ArrowError> {
         let this = batch.first().unwrap();
         let bins_per_dalton = UInt32Array::from(vec![this.bins_per_dalton]);
-        let max_item_mass = Float32Array::from(vec![this.max_item_mass]);
+        let max_item_mass = MassArray::from(vec![this.max_item_mass]);
+        let format_version = UInt32Array::from(vec![this.format_version]);
+        let num_entries = UInt64Array::from(vec![this.num_entries]);
+        let num_parents = UInt64Array::from(vec![this.num_parents]);
+        let build_id = UInt64Array::from(vec![this.build_id]);
+        let sort_type = StringArray::from(vec![sort_type_name(this.sort_type)]);
+        let bin_schedule =
+            StringArray::from(vec![this.bin_schedule.as_ref().map(bin_schedule_to_string)]);
+        let base_build_id = UInt64Array::from(vec![this.base_build_id]);
         RecordBatch::try_new(
             schema.clone(),
             vec![
                 Arc::new(bins_per_dalton) as ArrayRef,
                 Arc::new(max_item_mass) as ArrayRef,
+                Arc::new(format_version) as ArrayRef,
+                Arc::new(num_entries) as ArrayRef,
+                Arc::new(num_parents) as ArrayRef,
+                Arc::new(build_id) as ArrayRef,
+                Arc::new(sort_type) as ArrayRef,
+                Arc::new(bin_schedule) as ArrayRef,
+                Arc::new(base_build_id) as ArrayRef,
             ],
         )
     }
@@ -137,71 +424,432 @@ impl ArrowStorage for IndexMetadata {
     fn writer_properties() -> WriterPropertiesBuilder {
         WriterProperties::builder()
     }
+
+    fn validate(&self) -> io::Result<()> {
+        if self.format_version > Self::FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Index format version {} is newer than the newest supported version {}",
+                    self.format_version,
+                    Self::FORMAT_VERSION
+                ),
+            ));
+        }
+        if let Some(schedule) = self.bin_schedule.as_ref().filter(|s| !s.is_valid()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid bin schedule {:?}", schedule.regions()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn build_id(&self) -> Option<u64> {
+        (self.build_id != 0).then_some(self.build_id)
+    }
+
+    fn set_build_id(&mut self, build_id: u64) {
+        self.build_id = build_id
+    }
+}
+
+/// Compression settings for the parents and entries archives of an index. The entries
+/// are much larger and benefit from higher compression levels than the parents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexCompression {
+    pub parents: Compression,
+    pub entries: Compression,
+}
+
+impl IndexCompression {
+    pub fn new(parents: Compression, entries: Compression) -> Self {
+        Self { parents, entries }
+    }
+}
+
+impl Default for IndexCompression {
+    fn default() -> Self {
+        Compression::ZSTD(ZstdLevel::try_new(9).unwrap()).into()
+    }
+}
+
+impl From<Compression> for IndexCompression {
+    fn from(value: Compression) -> Self {
+        Self::new(value, value)
+    }
+}
+
+const BUILD_ID_KEY: &str = "build_id";
+
+/// Generate a fresh, non-zero id to tie together the files of one index
+pub(crate) fn new_build_id() -> u64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    RandomState::new().hash_one(now).max(1)
+}
+
+/// Record `build_id` in the key-value metadata of a parquet file
+pub(crate) fn with_build_id(
+    props: WriterPropertiesBuilder,
+    build_id: Option<u64>,
+) -> WriterPropertiesBuilder {
+    match build_id {
+        Some(build_id) => props.set_key_value_metadata(Some(vec![KeyValue::new(
+            BUILD_ID_KEY.into(),
+            build_id.to_string(),
+        )])),
+        None => props,
+    }
+}
+
+/// Check that a parquet file was written with `build_id`, if one is expected
+pub(crate) fn check_build_id(
+    metadata: &ParquetMetaData,
+    build_id: Option<u64>,
+    archive_name: &str,
+) -> io::Result<()> {
+    let Some(expected) = build_id else {
+        return Ok(());
+    };
+    let found = metadata
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|kvs| kvs.iter().find(|kv| kv.key == BUILD_ID_KEY))
+        .and_then(|kv| kv.value.as_ref())
+        .and_then(|v| v.parse::<u64>().ok());
+    if found != Some(expected) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{archive_name} has build id {} but the index metadata expects {expected}, the files come from different indices",
+                found.map(|v| v.to_string()).unwrap_or_else(|| "<missing>".into())
+            ),
+        ));
+    }
+    Ok(())
+}
+
+const APPENDED_SEGMENT_PREFIX: &str = "appended_";
+
+/// The directories of the segments appended to the index in `directory`, in the order
+/// they were appended
+pub(crate) fn appended_segments(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(segment) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(APPENDED_SEGMENT_PREFIX))
+            .and_then(|segment| segment.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        if entry.file_type()?.is_dir() {
+            segments.push((segment, entry.path()));
+        }
+    }
+    segments.sort();
+    Ok(segments.into_iter().map(|(_, path)| path).collect())
+}
+
+/// The directory the next segment appended to the index in `directory` is written to
+pub(crate) fn next_appended_segment(directory: &Path) -> io::Result<PathBuf> {
+    let n = appended_segments(directory)?.len();
+    Ok(directory.join(format!("{APPENDED_SEGMENT_PREFIX}{n}")))
+}
+
+fn existing_metadata(directory: &Path) -> Option<IndexMetadata> {
+    let meta = fs::File::open(directory.join(IndexMetadata::archive_name())).ok()?;
+    read_metadata_from(io::BufReader::new(meta)).ok()
+}
+
+/// The build id of the index currently written in `directory`, if it has one
+pub(crate) fn existing_build_id(directory: &Path) -> Option<u64> {
+    existing_metadata(directory)?.build_id()
+}
+
+/// Remove the segments appended to the index with `build_id` once it has been replaced,
+/// leaving any directory that was not appended to it
+pub(crate) fn remove_appended_segments(directory: &Path, build_id: u64) -> io::Result<()> {
+    for segment in appended_segments(directory)? {
+        if existing_metadata(&segment).is_some_and(|m| m.base_build_id == build_id) {
+            fs::remove_dir_all(segment)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn write_metadata_into<M: ArrowStorage, W: io::Write>(
+    metadata: M,
+    sink: W,
+) -> Result<(), MassFragmentIndexError> {
+    let meta_schema = M::schema();
+    let mut writer = LineDelimitedWriter::new(sink);
+    let metadata = M::to_batch(&[metadata], meta_schema, 0)?;
+
+    writer.write(&metadata)?;
+    writer.finish()?;
+    // A buffered sink would otherwise only report a failed write when dropped
+    io::Write::flush(&mut writer.into_inner())?;
+    Ok(())
+}
+
+pub(crate) fn write_metadata_to<M: ArrowStorage>(
+    metadata: M,
+    directory: &Path,
+) -> Result<(), MassFragmentIndexError> {
+    let meta_path = directory.join(M::archive_name());
+    let meta_fh = io::BufWriter::new(fs::File::create(meta_path)?);
+    write_metadata_into(metadata, meta_fh)
+}
+
+pub(crate) fn write_parents_into<P: ArrowStorage, W: io::Write + Send>(
+    parents: &[P],
+    sink: W,
+    compression_level: &Compression,
+    build_id: Option<u64>,
+) -> Result<(), MassFragmentIndexError> {
+    let parent_schema = P::schema();
+    let props = with_build_id(P::writer_properties(), build_id)
+        .set_compression(*compression_level)
+        .build();
+    let mut writer = ArrowWriter::try_new(sink, parent_schema.clone(), Some(props))?;
+    let batch = P::to_batch(parents, parent_schema.clone(), 0)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+pub(crate) fn write_parents_to<P: ArrowStorage>(
+    parents: &[P],
+    directory: &Path,
+    compression_level: &Compression,
+    build_id: Option<u64>,
+) -> Result<(), MassFragmentIndexError> {
+    let parent_path = directory.join(P::archive_name());
+    write_parents_into(parents, fs::File::create(parent_path)?, compression_level, build_id)
+}
+
+pub(crate) fn write_entries_into<'a, T: ArrowStorage + 'a, W: io::Write + Send>(
+    bins: impl Iterator<Item = &'a [T]>,
+    sink: W,
+    compression_level: &Compression,
+    build_id: Option<u64>,
+    max_row_group_size: Option<usize>,
+) -> Result<(), MassFragmentIndexError> {
+    let entries_schema = T::schema();
+    let mut props =
+        with_build_id(T::writer_properties(), build_id).set_compression(*compression_level);
+    if let Some(max_row_group_size) = max_row_group_size {
+        props = props.set_max_row_group_size(max_row_group_size.max(1));
+    }
+    let mut writer = ArrowWriter::try_new(sink, entries_schema.clone(), Some(props.build()))?;
+    for (i, bin) in bins.enumerate() {
+        // Keep each batch within a row group so a dense bin is not buffered all at once
+        let chunk_size = max_row_group_size.unwrap_or(bin.len()).max(1);
+        for chunk in bin.chunks(chunk_size) {
+            let batch = T::to_batch(chunk, entries_schema.clone(), i as u64)?;
+            writer.write(&batch)?;
+        }
+    }
+    writer.close()?;
+    Ok(())
+}
+
+pub(crate) fn read_metadata_from<M: ArrowStorage, R: io::BufRead>(
+    source: R,
+) -> Result<M, MassFragmentIndexError> {
+    let meta_schema = M::schema();
+    let mut reader = JSONReaderBuilder::new(meta_schema.clone()).build(source)?;
+    let no_metadata =
+        || io::Error::new(io::ErrorKind::UnexpectedEof, "No metadata record batch found");
+    let batch = reader.next().ok_or_else(no_metadata)??;
+    let (metadata, _) = M::from_batch(&batch, meta_schema)
+        .next()
+        .ok_or_else(no_metadata)?;
+    metadata.validate()?;
+    Ok(metadata)
+}
+
+pub(crate) fn read_parents_from<P: ArrowStorage, R: ChunkReader + 'static>(
+    source: R,
+    build_id: Option<u64>,
+) -> Result<Vec<P>, MassFragmentIndexError> {
+    let parent_schema = P::schema();
+    let builder = ArrowReaderBuilder::try_new(source)?;
+    check_build_id(builder.metadata(), build_id, &P::archive_name())?;
+    let reader = builder.build()?;
+    let mut parents = Vec::new();
+    for batch in reader {
+        let batch = batch?;
+        P::validate_batch(&batch)?;
+        parents.extend(P::from_batch(&batch, parent_schema.clone()).map(|(p, _)| p));
+    }
+    Ok(parents)
+}
+
+/// Lazily reads an entries file one bin at a time, yielding each non-empty bin's segment
+/// id with its entries.
+///
+/// Entries are written bin by bin, so each run of rows sharing a `segment_id` is a bin.
+/// A file whose segments are not contiguous yields the same segment id more than once.
+pub struct IndexBinReader<T: ArrowStorage> {
+    reader: ParquetRecordBatchReader,
+    schema: SchemaRef,
+    pending: std::vec::IntoIter<(T, u64)>,
+    current: Option<(u64, Vec<T>)>,
+}
+
+impl<T: ArrowStorage> IndexBinReader<T> {
+    pub fn new<R: ChunkReader + 'static>(source: R) -> io::Result<Self> {
+        let reader = ArrowReaderBuilder::try_new(source)?.build()?;
+        Ok(Self::from_reader(reader))
+    }
+
+    fn from_reader(reader: ParquetRecordBatchReader) -> Self {
+        Self {
+            reader,
+            schema: T::schema(),
+            pending: Vec::new().into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl<T: ArrowStorage> Iterator for IndexBinReader<T> {
+    type Item = Result<(u64, Vec<T>), MassFragmentIndexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for (entry, segment_id) in self.pending.by_ref() {
+                match self.current.as_mut() {
+                    Some((current_id, bin)) if *current_id == segment_id => bin.push(entry),
+                    _ => {
+                        if let Some(done) = self.current.replace((segment_id, vec![entry])) {
+                            return Some(Ok(done));
+                        }
+                    }
+                }
+            }
+            match self.reader.next() {
+                None => return self.current.take().map(Ok),
+                Some(Ok(batch)) => {
+                    if let Err(e) = T::validate_batch(&batch) {
+                        return Some(Err(e.into()));
+                    }
+                    self.pending = T::from_batch(&batch, self.schema.clone())
+                        .collect::<Vec<_>>()
+                        .into_iter();
+                }
+                Some(Err(e)) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+pub(crate) fn read_entries_from<T: ArrowStorage, R: ChunkReader + 'static>(
+    source: R,
+    build_id: Option<u64>,
+) -> Result<HashMap<u64, Vec<T>>, MassFragmentIndexError> {
+    let builder = ArrowReaderBuilder::try_new(source)?;
+    check_build_id(builder.metadata(), build_id, &T::archive_name())?;
+    let mut bin_collector: HashMap<u64, Vec<T>> = HashMap::default();
+    for bin in IndexBinReader::from_reader(builder.build()?) {
+        let (segment_id, entries) = bin?;
+        bin_collector.entry(segment_id).or_default().extend(entries);
+    }
+    Ok(bin_collector)
 }
 
 pub trait IndexBinaryStorage<'a, T: ArrowStorage + 'a, P: ArrowStorage, M: ArrowStorage> {
-    fn write_metadata(&self, directory: &Path) -> io::Result<()> {
-        let metadata = self.to_metadata();
-        let meta_path = directory.join(M::archive_name());
-        let meta_schema = M::schema();
-        let meta_fh = io::BufWriter::new(fs::File::create(meta_path)?);
-        let mut writer = LineDelimitedWriter::new(meta_fh);
-        let metadata = M::to_batch(&[metadata], meta_schema, 0).unwrap();
-
-        writer.write(&metadata).unwrap();
-        writer.finish().unwrap();
-        Ok(())
+    fn write_metadata(
+        &self,
+        directory: &Path,
+        build_id: Option<u64>,
+    ) -> Result<(), MassFragmentIndexError> {
+        let mut metadata = self.to_metadata();
+        if let Some(build_id) = build_id {
+            metadata.set_build_id(build_id);
+        }
+        write_metadata_to(metadata, directory)
     }
 
-    fn write_parents(&self, directory: &Path, compression_level: &Compression) -> io::Result<()> {
-        let parent_path = directory.join(P::archive_name());
-        let parent_schema = P::schema();
-        let props = P::writer_properties()
-            .set_compression(compression_level.clone())
-            .build();
-        let mut writer = ArrowWriter::try_new(
-            fs::File::create(parent_path)?,
-            parent_schema.clone(),
-            Some(props),
-        )?;
-        let batch = P::to_batch(self.parents(), parent_schema.clone(), 0).unwrap();
-        writer.write(&batch)?;
-        writer.close()?;
-        Ok(())
+    /// Write only the parents file into `directory`, stamped with `build_id`.
+    ///
+    /// To replace the parents of an index already written there, pass the build id of
+    /// its metadata so the files still read together. Entries refer to parents by
+    /// position, so the parents must keep their number and order.
+    fn write_parents(
+        &self,
+        directory: &Path,
+        compression_level: &Compression,
+        build_id: Option<u64>,
+    ) -> Result<(), MassFragmentIndexError> {
+        write_parents_to(self.parents(), directory, compression_level, build_id)
     }
 
-    fn write_entries(&'a self, directory: &Path, compression_level: &Compression) -> io::Result<()> {
+    /// Write only the entries file into `directory`, stamped with `build_id`. As with
+    /// [`IndexBinaryStorage::write_parents`], replacing the entries of an existing index
+    /// needs its build id, and the metadata's entry count must still hold.
+    fn write_entries(
+        &'a self,
+        directory: &Path,
+        compression_level: &Compression,
+        build_id: Option<u64>,
+        max_row_group_size: Option<usize>,
+    ) -> Result<(), MassFragmentIndexError> {
         let entries_path = directory.join(T::archive_name());
-        let entries_schema = T::schema();
-        let props = T::writer_properties()
-            .set_compression(compression_level.clone())
-            .build();
-        let mut writer = ArrowWriter::try_new(
+        write_entries_into(
+            self.iter_entries(),
             fs::File::create(entries_path)?,
-            entries_schema.clone(),
-            Some(props),
-        )?;
-        for (i, bin) in self.iter_entries().enumerate() {
-            let batch = T::to_batch(bin, entries_schema.clone(), i as u64).unwrap();
-            writer.write(&batch)?;
-        }
-        writer.close()?;
-        Ok(())
+            compression_level,
+            build_id,
+            max_row_group_size,
+        )
     }
 
+    /// Write the index's metadata, parents and entries into `directory`.
+    ///
+    /// When `compression_level` is `None`, the library default of ZSTD level 9 is used
+    /// for both archives. Pass `Some(Compression::UNCOMPRESSED.into())` to skip compression
+    /// entirely when write speed matters more than size.
+    ///
+    /// All three files are stamped with a fresh build id, which [`IndexBinaryStorage::read`]
+    /// checks so that files from different indices are not mixed.
     fn write<D: AsRef<Path>>(
         &'a self,
         directory: &D,
-        compression_level: Option<Compression>,
-    ) -> io::Result<()> {
+        compression_level: Option<IndexCompression>,
+    ) -> Result<(), MassFragmentIndexError> {
+        self.write_with_row_group_size(directory, compression_level, None)
+    }
+
+    /// Write the index as with [`IndexBinaryStorage::write`], but cap the number of rows in
+    /// each row group of the entries file at `max_row_group_size`, splitting dense bins
+    /// across several row groups. Smaller row groups let readers skip more of the file.
+    /// When `None`, the parquet default is used.
+    fn write_with_row_group_size<D: AsRef<Path>>(
+        &'a self,
+        directory: &D,
+        compression_level: Option<IndexCompression>,
+        max_row_group_size: Option<usize>,
+    ) -> Result<(), MassFragmentIndexError> {
         let directory = directory.as_ref();
 
-        let compression_level =
-            compression_level.unwrap_or_else(|| Compression::ZSTD(ZstdLevel::try_new(9).unwrap()));
+        let compression_level = compression_level.unwrap_or_default();
 
-        self.write_metadata(directory)?;
-        self.write_parents(directory, &compression_level)?;
-        self.write_entries(directory, &compression_level)?;
+        let build_id = Some(new_build_id());
+        self.write_metadata(directory, build_id)?;
+        self.write_parents(directory, &compression_level.parents, build_id)?;
+        self.write_entries(
+            directory,
+            &compression_level.entries,
+            build_id,
+            max_row_group_size,
+        )?;
 
         Ok(())
     }
@@ -214,7 +862,22 @@ pub trait IndexBinaryStorage<'a, T: ArrowStorage + 'a, P: ArrowStorage, M: Arrow
 
     fn from_components(metadata: M, parents: Vec<P>, entries: HashMap<u64, Vec<T>>) -> Self;
 
-    fn read<D: AsRef<Path>>(directory: &D) -> io::Result<Self>
+    /// Check that every segment id read from storage names a bin described by the metadata
+    fn check_segments(_metadata: &M, _entries: &HashMap<u64, Vec<T>>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Check the components read from storage are consistent with their metadata
+    /// before they are assembled.
+    fn validate_components(
+        _metadata: &M,
+        _parents: &[P],
+        _entries: &HashMap<u64, Vec<T>>,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn read<D: AsRef<Path>>(directory: &D) -> Result<Self, MassFragmentIndexError>
     where
         Self: Sized,
     {
@@ -222,50 +885,28 @@ pub trait IndexBinaryStorage<'a, T: ArrowStorage + 'a, P: ArrowStorage, M: Arrow
         let entries_path = directory.as_ref().join(T::archive_name());
         let meta_path = directory.as_ref().join(M::archive_name());
 
-        let metadata = {
-            let meta_schema = M::schema();
-            let meta_fh = io::BufReader::new(fs::File::open(meta_path)?);
-            let meta_rec = JSONReaderBuilder::new(meta_schema.clone())
-                .build(meta_fh)
-                .unwrap()
-                .next()
-                .unwrap()
-                .unwrap();
-
-            let (metadata, _) = M::from_batch(&meta_rec, meta_schema.clone())
-                .next()
-                .unwrap();
-            metadata
-        };
-
-        let parents = {
-            let parent_schema = P::schema();
-            let parents_fh = fs::File::open(parents_path)?;
-
-            let reader = ArrowReaderBuilder::try_new(parents_fh)?.build()?;
-            let mut parents = Vec::new();
-            for batch in reader {
-                parents
-                    .extend(P::from_batch(&batch.unwrap(), parent_schema.clone()).map(|(p, _)| p));
-            }
-
-            parents
-        };
-
-        let entries = {
-            let mut bin_collector: HashMap<u64, Vec<T>> = HashMap::default();
-            let entries_fh = fs::File::open(entries_path)?;
-            let reader = ArrowReaderBuilder::try_new(entries_fh)?.build()?;
-            let entry_schema = T::schema();
-
-            for batch in reader {
-                for (entry, segment_id) in T::from_batch(&batch.unwrap(), entry_schema.clone()) {
-                    bin_collector.entry(segment_id).or_default().push(entry);
-                }
-            }
+        Self::read_from_sources(
+            io::BufReader::new(fs::File::open(meta_path)?),
+            fs::File::open(parents_path)?,
+            fs::File::open(entries_path)?,
+        )
+    }
 
-            bin_collector
-        };
+    /// Read an index from the contents of its three files rather than a directory, e.g.
+    /// [`Bytes`](bytes::Bytes) already fetched into memory. `metadata` holds the JSON
+    /// metadata and `parents` and `entries` the parquet files.
+    fn read_from_sources<R: io::BufRead, PR: ChunkReader + 'static, TR: ChunkReader + 'static>(
+        metadata: R,
+        parents: PR,
+        entries: TR,
+    ) -> Result<Self, MassFragmentIndexError>
+    where
+        Self: Sized,
+    {
+        let metadata: M = read_metadata_from(metadata)?;
+        let parents = read_parents_from(parents, metadata.build_id())?;
+        let entries = read_entries_from(entries, metadata.build_id())?;
+        Self::validate_components(&metadata, &parents, &entries)?;
 
         let this = Self::from_components(metadata, parents, entries);
         Ok(this)
@@ -280,6 +921,7 @@ pub struct SearchIndexOnDisk<
 > {
     root: PathBuf,
     pub metadata: M,
+    parents: IndexBin<P>,
     _t: PhantomData<T>,
     _p: PhantomData<P>,
     _index: PhantomData<SearchIndex<T, P>>,
@@ -320,36 +962,40 @@ impl<
         let mut this = Self {
             root: path,
             metadata: M::default(),
+            parents: IndexBin::default(),
             _t: PhantomData,
             _p: PhantomData,
             _index: PhantomData,
         };
         this.metadata = this.read_metadata()?;
+        let mut parents = IndexBin::from(this.read_parents()?);
+        parents.assume_sorted(SortType::ByMass);
+        this.parents = parents;
         Ok(this)
     }
 
+    /// Read the entries file one bin at a time instead of loading the whole index
+    pub fn iter_bins(&self) -> io::Result<IndexBinReader<T>> {
+        let arch = self.root.join(T::archive_name());
+        IndexBinReader::new(fs::File::open(arch)?)
+    }
+
+    fn read_parents(&self) -> io::Result<Vec<P>> {
+        let arch = self.root.join(P::archive_name());
+        Ok(read_parents_from(fs::File::open(arch)?, self.metadata.build_id())?)
+    }
+
+    pub fn parents(&self) -> &[P] {
+        self.parents.as_slice()
+    }
+
     fn read_metadata(&self) -> io::Result<M> {
         let arch = self.root.join(M::archive_name());
-        let handle = io::BufReader::new(fs::File::open(arch)?);
-        let mut reader = JSONReaderBuilder::new(M::schema())
-            .build(handle)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let (meta, _) = M::from_batch(
-            &reader
-                .next()
-                .expect("No metadata record batch found")
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
-            M::schema(),
-        )
-        .next()
-        .unwrap();
-        Ok(meta)
+        Ok(read_metadata_from(io::BufReader::new(fs::File::open(arch)?))?)
     }
 
     pub fn parents_for(&self, mass: MassType, error_tolerance: Tolerance) -> Interval {
-        // let iv = self.parents.search_mass(mass, error_tolerance);
-        // iv
-        todo!()
+        self.parents.search_mass(mass, error_tolerance)
     }
 
     pub fn parents_for_range(
@@ -358,10 +1004,85 @@ impl<
         high: MassType,
         error_tolerance: Tolerance,
     ) -> Interval {
-        // let mut out = Interval::default();
-        // out.start = self.parents_for(low, error_tolerance).start;
-        // out.end = self.parents_for(high, error_tolerance).end;
-        // out
-        todo!()
+        if low > high {
+            return Interval::default();
+        }
+        let mut out = Interval::default();
+        out.start = self.parents_for(low, error_tolerance).start;
+        out.end = self.parents_for(high, error_tolerance).end.max(out.start);
+        out
+    }
+
+    /// Find the row groups of the entries file whose mass statistics overlap
+    /// the tolerance window around `query`. Row groups without usable statistics
+    /// are always included.
+    ///
+    /// [`IndexBinaryStorage::write`] uses the parquet default row group size, so most
+    /// indices fit in a single row group and nothing can be skipped. Write the index with
+    /// [`IndexBinaryStorage::write_with_row_group_size`] to make pruning effective.
+    pub fn row_groups_for(&self, query: MassType, error_tolerance: Tolerance) -> io::Result<Vec<usize>> {
+        let arch = self.root.join(T::archive_name());
+        let builder = ArrowReaderBuilder::try_new(fs::File::open(arch)?)?;
+        let (low, high) = error_tolerance.bounds(query);
+        Ok(Self::select_row_groups(builder.metadata(), low, high))
+    }
+
+    fn select_row_groups(metadata: &ParquetMetaData, low: MassType, high: MassType) -> Vec<usize> {
+        let row_groups = metadata.row_groups();
+        let Some(mass_column) = T::mass_column() else {
+            return (0..row_groups.len()).collect();
+        };
+        row_groups
+            .iter()
+            .enumerate()
+            .filter(|(_, rg)| {
+                let bounds = match rg.column(mass_column).statistics() {
+                    Some(Statistics::Float(stats)) if stats.has_min_max_set() => {
+                        Some((*stats.min() as MassType, *stats.max() as MassType))
+                    }
+                    Some(Statistics::Double(stats)) if stats.has_min_max_set() => {
+                        Some((*stats.min() as MassType, *stats.max() as MassType))
+                    }
+                    _ => None,
+                };
+                match bounds {
+                    Some((min, max)) => max >= low && min <= high,
+                    None => true,
+                }
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Search the entries file for `query`, decoding only the row groups whose
+    /// mass range overlaps the tolerance window, see [`SearchIndexOnDisk::row_groups_for`].
+    pub fn search(
+        &self,
+        query: MassType,
+        error_tolerance: Tolerance,
+        parent_interval: Option<Interval>,
+    ) -> io::Result<Vec<T>> {
+        let parent_interval =
+            parent_interval.unwrap_or_else(|| Interval::new(0, self.parents.len()));
+        let arch = self.root.join(T::archive_name());
+        let builder = ArrowReaderBuilder::try_new(fs::File::open(arch)?)?;
+        let (low, high) = error_tolerance.bounds(query);
+        let row_groups = Self::select_row_groups(builder.metadata(), low, high);
+        let reader = builder.with_row_groups(row_groups).build()?;
+        let schema = T::schema();
+        let mut hits = Vec::new();
+        for batch in reader {
+            let batch = batch.map_err(io::Error::other)?;
+            T::validate_batch(&batch)?;
+            hits.extend(
+                T::from_batch(&batch, schema.clone())
+                    .map(|(e, _)| e)
+                    .filter(|e| {
+                        error_tolerance.test(query, e.mass())
+                            && parent_interval.contains(e.parent_id() as usize)
+                    }),
+            );
+        }
+        Ok(hits)
     }
 }
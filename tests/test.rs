@@ -29,13 +29,13 @@ fn parse_csv<R: io::BufRead>(reader: R) -> io::Result<Vec<(Peptide, Vec<Fragment
                     accumulator.push((peptide.clone(), mem::take(&mut fragments)));
                 }
 
-                peprec = Some(Peptide::new(
-                    record.get(2).unwrap().parse::<MassType>().unwrap(),
-                    parent_i as ParentID,
-                    0,
-                    0,
-                    record.get(1).unwrap().to_string(),
-                ));
+                peprec = Some(
+                    Peptide::builder()
+                        .mass(record.get(2).unwrap().parse::<MassType>().unwrap())
+                        .id(parent_i as ParentID)
+                        .sequence(record.get(1).unwrap())
+                        .build(),
+                );
             }
             "FRAGMENT" => {
                 let name: FragmentName = record.get(1).unwrap().parse().unwrap();
@@ -338,3 +338,21 @@ fn test_index_build_traversal() -> io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_bin_histogram() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index = build_index(reader)?;
+
+    let histogram = search_index.bin_histogram();
+    assert_eq!(histogram.len(), search_index.num_bins());
+    assert_eq!(histogram.iter().sum::<usize>(), search_index.num_entries());
+
+    let stats = search_index.bin_stats();
+    assert_eq!(stats.max, *histogram.iter().max().unwrap());
+    assert_eq!(stats.min, 0);
+    assert!(stats.empty_bins > 0 && stats.empty_bins < histogram.len());
+    let mean = search_index.num_entries() as f64 / search_index.num_bins() as f64;
+    assert!((stats.mean - mean).abs() < 1e-9);
+    Ok(())
+}
@@ -5,13 +5,18 @@ use std::{fs, io, mem};
 
 use csv;
 
-use mass_fragment_index::fragment::{Fragment, FragmentName};
-use mass_fragment_index::index::SearchIndex;
+use mass_fragment_index::fragment::{AnnotatedFragment, Fragment, FragmentName, FragmentSeries};
+use mass_fragment_index::index::{BinSchedule, SearchIndex};
 use mass_fragment_index::parent::Peptide;
-use mass_fragment_index::sort::{MassType, ParentID, SortType};
+use mass_fragment_index::sort::{IndexSortable, MassType, ParentID, SortType};
 
-use mass_fragment_index::storage::{read_fragment_index, write_fragment_index};
-use mass_fragment_index::Tolerance;
+use mass_fragment_index::storage::{
+    fragment_schema, index_file_paths, peptide_schema, read_csv_index, read_fragment_index, write_fragment_index,
+    ArrowStorage, Bytes, Compression, IndexArchiveStorage, IndexBinaryStorage, IndexCompression, IndexIpcStorage,
+    IndexMetadata, SearchIndexOnDisk, SearchIndexWriter, SplitIndexBinaryStorage, ZstdLevel,
+    BrotliLevel, GzipLevel,
+};
+use mass_fragment_index::{MassFragmentIndexError, Tolerance};
 
 fn parse_csv<R: io::BufRead>(reader: R) -> io::Result<Vec<(Peptide, Vec<Fragment>)>> {
     let mut csv_reader = csv::Reader::from_reader(reader);
@@ -30,13 +35,13 @@ fn parse_csv<R: io::BufRead>(reader: R) -> io::Result<Vec<(Peptide, Vec<Fragment
                     accumulator.push((peptide.clone(), mem::take(&mut fragments)));
                 }
 
-                peprec = Some(Peptide::new(
-                    record.get(2).unwrap().parse::<MassType>().unwrap(),
-                    parent_i as ParentID,
-                    0,
-                    0,
-                    record.get(1).unwrap().to_string(),
-                ));
+                peprec = Some(
+                    Peptide::builder()
+                        .mass(record.get(2).unwrap().parse::<MassType>().unwrap())
+                        .id(parent_i as ParentID)
+                        .sequence(record.get(1).unwrap())
+                        .build(),
+                );
             }
             "FRAGMENT" => {
                 let name: FragmentName = record.get(1).unwrap().parse().unwrap();
@@ -83,7 +88,7 @@ fn test_index_build_traversal() -> io::Result<()> {
     search_index.write_parquet(&tmpdir_path, None)?;
 
     let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
-    assert_eq!(duplicate_index.parents.len(), search_index.parents.len());
+    assert_eq!(duplicate_index.num_parents(), search_index.num_parents());
     assert_eq!(duplicate_index.num_entries(), search_index.num_entries());
 
     let parent_interval = search_index.parents_for_range(200.0, 1200.0, Tolerance::PPM(10.0));
@@ -96,3 +101,1477 @@ fn test_index_build_traversal() -> io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_on_disk_parents_for_range() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+
+    let on_disk = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())?;
+    assert_eq!(on_disk.parents().len(), search_index.parents.len());
+
+    let tol = Tolerance::PPM(10.0);
+    let expected = search_index.parents_for_range(200.0, 1200.0, tol);
+    let parent_interval = on_disk.parents_for_range(200.0, 1200.0, tol);
+    assert_eq!(parent_interval, expected);
+
+    let expected = search_index.parents_for(432.23324739196, tol);
+    assert_eq!(on_disk.parents_for(432.23324739196, tol), expected);
+
+    assert!(on_disk.parents_for_range(1200.0, 200.0, tol).is_empty());
+    assert!(on_disk.parents_for_range(0.0, 1.0, tol).is_empty());
+    assert!(on_disk.parents_for_range(1e6, 2e6, tol).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_streaming_writer() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let pepfrags: Vec<_> = parse_csv(reader)?.into_iter().take(200).collect();
+
+    let mut search_index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    let mut fragments = Vec::new();
+    for (pep, frags) in pepfrags {
+        search_index.add_parent(pep);
+        for frag in frags {
+            search_index.add(frag);
+            fragments.push(frag);
+        }
+    }
+    search_index.sort(SortType::ByParentId);
+    assert!(fragments.len() > 1000);
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    let mut writer: SearchIndexWriter<Fragment, Peptide> =
+        SearchIndexWriter::new(&tmpdir_path, 100, 10000.0, None)?;
+    for parent in search_index.parents.iter() {
+        writer.add_parent(parent.clone())?;
+    }
+    fragments.sort_by(|a, b| a.mass.total_cmp(&b.mass));
+    for frag in fragments.iter() {
+        writer.add(*frag)?;
+    }
+    assert!(writer.add(fragments[0]).is_err());
+    let lighter = search_index.parents[0].clone();
+    assert_eq!(writer.add_parent(lighter).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    writer.finish()?;
+
+    let streamed = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(streamed.num_parents(), search_index.num_parents());
+    assert_eq!(streamed.num_entries(), search_index.num_entries());
+    assert_eq!(streamed.num_bins(), search_index.num_bins());
+    for (a, b) in streamed.iter_bins().zip(search_index.iter_bins()) {
+        let mut a: Vec<_> = a.iter().collect();
+        let mut b: Vec<_> = b.iter().collect();
+        let key = |f: &&Fragment| (f.parent_id, f.mass.to_bits(), f.ordinal);
+        a.sort_by_key(key);
+        b.sort_by_key(key);
+        assert_eq!(a, b);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_exposed_schemas_match_written_files() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+
+    for (name, expected) in [
+        (Fragment::archive_name(), fragment_schema()),
+        (Peptide::archive_name(), peptide_schema()),
+    ] {
+        let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+            fs::File::open(tmpdir_path.join(name))?,
+        )?;
+        let written: Vec<_> = builder
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| (f.name().clone(), f.data_type().clone()))
+            .collect();
+        let expected: Vec<_> = expected
+            .fields()
+            .iter()
+            .map(|f| (f.name().clone(), f.data_type().clone()))
+            .collect();
+        assert_eq!(written, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_on_disk_lazy_bins() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+    let eager = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+
+    let on_disk = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())?;
+    let mut total = 0;
+    let mut last_segment = None;
+    for bin in on_disk.iter_bins()? {
+        let (segment_id, entries) = bin?;
+        assert!(last_segment < Some(segment_id));
+        assert_eq!(entries.len(), eager.bins[segment_id as usize].len());
+        last_segment = Some(segment_id);
+        total += entries.len();
+    }
+    assert_eq!(total, eager.num_entries());
+    assert_eq!(total, search_index.num_entries());
+
+    Ok(())
+}
+
+#[test]
+fn test_row_group_size() -> io::Result<()> {
+    let mut search_index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(10, 2000.0);
+    for i in 0..50u32 {
+        search_index.add_parent(Peptide::new(500.0 + i as MassType, i, 0, 0, "PEPTIDE".into()));
+        for j in 0..100u16 {
+            // Every fragment lands in the same bin
+            let mass = 300.0 + (i as MassType * 100.0 + j as MassType) * 1e-6;
+            search_index.add(Fragment::new(mass, i, FragmentSeries::b, j));
+        }
+    }
+    search_index.sort(SortType::ByParentId);
+    assert_eq!(search_index.bin_stats().max, 5000);
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_with_row_group_size(&tmpdir_path, None, Some(1024))?;
+
+    let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+        fs::File::open(tmpdir_path.join(Fragment::archive_name()))?,
+    )?;
+    let row_groups = builder.metadata().row_groups();
+    assert_eq!(row_groups.len(), 5);
+    assert!(row_groups.iter().all(|rg| rg.num_rows() <= 1024));
+
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(duplicate_index.num_entries(), search_index.num_entries());
+    for (a, b) in duplicate_index.iter_bins().zip(search_index.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sort_type_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(duplicate_index.sort_type(), SortType::ByParentId);
+
+    search_index.sort(SortType::ByMass);
+    search_index.write_parquet(&tmpdir_path, None)?;
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(duplicate_index.sort_type(), SortType::ByMass);
+    assert!(duplicate_index.iter_bins().all(|b| b.sort_type() == SortType::ByMass || b.is_empty()));
+
+    let query = 113.08406397713001;
+    let tol = Tolerance::PPM(10.0);
+    assert_eq!(
+        duplicate_index.search(query, tol, None).count(),
+        search_index.search(query, tol, None).count()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_append_parquet() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let full_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut first: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    let mut second: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (i, (mut pep, frags)) in parse_csv(reader)?.into_iter().enumerate() {
+        let part = if i % 3 == 0 { &mut second } else { &mut first };
+        let parent_id = part.num_parents() as ParentID;
+        pep.id = parent_id;
+        part.add_parent(pep);
+        for mut frag in frags {
+            frag.parent_id = parent_id;
+            part.add(frag);
+        }
+    }
+    first.sort(SortType::ByParentId);
+    second.sort(SortType::ByParentId);
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    first.write_parquet(&tmpdir_path, None)?;
+    second.append_parquet(&tmpdir_path, None)?;
+
+    let combined = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(combined.num_parents(), full_index.num_parents());
+    assert_eq!(combined.num_entries(), full_index.num_entries());
+
+    let tol = Tolerance::PPM(10.0);
+    for query in [113.08406397713001, 147.11280, 175.11895] {
+        let expected: Vec<_> = full_index
+            .search(query, tol, None)
+            .map(|f| &full_index.parents[f.parent_id as usize].sequence)
+            .collect();
+        let mut found: Vec<_> = combined
+            .search(query, tol, None)
+            .map(|f| &combined.parents[f.parent_id as usize].sequence)
+            .collect();
+        let mut expected = expected;
+        expected.sort();
+        found.sort();
+        assert_eq!(found, expected);
+    }
+    let query = 113.08406397713001;
+    assert!(combined.search(query, tol, None).count() > first.search(query, tol, None).count());
+
+    Ok(())
+}
+
+#[test]
+fn test_rewrite_after_append() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut first: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    let mut second: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (i, (mut pep, frags)) in parse_csv(reader)?.into_iter().take(300).enumerate() {
+        let part = if i % 2 == 0 { &mut second } else { &mut first };
+        let parent_id = part.num_parents() as ParentID;
+        pep.id = parent_id;
+        part.add_parent(pep);
+        for mut frag in frags {
+            frag.parent_id = parent_id;
+            part.add(frag);
+        }
+    }
+    first.sort(SortType::ByParentId);
+    second.sort(SortType::ByParentId);
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    first.write_parquet(&tmpdir_path, None)?;
+    second.append_parquet(&tmpdir_path, None)?;
+    assert!(tmpdir_path.join("appended_0").exists());
+
+    // Rewriting the directory drops the segments appended to the old index, but not
+    // a directory that merely shares their naming
+    fs::create_dir(tmpdir_path.join("appended_7"))?;
+    fs::write(tmpdir_path.join("appended_7").join("notes.txt"), "keep")?;
+    second.write_parquet(&tmpdir_path, None)?;
+    assert!(!tmpdir_path.join("appended_0").exists());
+    assert!(tmpdir_path.join("appended_7").join("notes.txt").exists());
+    fs::remove_dir_all(tmpdir_path.join("appended_7"))?;
+    let rewritten = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(rewritten.num_parents(), second.num_parents());
+    assert_eq!(rewritten.num_entries(), second.num_entries());
+
+    // So does replacing the index with the streaming writer
+    first.append_parquet(&tmpdir_path, None)?;
+    assert!(tmpdir_path.join("appended_0").exists());
+    let mut writer: SearchIndexWriter<Fragment, Peptide> =
+        SearchIndexWriter::new(&tmpdir_path, 100, 10000.0, None)?;
+    for parent in second.parents.iter() {
+        writer.add_parent(parent.clone())?;
+    }
+    let mut fragments: Vec<Fragment> = second.iter_bins().flat_map(|bin| bin.iter().copied()).collect();
+    fragments.sort_by(|a, b| a.mass.total_cmp(&b.mass));
+    for frag in fragments {
+        writer.add(frag)?;
+    }
+    writer.finish()?;
+    assert!(!tmpdir_path.join("appended_0").exists());
+    let rewritten = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(rewritten.num_entries(), second.num_entries());
+
+    // A segment appended to another index is rejected rather than merged
+    let other_dir = tempfile::tempdir()?;
+    first.write_parquet(&other_dir.path(), None)?;
+    first.append_parquet(&other_dir.path(), None)?;
+    fs::rename(other_dir.path().join("appended_0"), tmpdir_path.join("appended_0"))?;
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    Ok(())
+}
+
+#[test]
+fn test_on_disk_search_row_groups() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    // Small row groups give the statistics something to prune
+    search_index.write_with_row_group_size(&tmpdir_path, None, Some(1024))?;
+    let entries_path = tmpdir_path.join(Fragment::archive_name());
+
+    let on_disk = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())?;
+    let metadata = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+        fs::File::open(&entries_path)?,
+    )?
+    .metadata()
+    .clone();
+    let total_rows = metadata.file_metadata().num_rows() as usize;
+    assert_eq!(total_rows, search_index.num_entries());
+    assert!(metadata.num_row_groups() > 10);
+
+    let tol = Tolerance::PPM(10.0);
+    let query = 113.08406397713001;
+    let row_groups = on_disk.row_groups_for(query, tol)?;
+    let decoded_rows: usize = row_groups
+        .iter()
+        .map(|i| metadata.row_group(*i).num_rows() as usize)
+        .sum();
+    assert!(!row_groups.is_empty());
+    assert!(decoded_rows * 10 < total_rows);
+
+    let parent_interval = search_index.parents_for_range(200.0, 1200.0, tol);
+    let key = |f: &Fragment| (f.parent_id, f.mass.to_bits(), f.ordinal);
+    let mut expected: Vec<_> = search_index
+        .search(query, tol, Some(parent_interval))
+        .copied()
+        .collect();
+    let mut hits = on_disk.search(query, tol, Some(parent_interval))?;
+    expected.sort_by_key(key);
+    hits.sort_by_key(key);
+    assert_eq!(hits.len(), 504);
+    assert_eq!(hits, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_archive_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let archive_path = tmpdir.path().join("index.tar");
+    search_index.write_archive(&archive_path, None)?;
+    assert!(archive_path.is_file());
+
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_archive(&archive_path)?;
+    assert_eq!(duplicate_index.num_parents(), search_index.num_parents());
+    assert_eq!(duplicate_index.num_entries(), search_index.num_entries());
+    for (a, b) in search_index.parents.iter().zip(duplicate_index.parents.iter()) {
+        assert_eq!(a, b);
+    }
+
+    let parent_interval = duplicate_index.parents_for_range(200.0, 1200.0, Tolerance::PPM(10.0));
+    let dup_search: Vec<_> = duplicate_index
+        .search(113.08406397713001, Tolerance::PPM(10.0), Some(parent_interval))
+        .collect();
+    assert_eq!(dup_search.len(), 504);
+
+    Ok(())
+}
+
+#[test]
+fn test_format_version_check() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+
+    let on_disk = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())?;
+    assert_eq!(on_disk.metadata.format_version, IndexMetadata::FORMAT_VERSION);
+
+    let meta_path = tmpdir_path.join(IndexMetadata::archive_name());
+    let meta = fs::read_to_string(&meta_path)?;
+    let current = format!("\"format_version\":{}", IndexMetadata::FORMAT_VERSION);
+    assert!(meta.contains(&current));
+    let bumped = format!("\"format_version\":{}", IndexMetadata::FORMAT_VERSION + 1);
+    fs::write(&meta_path, meta.replace(&current, &bumped))?;
+
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    Ok(())
+}
+
+#[test]
+fn test_mismatched_bin_count_detected() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let parquet_dir = tempfile::tempdir()?;
+    search_index.write_parquet(&parquet_dir.path(), None)?;
+    let ipc_dir = tempfile::tempdir()?;
+    search_index.write_ipc(&ipc_dir.path())?;
+    let banded_dir = tempfile::tempdir()?;
+    search_index.write_banded_parquet(&banded_dir.path(), 50.0, None)?;
+
+    // Metadata describing far fewer bins than the entries were written with
+    for dir in [parquet_dir.path(), ipc_dir.path(), banded_dir.path()] {
+        let meta_path = dir.join(IndexMetadata::archive_name());
+        let meta = fs::read_to_string(&meta_path)?;
+        assert!(meta.contains("\"bins_per_dalton\":100"));
+        fs::write(&meta_path, meta.replace("\"bins_per_dalton\":100", "\"bins_per_dalton\":1"))?;
+    }
+
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_parquet(&parquet_dir.path()).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_ipc(&ipc_dir.path()).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_banded_parquet(&banded_dir.path()).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = io::Error::from(
+        SearchIndex::<Fragment, Peptide>::read_bands_for(&banded_dir.path(), 0.0, 10000.0).unwrap_err(),
+    );
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    Ok(())
+}
+
+#[test]
+fn test_mismatched_schema_detected() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+    let on_disk = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())?;
+
+    // Write the entries with a parent id column of the wrong width and no ordinal column
+    let schema = Fragment::schema();
+    let fields: Vec<_> = schema
+        .fields()
+        .iter()
+        .filter(|f| f.name() != "ordinal")
+        .map(|f| match f.name().as_str() {
+            "parent_id" => f.as_ref().clone().with_data_type(arrow::datatypes::DataType::UInt64),
+            _ => f.as_ref().clone(),
+        })
+        .collect();
+    let wrong_schema = std::sync::Arc::new(arrow::datatypes::Schema::new(fields));
+    let build_id = parquet::file::metadata::KeyValue::new("build_id".into(), on_disk.metadata.build_id.to_string());
+    let mut writer = parquet::arrow::ArrowWriter::try_new(
+        fs::File::create(tmpdir_path.join(Fragment::archive_name()))?,
+        wrong_schema.clone(),
+        Some(
+            Fragment::writer_properties()
+                .set_key_value_metadata(Some(vec![build_id]))
+                .build(),
+        ),
+    )?;
+    for (i, bin) in search_index.iter_bins().enumerate() {
+        let batch = Fragment::to_batch(bin.as_slice(), Fragment::schema(), i as u64).unwrap();
+        let columns = wrong_schema
+            .fields()
+            .iter()
+            .map(|f| arrow::compute::cast(batch.column_by_name(f.name()).unwrap(), f.data_type()).unwrap())
+            .collect();
+        writer.write(&arrow::array::RecordBatch::try_new(wrong_schema.clone(), columns).unwrap())?;
+    }
+    writer.close()?;
+
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("parent_id") || err.to_string().contains("ordinal"));
+
+    Ok(())
+}
+
+#[test]
+fn test_truncated_entries_detected() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+
+    let on_disk = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())?;
+    assert_eq!(on_disk.metadata.num_entries as usize, search_index.num_entries());
+    assert_eq!(on_disk.metadata.num_parents as usize, search_index.num_parents());
+
+    // Simulate an interrupted write that only got through the first half of the bins
+    let entries_path = tmpdir_path.join(Fragment::archive_name());
+    let build_id = parquet::file::metadata::KeyValue::new("build_id".into(), on_disk.metadata.build_id.to_string());
+    let mut writer = parquet::arrow::ArrowWriter::try_new(
+        fs::File::create(&entries_path)?,
+        Fragment::schema(),
+        Some(
+            Fragment::writer_properties()
+                .set_key_value_metadata(Some(vec![build_id]))
+                .build(),
+        ),
+    )?;
+    for (i, bin) in search_index
+        .iter_bins()
+        .enumerate()
+        .take(search_index.num_bins() / 2)
+    {
+        let batch = Fragment::to_batch(bin.as_slice(), Fragment::schema(), i as u64).unwrap();
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("entries"));
+
+    Ok(())
+}
+
+/// Rewrite the `mass` column of the parquet file at `path` with the other floating point
+/// precision, as a build with the other `high_precision` setting would have written it
+fn swap_mass_precision(path: &std::path::Path) -> io::Result<()> {
+    use arrow::datatypes::{DataType, Field, Schema};
+
+    let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path)?)?;
+    let key_values = builder.metadata().file_metadata().key_value_metadata().cloned();
+    let batches: Vec<_> = builder.build()?.collect::<Result<_, _>>().map_err(io::Error::other)?;
+
+    let schema = batches[0].schema();
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .map(|f| match (f.name().as_str(), f.data_type()) {
+            ("mass", DataType::Float32) => f.as_ref().clone().with_data_type(DataType::Float64),
+            ("mass", DataType::Float64) => f.as_ref().clone().with_data_type(DataType::Float32),
+            _ => f.as_ref().clone(),
+        })
+        .collect();
+    let swapped = std::sync::Arc::new(Schema::new(fields));
+
+    let mut writer = parquet::arrow::ArrowWriter::try_new(
+        fs::File::create(path)?,
+        swapped.clone(),
+        Some(
+            parquet::file::properties::WriterProperties::builder()
+                .set_key_value_metadata(key_values)
+                .build(),
+        ),
+    )?;
+    for batch in batches {
+        let columns = batch
+            .columns()
+            .iter()
+            .zip(swapped.fields())
+            .map(|(col, f)| arrow::compute::cast(col, f.data_type()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io::Error::other)?;
+        writer.write(&arrow::array::RecordBatch::try_new(swapped.clone(), columns).map_err(io::Error::other)?)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_read_other_mass_precision() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+    // Under `high_precision` this leaves Float32 masses, as a default build writes them
+    swap_mass_precision(&tmpdir_path.join(Peptide::archive_name()))?;
+    swap_mass_precision(&tmpdir_path.join(Fragment::archive_name()))?;
+
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(duplicate_index.num_parents(), search_index.num_parents());
+    assert_eq!(duplicate_index.num_entries(), search_index.num_entries());
+    for (a, b) in duplicate_index.parents.iter().zip(search_index.parents.iter()) {
+        assert!((a.mass - b.mass).abs() < 1e-2);
+    }
+
+    let on_disk = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())?;
+    assert!(!on_disk.search(113.08406397713001, Tolerance::PPM(10.0), None)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_series_code_detected() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+    let on_disk = SearchIndexOnDisk::<Fragment, Peptide, IndexMetadata>::new(tmpdir_path.into())?;
+
+    // Rewrite the entries with a series code no fragment series uses
+    let entries_path = tmpdir_path.join(Fragment::archive_name());
+    let build_id = parquet::file::metadata::KeyValue::new("build_id".into(), on_disk.metadata.build_id.to_string());
+    let mut writer = parquet::arrow::ArrowWriter::try_new(
+        fs::File::create(&entries_path)?,
+        Fragment::schema(),
+        Some(
+            Fragment::writer_properties()
+                .set_key_value_metadata(Some(vec![build_id]))
+                .build(),
+        ),
+    )?;
+    let series_idx = Fragment::schema().index_of("series").unwrap();
+    for (i, bin) in search_index.iter_bins().enumerate() {
+        let batch = Fragment::to_batch(bin.as_slice(), Fragment::schema(), i as u64).unwrap();
+        let mut columns = batch.columns().to_vec();
+        columns[series_idx] = std::sync::Arc::new(arrow::array::UInt8Array::from(vec![200u8; batch.num_rows()]));
+        writer.write(&arrow::array::RecordBatch::try_new(Fragment::schema(), columns).unwrap())?;
+    }
+    writer.close()?;
+
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("series"));
+
+    let err = on_disk
+        .search(113.08406397713001, Tolerance::Da(50.0), None)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    Ok(())
+}
+
+#[test]
+fn test_mismatched_files_detected() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let pepfrags = parse_csv(reader)?;
+
+    let mut first: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    let mut second: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (i, (pep, frags)) in pepfrags.into_iter().enumerate() {
+        first.add_parent(pep.clone());
+        frags.iter().for_each(|frag| {
+            first.add(*frag);
+        });
+        if i < 100 {
+            second.add_parent(pep);
+            frags.into_iter().for_each(|frag| {
+                second.add(frag);
+            });
+        }
+    }
+    first.sort(SortType::ByParentId);
+    second.sort(SortType::ByParentId);
+
+    let first_dir = tempfile::tempdir()?;
+    let second_dir = tempfile::tempdir()?;
+    first.write_parquet(&first_dir.path(), None)?;
+    second.write_parquet(&second_dir.path(), None)?;
+    SearchIndex::<Fragment, Peptide>::read_parquet(&first_dir.path())?;
+
+    fs::copy(
+        second_dir.path().join(Peptide::archive_name()),
+        first_dir.path().join(Peptide::archive_name()),
+    )?;
+    let err = io::Error::from(SearchIndex::<Fragment, Peptide>::read_parquet(&first_dir.path()).unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("build id"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_metadata_write_failure() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    // Every write to /dev/full fails, even though opening it succeeds
+    std::os::unix::fs::symlink("/dev/full", tmpdir_path.join(IndexMetadata::archive_name()))?;
+
+    assert!(search_index.write_metadata(tmpdir_path, None).is_err());
+    assert!(search_index.write_parquet(&tmpdir_path, None).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_malformed_files_yield_typed_errors() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+
+    let entries_path = tmpdir_path.join(Fragment::archive_name());
+    let entries = fs::read(&entries_path)?;
+    fs::write(&entries_path, b"not a parquet file")?;
+    let err = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path).unwrap_err();
+    assert!(matches!(err, MassFragmentIndexError::Parquet(_)), "{err:?}");
+    fs::write(&entries_path, entries)?;
+
+    let meta_path = tmpdir_path.join(IndexMetadata::archive_name());
+    fs::write(&meta_path, "{\"bins_per_dalton\": \"many\"}\n")?;
+    let err = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path).unwrap_err();
+    assert!(matches!(err, MassFragmentIndexError::Arrow(_)), "{err:?}");
+
+    let err = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path.join("missing")).unwrap_err();
+    assert!(matches!(&err, MassFragmentIndexError::IO(e) if e.kind() == io::ErrorKind::NotFound));
+
+    Ok(())
+}
+
+#[test]
+fn test_split_compression() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    let compression = IndexCompression::new(
+        Compression::SNAPPY,
+        Compression::ZSTD(ZstdLevel::try_new(15).unwrap()),
+    );
+    search_index.write(&tmpdir_path, Some(compression))?;
+
+    let codec_of = |name: String| -> io::Result<Compression> {
+        let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+            fs::File::open(tmpdir_path.join(name))?,
+        )?;
+        Ok(builder.metadata().row_group(0).column(0).compression())
+    };
+    assert_eq!(codec_of(Peptide::archive_name())?, compression.parents);
+    // Parquet records the codec but not its level
+    assert_eq!(
+        std::mem::discriminant(&codec_of(Fragment::archive_name())?),
+        std::mem::discriminant(&compression.entries)
+    );
+
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(duplicate_index.num_parents(), search_index.num_parents());
+    assert_eq!(duplicate_index.num_entries(), search_index.num_entries());
+
+    Ok(())
+}
+
+#[test]
+fn test_mass_sort_is_deterministic() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let pepfrags = parse_csv(reader)?;
+
+    // The same data added in opposite orders
+    let mut forward: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    let mut backward: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (pep, _) in pepfrags.iter() {
+        forward.add_parent(pep.clone());
+        backward.add_parent(pep.clone());
+    }
+    let fragments: Vec<Fragment> = pepfrags.into_iter().flat_map(|(_, frags)| frags).collect();
+    fragments.iter().for_each(|frag| {
+        forward.add(*frag);
+    });
+    fragments.iter().rev().for_each(|frag| {
+        backward.add(*frag);
+    });
+    forward.sort(SortType::ByMass);
+    backward.sort(SortType::ByMass);
+
+    let forward_dir = tempfile::tempdir()?;
+    let backward_dir = tempfile::tempdir()?;
+    let compression = Compression::ZSTD(ZstdLevel::try_new(9).unwrap());
+    forward.write_entries(forward_dir.path(), &compression, None, None)?;
+    backward.write_entries(backward_dir.path(), &compression, None, None)?;
+
+    let forward_bytes = fs::read(forward_dir.path().join(Fragment::archive_name()))?;
+    let backward_bytes = fs::read(backward_dir.path().join(Fragment::archive_name()))?;
+    assert!(forward_bytes == backward_bytes);
+
+    Ok(())
+}
+
+#[test]
+fn test_gzip_and_brotli_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    for codec in [
+        Compression::GZIP(GzipLevel::default()),
+        Compression::GZIP(GzipLevel::try_new(9).unwrap()),
+        Compression::BROTLI(BrotliLevel::default()),
+        Compression::BROTLI(BrotliLevel::try_new(11).unwrap()),
+    ] {
+        let tmpdir = tempfile::tempdir()?;
+        let tmpdir_path = tmpdir.path();
+        search_index.write(&tmpdir_path, Some(IndexCompression::new(codec, codec)))?;
+
+        for name in [Peptide::archive_name(), Fragment::archive_name()] {
+            let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+                fs::File::open(tmpdir_path.join(name))?,
+            )?;
+            // Files record the codec but not its level
+            let written = builder.metadata().row_group(0).column(0).compression();
+            assert_eq!(std::mem::discriminant(&written), std::mem::discriminant(&codec));
+        }
+
+        let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+        assert_eq!(duplicate_index.parents.as_slice(), search_index.parents.as_slice());
+        assert_eq!(duplicate_index.num_entries(), search_index.num_entries());
+        for (a, b) in search_index.iter_bins().zip(duplicate_index.iter_bins()) {
+            assert_eq!(a.as_slice(), b.as_slice());
+        }
+
+        let archive_path = tmpdir_path.join("index.tar");
+        search_index.write_archive(&archive_path, Some(IndexCompression::new(codec, codec)))?;
+        let archived = SearchIndex::<Fragment, Peptide>::read_archive(&archive_path)?;
+        assert_eq!(archived.num_entries(), search_index.num_entries());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_uncompressed_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let compressed_dir = tempfile::tempdir()?;
+    search_index.write_parquet(&compressed_dir.path(), None)?;
+    let plain_dir = tempfile::tempdir()?;
+    search_index.write_parquet(&plain_dir.path(), Some(Compression::UNCOMPRESSED))?;
+
+    let entries_size = |dir: &std::path::Path| -> io::Result<u64> {
+        Ok(fs::metadata(dir.join(Fragment::archive_name()))?.len())
+    };
+    assert!(entries_size(plain_dir.path())? > entries_size(compressed_dir.path())?);
+
+    let compressed = SearchIndex::<Fragment, Peptide>::read_parquet(&compressed_dir.path())?;
+    let plain = SearchIndex::<Fragment, Peptide>::read_parquet(&plain_dir.path())?;
+    assert_eq!(plain.parents.as_slice(), compressed.parents.as_slice());
+    assert_eq!(plain.num_bins(), compressed.num_bins());
+    for (a, b) in plain.iter_bins().zip(compressed.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_read_csv_index() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let expected = build_index(reader)?;
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index = read_csv_index(reader, 100, 10000.0)?;
+
+    assert_eq!(search_index.num_parents(), expected.num_parents());
+    assert_eq!(search_index.num_entries(), expected.num_entries());
+    assert_eq!(search_index.parents.as_slice(), expected.parents.as_slice());
+    for (a, b) in search_index.iter_bins().zip(expected.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_read_csv_index_edge_cases() -> io::Result<()> {
+    let empty = read_csv_index(io::Cursor::new(""), 100, 10000.0)?;
+    assert!(empty.is_empty());
+    assert_eq!(empty.num_parents(), 0);
+
+    let header_only = read_csv_index(io::Cursor::new("Type,sequence,mass,id\n"), 100, 10000.0)?;
+    assert!(header_only.is_empty());
+
+    let text = "Type,sequence,mass,id
+PEPTIDE,TGAGK,432.23324739196,0
+FRAGMENT,b1,101.04767846841,0
+FRAGMENT,y4,331.18556892354997,0
+PEPTIDE,ITGTG,447.23291303879,1
+FRAGMENT,b1,113.08406397713001,1
+FRAGMENT,b2,214.13174244563,1";
+    let search_index = read_csv_index(io::Cursor::new(text), 100, 10000.0)?;
+    assert_eq!(search_index.num_parents(), 2);
+    assert_eq!(search_index.num_entries(), 4);
+    let hits: Vec<_> = search_index
+        .search(214.13174244563, Tolerance::PPM(10.0), None)
+        .collect();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].parent_id, 1);
+
+    let text = "Type,sequence,mass,id,protein_id,start_position
+PEPTIDE,TGAGK,432.23324739196,0,7,112
+FRAGMENT,b1,101.04767846841,0,,
+PEPTIDE,ITGTG,447.23291303879,1,,";
+    let search_index = read_csv_index(io::Cursor::new(text), 100, 10000.0)?;
+    let parent = &search_index.parents[0];
+    assert_eq!((parent.protein_id, parent.start_position), (7, 112));
+    let parent = &search_index.parents[1];
+    assert_eq!((parent.protein_id, parent.start_position), (0, 0));
+
+    let text = "\u{feff}\nType,sequence,mass,id,protein_id,start_position
+PEPTIDE,TGAGK,432.23324739196,0,7,112";
+    let search_index = read_csv_index(io::Cursor::new(text), 100, 10000.0)?;
+    assert_eq!(search_index.num_parents(), 1);
+    assert_eq!(search_index.parents[0].protein_id, 7);
+
+    let text = "PEPTIDE,TGAGK,432.23324739196,0\nFRAGMENT,b3^200,101.04767846841,0";
+    let err = read_csv_index(io::Cursor::new(text), 100, 10000.0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let dangling = read_csv_index(io::Cursor::new("FRAGMENT,b1,101.04767846841,0\n"), 100, 10000.0);
+    assert_eq!(dangling.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    Ok(())
+}
+
+#[test]
+fn test_peptide_position_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut search_index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (i, (mut pep, frags)) in parse_csv(reader)?.into_iter().enumerate() {
+        pep.protein_id = i as ParentID / 10;
+        pep.start_position = (i % 10) as u16 * 17;
+        search_index.add_parent(pep);
+        frags.into_iter().for_each(|frag| {
+            search_index.add(frag);
+        });
+    }
+    search_index.sort(SortType::ByParentId);
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+
+    assert_eq!(duplicate_index.parents.as_slice(), search_index.parents.as_slice());
+    let parents = duplicate_index.parents.as_slice();
+    assert!(parents.iter().any(|p| p.protein_id > 0 && p.start_position > 0));
+    for (a, b) in parents.iter().zip(search_index.parents.as_slice()) {
+        assert_eq!(a.parent_id(), b.protein_id);
+        assert_eq!(a.start_position, b.start_position);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_decoy_and_group_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let plain_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tol = Tolerance::PPM(10.0);
+    let query = 113.08406397713001;
+    let decoy_id = plain_index.search(query, tol, None).next().unwrap().parent_id;
+
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut search_index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (i, (mut pep, frags)) in parse_csv(reader)?.into_iter().enumerate() {
+        pep.is_decoy = i as ParentID == decoy_id;
+        pep.group_id = i as ParentID % 5;
+        search_index.add_parent(pep);
+        frags.into_iter().for_each(|frag| {
+            search_index.add(frag);
+        });
+    }
+    search_index.sort(SortType::ByParentId);
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+    assert_eq!(duplicate_index.parents.iter().filter(|p| p.is_decoy).count(), 1);
+    assert!(duplicate_index.parents_in_group(3).all(|p| p.group_id == 3));
+    assert_eq!(
+        duplicate_index.parents_in_group(3).count(),
+        search_index.parents_in_group(3).count()
+    );
+    assert_eq!(duplicate_index.parents.as_slice(), search_index.parents.as_slice());
+
+    let all = duplicate_index.search_with_decoys(query, tol, None, false).count();
+    let targets: Vec<_> = duplicate_index
+        .search_with_decoys(query, tol, None, true)
+        .collect();
+    assert!(targets.len() < all);
+    assert!(targets.iter().all(|f| f.parent_id != decoy_id));
+
+    Ok(())
+}
+
+#[test]
+fn test_fragment_charge_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut search_index: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (pep, frags) in parse_csv(reader)? {
+        search_index.add_parent(pep);
+        frags.into_iter().for_each(|frag| {
+            let charge = if frag.ordinal % 2 == 0 { 2 } else { 1 };
+            search_index.add(frag.with_charge(charge));
+        });
+    }
+    search_index.sort(SortType::ByParentId);
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_parquet(&tmpdir_path, None)?;
+    let duplicate_index = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir_path)?;
+
+    let hits: Vec<_> = duplicate_index
+        .search(113.08406397713001, Tolerance::PPM(10.0), None)
+        .collect();
+    assert!(!hits.is_empty());
+    assert!(hits.iter().all(|f| f.charge() == if f.ordinal % 2 == 0 { 2 } else { 1 }));
+    assert!(duplicate_index.iter_entries().any(|f| f.charge == 2));
+
+    Ok(())
+}
+
+#[cfg(feature = "parallelism")]
+#[test]
+fn test_parallel_banded_write() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let serial_dir = tempfile::tempdir()?;
+    search_index.write_banded_parquet(&serial_dir.path(), 50.0, None)?;
+    let parallel_dir = tempfile::tempdir()?;
+    search_index.par_write_banded_parquet(&parallel_dir.path(), 50.0, None)?;
+    assert!(!parallel_dir.path().join(Fragment::archive_name()).exists());
+    assert!(fs::read_dir(parallel_dir.path())?.count() > 4);
+
+    let serial = SearchIndex::<Fragment, Peptide>::read_banded_parquet(&serial_dir.path())?;
+    let parallel = SearchIndex::<Fragment, Peptide>::read_banded_parquet(&parallel_dir.path())?;
+    assert_eq!(parallel.parents.as_slice(), serial.parents.as_slice());
+    assert_eq!(parallel.num_entries(), search_index.num_entries());
+    for (a, b) in parallel.iter_bins().zip(serial.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_read_bands_for() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    let tmpdir_path = tmpdir.path();
+    search_index.write_banded_parquet(&tmpdir_path, 50.0, None)?;
+
+    let (low, high) = (700.0, 760.0);
+    let all_bands = SearchIndex::<Fragment, Peptide>::read_split_log(tmpdir_path)?;
+    let bands = SearchIndex::<Fragment, Peptide>::bands_for(tmpdir_path, low, high)?;
+    assert!(!bands.is_empty());
+    assert!(bands.len() < all_bands.len());
+
+    let partial = SearchIndex::<Fragment, Peptide>::read_bands_for(&tmpdir_path, low, high)?;
+    assert_eq!(partial.num_parents(), search_index.num_parents());
+    assert!(partial.num_entries() < search_index.num_entries());
+
+    let tol = Tolerance::PPM(10.0);
+    let parent_interval = search_index.parents_for_range(low, high, tol);
+    assert!(!parent_interval.is_empty());
+    for query in [113.08406397713001, 147.11280417418, 175.11895217713] {
+        let expected: Vec<_> = search_index.search(query, tol, Some(parent_interval)).collect();
+        let hits: Vec<_> = partial.search(query, tol, Some(parent_interval)).collect();
+        assert_eq!(hits, expected);
+    }
+
+    #[cfg(feature = "parallelism")]
+    {
+        let band_dir = tempfile::tempdir()?;
+        search_index.par_write_banded_parquet(&band_dir.path(), 50.0, None)?;
+        let partial = SearchIndex::<Fragment, Peptide>::read_bands_for(&band_dir.path(), low, high)?;
+        for query in [113.08406397713001, 147.11280417418, 175.11895217713] {
+            let expected: Vec<_> = search_index.search(query, tol, Some(parent_interval)).collect();
+            let hits: Vec<_> = partial.search(query, tol, Some(parent_interval)).collect();
+            assert_eq!(hits, expected);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_peak_intensity_round_trip() -> io::Result<()> {
+    use mass_fragment_index::{DeconvolutedPeak, DeconvolutedSpectrumIndex, Spectrum};
+
+    let mut index = DeconvolutedSpectrumIndex::empty(100, 3000.0);
+    for i in 0..10u32 {
+        index.add_parent(Spectrum::new(1000.0 + i as MassType * 10.0, 2, 0, i, i));
+        for j in 0..20u32 {
+            let mass = 200.0 + j as MassType * 25.0;
+            index.add(DeconvolutedPeak::new(mass, 1, (i * 100 + j) as f32 + 0.5, i));
+        }
+    }
+    index.sort(SortType::ByParentId);
+
+    let tmpdir = tempfile::tempdir()?;
+    index.write_parquet(&tmpdir.path(), None)?;
+    let duplicate = DeconvolutedSpectrumIndex::read_parquet(&tmpdir.path())?;
+
+    let mut matches: Vec<_> = duplicate
+        .search_peaks(275.0, Tolerance::PPM(10.0), None)
+        .map(|m| (m.peak.scan_ref, m.intensity))
+        .collect();
+    matches.sort_by_key(|(scan_ref, _)| *scan_ref);
+    let expected: Vec<_> = (0..10u32).map(|i| (i, (i * 100 + 3) as f32 + 0.5)).collect();
+    assert_eq!(matches, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_arrow_batch_export() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let entry_batches = search_index.to_entry_batches().map_err(io::Error::other)?;
+    let parent_batch = search_index.to_parent_batch().map_err(io::Error::other)?;
+
+    let mut entries: std::collections::HashMap<u64, Vec<Fragment>> = Default::default();
+    for batch in entry_batches.iter() {
+        for (frag, segment_id) in Fragment::from_batch(batch, fragment_schema()) {
+            entries.entry(segment_id).or_default().push(frag);
+        }
+    }
+    let parents: Vec<Peptide> = Peptide::from_batch(&parent_batch, peptide_schema())
+        .map(|(p, _)| p)
+        .collect();
+
+    let rebuilt: SearchIndex<Fragment, Peptide> =
+        SearchIndex::from_components(search_index.to_metadata(), parents, entries);
+    assert_eq!(rebuilt.num_entries(), search_index.num_entries());
+    assert_eq!(rebuilt.num_parents(), search_index.num_parents());
+    assert_eq!(rebuilt.parents.as_slice(), search_index.parents.as_slice());
+    Ok(())
+}
+
+#[test]
+fn test_arrow_batch_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let entry_batches = search_index.to_entry_batches().map_err(io::Error::other)?;
+    let parent_batch = search_index.to_parent_batch().map_err(io::Error::other)?;
+    let rebuilt = SearchIndex::<Fragment, Peptide>::from_entry_batches(
+        &entry_batches,
+        &parent_batch,
+        search_index.to_metadata(),
+    )?;
+
+    assert_eq!(rebuilt.parents.as_slice(), search_index.parents.as_slice());
+    assert_eq!(rebuilt.num_bins(), search_index.num_bins());
+    for (a, b) in rebuilt.iter_bins().zip(search_index.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+    let expected: Vec<_> = search_index
+        .search(113.08406, Tolerance::PPM(10.0), None)
+        .collect();
+    let found: Vec<_> = rebuilt.search(113.08406, Tolerance::PPM(10.0), None).collect();
+    assert!(!expected.is_empty());
+    assert_eq!(found, expected);
+
+    let mut metadata = search_index.to_metadata();
+    metadata.num_entries += 1;
+    assert!(SearchIndex::<Fragment, Peptide>::from_entry_batches(
+        &entry_batches,
+        &parent_batch,
+        metadata
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_ipc_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let parquet_dir = tempfile::tempdir()?;
+    search_index.write_parquet(&parquet_dir.path(), None)?;
+    let ipc_dir = tempfile::tempdir()?;
+    search_index.write_ipc(&ipc_dir.path())?;
+    assert!(ipc_dir.path().join("fragments.arrow").is_file());
+    assert!(ipc_dir.path().join("peptides.arrow").is_file());
+
+    let from_parquet = SearchIndex::<Fragment, Peptide>::read_parquet(&parquet_dir.path())?;
+    let from_ipc = SearchIndex::<Fragment, Peptide>::read_ipc(&ipc_dir.path())?;
+    assert_eq!(from_ipc.num_entries(), from_parquet.num_entries());
+    assert_eq!(from_ipc.parents.as_slice(), from_parquet.parents.as_slice());
+    assert_eq!(from_ipc.num_bins(), from_parquet.num_bins());
+    for (a, b) in from_ipc.iter_bins().zip(from_parquet.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_observed_mass_range() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let (low, high) = search_index.observed_mass_range().unwrap();
+    // Fragments above the maximum mass are kept in the overflow bin and still count
+    assert!(0.0 <= low && low <= high && high > 10000.0);
+    let masses: Vec<MassType> = search_index
+        .iter_bins()
+        .flat_map(|b| b.as_slice().iter().map(|f| f.mass))
+        .collect();
+    assert!(masses.iter().all(|m| (low..=high).contains(m)));
+    assert!(masses.contains(&low) && masses.contains(&high));
+
+    assert_eq!(
+        SearchIndex::<Fragment, Peptide>::empty(100, 10000.0).observed_mass_range(),
+        None
+    );
+    Ok(())
+}
+
+#[test]
+fn test_rewrite_parents_only() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    search_index.write_parquet(&tmpdir.path(), None)?;
+    let entries_path = tmpdir.path().join(Fragment::archive_name());
+    let entries_before = fs::read(&entries_path)?;
+
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut relabeled: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (i, (mut peptide, _)) in parse_csv(reader)?.into_iter().enumerate() {
+        peptide.is_decoy = i % 2 == 0;
+        relabeled.add_parent(peptide);
+    }
+    relabeled.sort(SortType::ByParentId);
+    relabeled.write_parents_parquet(&tmpdir.path(), None)?;
+    assert_eq!(fs::read(&entries_path)?, entries_before);
+
+    let reloaded = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir.path())?;
+    assert_eq!(reloaded.parents.as_slice(), relabeled.parents.as_slice());
+    assert!(reloaded.parents.as_slice().iter().any(|p| p.is_decoy));
+    assert_eq!(reloaded.num_entries(), search_index.num_entries());
+    for (a, b) in reloaded.iter_bins().zip(search_index.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_fragment_payload_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut search_index: SearchIndex<AnnotatedFragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (peptide, fragments) in parse_csv(reader)? {
+        for frag in fragments {
+            // Leave some payloads out to check they read back as missing
+            let payload = (frag.ordinal % 2 == 0)
+                .then(|| format!("{}:{}", peptide.sequence, frag.ordinal).into_bytes());
+            search_index.add(AnnotatedFragment::new(frag, payload));
+        }
+        search_index.add_parent(peptide);
+    }
+    search_index.sort(SortType::ByParentId);
+
+    let tmpdir = tempfile::tempdir()?;
+    search_index.write_parquet(&tmpdir.path(), None)?;
+    let reloaded = SearchIndex::<AnnotatedFragment, Peptide>::read_parquet(&tmpdir.path())?;
+    for (a, b) in reloaded.iter_bins().zip(search_index.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+
+    let hits: Vec<_> = reloaded
+        .search(113.08406, Tolerance::PPM(10.0), None)
+        .collect();
+    assert!(!hits.is_empty());
+    for hit in hits {
+        let sequence = &reloaded.parents.as_slice()[hit.fragment.parent_id as usize].sequence;
+        match &hit.payload {
+            Some(payload) => assert_eq!(
+                payload.as_slice(),
+                format!("{}:{}", sequence, hit.fragment.ordinal).as_bytes()
+            ),
+            None => assert_eq!(hit.fragment.ordinal % 2, 1),
+        }
+    }
+
+    // A plain fragment index reads back with empty payloads
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let plain_index = build_index(reader)?;
+    let plain_dir = tempfile::tempdir()?;
+    plain_index.write_parquet(&plain_dir.path(), None)?;
+    let annotated = SearchIndex::<AnnotatedFragment, Peptide>::read_parquet(&plain_dir.path())?;
+    assert_eq!(annotated.num_entries(), plain_index.num_entries());
+    assert!(annotated
+        .iter_bins()
+        .all(|b| b.as_slice().iter().all(|f| f.payload.is_none())));
+    Ok(())
+}
+
+#[test]
+fn test_bin_schedule_round_trip() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let schedule = BinSchedule::new(100).then(500.0, 10).then(2000.0, 1);
+    let mut search_index: SearchIndex<Fragment, Peptide> =
+        SearchIndex::with_bin_schedule(schedule.clone(), 10000.0);
+    for (peptide, fragments) in parse_csv(reader)? {
+        search_index.add_parent(peptide);
+        fragments.into_iter().for_each(|frag| {
+            search_index.add(frag);
+        });
+    }
+    search_index.sort(SortType::ByParentId);
+
+    let tmpdir = tempfile::tempdir()?;
+    search_index.write_parquet(&tmpdir.path(), None)?;
+    let reloaded = SearchIndex::<Fragment, Peptide>::read_parquet(&tmpdir.path())?;
+    assert_eq!(reloaded.bin_schedule(), Some(&schedule));
+    assert_eq!(reloaded.num_bins(), search_index.num_bins());
+    for (a, b) in reloaded.iter_bins().zip(search_index.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+    let expected: Vec<_> = search_index.search(113.08406, Tolerance::PPM(10.0), None).collect();
+    let found: Vec<_> = reloaded.search(113.08406, Tolerance::PPM(10.0), None).collect();
+    assert!(!found.is_empty());
+    assert_eq!(found, expected);
+    Ok(())
+}
+
+#[test]
+fn test_index_file_paths() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    search_index.write_parquet(&tmpdir.path(), None)?;
+    let paths = index_file_paths::<Fragment, Peptide>(tmpdir.path());
+    for path in [&paths.meta, &paths.parents, &paths.entries] {
+        assert!(path.is_file(), "{} was not written", path.display());
+        assert_eq!(path.parent(), Some(tmpdir.path()));
+    }
+    assert_eq!(fs::read_dir(tmpdir.path())?.count(), 3);
+    Ok(())
+}
+
+#[test]
+fn test_read_from_memory() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    search_index.write_parquet(&tmpdir.path(), None)?;
+    let paths = index_file_paths::<Fragment, Peptide>(tmpdir.path());
+    let metadata = fs::read(&paths.meta)?;
+    let parents = Bytes::from(fs::read(&paths.parents)?);
+    let entries = Bytes::from(fs::read(&paths.entries)?);
+    drop(tmpdir);
+
+    let reloaded = SearchIndex::<Fragment, Peptide>::read_from_sources(
+        metadata.as_slice(),
+        parents.clone(),
+        entries,
+    )?;
+    assert_eq!(reloaded.parents.as_slice(), search_index.parents.as_slice());
+    assert_eq!(reloaded.num_entries(), search_index.num_entries());
+    for (a, b) in reloaded.iter_bins().zip(search_index.iter_bins()) {
+        assert_eq!(a.as_slice(), b.as_slice());
+    }
+
+    // The build ids of the buffers are still checked against each other
+    let other_dir = tempfile::tempdir()?;
+    search_index.write_parquet(&other_dir.path(), None)?;
+    let other_entries = Bytes::from(fs::read(
+        index_file_paths::<Fragment, Peptide>(other_dir.path()).entries,
+    )?);
+    assert!(SearchIndex::<Fragment, Peptide>::read_from_sources(
+        metadata.as_slice(),
+        parents,
+        other_entries,
+    )
+    .is_err());
+    Ok(())
+}
+
+#[cfg(feature = "object_store")]
+#[test]
+fn test_read_from_object_store() -> io::Result<()> {
+    use mass_fragment_index::storage::read_from_object_store;
+    use object_store::{memory::InMemory, path::Path as ObjectPath, ObjectStore};
+
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+    let tmpdir = tempfile::tempdir()?;
+    search_index.write_parquet(&tmpdir.path(), None)?;
+
+    let store = InMemory::new();
+    let prefix = ObjectPath::from("indices/test");
+    futures::executor::block_on(async {
+        let paths = index_file_paths::<Fragment, Peptide>(tmpdir.path());
+        for path in [paths.meta, paths.parents, paths.entries] {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            store
+                .put(&prefix.child(name), fs::read(&path)?.into())
+                .await
+                .map_err(io::Error::other)?;
+        }
+
+        let reloaded: SearchIndex<Fragment, Peptide> =
+            read_from_object_store(&store, &prefix).await?;
+        assert_eq!(reloaded.parents.as_slice(), search_index.parents.as_slice());
+        for (a, b) in reloaded.iter_bins().zip(search_index.iter_bins()) {
+            assert_eq!(a.as_slice(), b.as_slice());
+        }
+
+        let missing = read_from_object_store::<Fragment, Peptide>(&store, &ObjectPath::from("nowhere")).await;
+        assert!(matches!(missing, Err(MassFragmentIndexError::ObjectStore(_))));
+        Ok::<_, io::Error>(())
+    })
+}
+
+#[test]
+fn test_estimated_parquet_size() -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let search_index: SearchIndex<Fragment, Peptide> = build_index(reader)?;
+
+    let tmpdir = tempfile::tempdir()?;
+    search_index.write_parquet(&tmpdir.path(), None)?;
+    let paths = index_file_paths::<Fragment, Peptide>(tmpdir.path());
+    let actual: u64 = [paths.meta, paths.parents, paths.entries]
+        .iter()
+        .map(|p| fs::metadata(p).map(|m| m.len()))
+        .sum::<io::Result<u64>>()?;
+
+    let estimate = search_index.estimated_parquet_size(None)?;
+    assert!(estimate > 0);
+    let ratio = estimate as f64 / actual as f64;
+    assert!((0.5..2.0).contains(&ratio), "estimated {estimate} bytes, wrote {actual}");
+
+    let uncompressed = search_index.estimated_parquet_size(Some(Compression::UNCOMPRESSED))?;
+    assert!(uncompressed > estimate);
+
+    // Repeating every fragment more than doubles the number of entries
+    let reader = io::BufReader::new(fs::File::open("tests/data/test_data.csv")?);
+    let mut larger: SearchIndex<Fragment, Peptide> = SearchIndex::empty(100, 10000.0);
+    for (peptide, fragments) in parse_csv(reader)? {
+        larger.add_parent(peptide);
+        for frag in fragments {
+            for charge in 1..=3 {
+                larger.add(frag.with_charge(charge));
+            }
+        }
+    }
+    larger.sort(SortType::ByParentId);
+    assert!(larger.estimated_parquet_size(None)? > estimate);
+    Ok(())
+}